@@ -0,0 +1,107 @@
+use ggez::audio;
+use ggez::audio::SoundSource;
+use ggez::{Context, GameResult};
+
+use chip_8_emulator::chip_8_emulator::{AUDIO_PATTERN_SIZE, BYTE};
+use chip_8_emulator::Chip8Hardware;
+
+// rate of the wav we build from the pattern, the pattern itself plays at whatever the pitch says
+const OUTPUT_SAMPLE_RATE: u32 = 44100;
+const PATTERN_BITS: usize = AUDIO_PATTERN_SIZE * 8;
+// 8 bit unsigned samples, silence is 0x80
+const SAMPLE_HIGH: u8 = 0xB0;
+const SAMPLE_LOW: u8 = 0x50;
+
+// plays the xo-chip audio pattern on a loop while the sound timer is running
+pub struct Beeper {
+    source: Option<audio::Source>,
+    pattern: [BYTE; AUDIO_PATTERN_SIZE],
+    pitch: BYTE,
+    // the audio device wouldn't take our sound, don't keep trying every frame
+    disabled: bool,
+}
+
+impl Beeper {
+    pub fn new() -> Beeper {
+        Beeper {
+            source: None,
+            pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: 0,
+            disabled: false,
+        }
+    }
+
+    pub fn update(&mut self, ctx: &mut Context, chip_8: &Chip8Hardware) {
+        if self.disabled {
+            return;
+        }
+
+        if !chip_8.is_sound_playing() {
+            if let Some(source) = &self.source {
+                if source.playing() {
+                    source.pause();
+                }
+            }
+            return;
+        }
+
+        let pattern = chip_8.get_audio_pattern();
+        let pitch = chip_8.get_audio_pitch();
+        if self.source.is_none() || pattern != self.pattern || pitch != self.pitch {
+            // the pattern or pitch changed, build a new loop for it
+            if let Err(e) = self.rebuild(ctx, chip_8) {
+                println!("Sound disabled: {}", e);
+                self.disabled = true;
+            }
+            self.pattern = pattern;
+            self.pitch = pitch;
+        } else if let Some(source) = &self.source {
+            if source.paused() {
+                source.resume();
+            }
+        }
+    }
+
+    fn rebuild(&mut self, ctx: &mut Context, chip_8: &Chip8Hardware) -> GameResult {
+        if let Some(source) = &mut self.source {
+            source.stop();
+        }
+        let wav = pattern_to_wav(&chip_8.get_audio_pattern(), chip_8.get_audio_sample_rate());
+        let mut source = audio::Source::from_data(ctx, audio::SoundData::from_bytes(&wav))?;
+        source.set_repeat(true);
+        source.play()?;
+        self.source = Some(source);
+        Ok(())
+    }
+}
+
+fn pattern_to_wav(pattern: &[BYTE; AUDIO_PATTERN_SIZE], pattern_rate: f64) -> Vec<u8> {
+    // resample the 1 bit pattern to the output rate, about a second's worth of whole loops so the repeat doesn't click
+    let loop_seconds = PATTERN_BITS as f64 / pattern_rate;
+    let loops = (1.0 / loop_seconds).ceil().max(1.0);
+    let sample_count = (loops * loop_seconds * OUTPUT_SAMPLE_RATE as f64) as u32;
+
+    let mut samples = Vec::with_capacity(sample_count as usize);
+    for i in 0..sample_count {
+        let bit = (i as f64 * pattern_rate / OUTPUT_SAMPLE_RATE as f64) as usize % PATTERN_BITS;
+        let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+        samples.push(if set {SAMPLE_HIGH} else {SAMPLE_LOW});
+    }
+
+    // plain PCM wav header, mono 8 bit
+    let mut wav = Vec::with_capacity(44 + samples.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + sample_count).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&OUTPUT_SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&OUTPUT_SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&8u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&sample_count.to_le_bytes());
+    wav.extend_from_slice(&samples);
+    wav
+}
@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use chip_8_emulator::chip_8_assembler;
+use chip_8_emulator::chip_8_cli::{fail, parse_hex};
+
+const USAGE: &str = "\
+usage: chip8-asm [options] <source>
+
+options:
+  -o, --output <path>   where to write the rom (default: the source with a .ch8 extension)
+  -l, --listing <path>  also write a listing with addresses and bytes, - prints it
+  -m, --map <path>      also write a source map for debuggers, lines and labels by address
+  --origin <address>    where the rom gets loaded (default 0x200)
+  -h, --help            show this";
+
+
+fn main() {
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut listing_path = None;
+    let mut map_path = None;
+    let mut origin = 0x200;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-o" | "--output" => output_path = Some(args.next().unwrap_or_else(|| fail("--output needs a path", USAGE))),
+            "-l" | "--listing" => listing_path = Some(args.next().unwrap_or_else(|| fail("--listing needs a path", USAGE))),
+            "-m" | "--map" => map_path = Some(args.next().unwrap_or_else(|| fail("--map needs a path", USAGE))),
+            "--origin" => match args.next().as_deref().and_then(parse_hex) {
+                Some(address) => origin = address,
+                None => fail("--origin needs a hex address", USAGE),
+            },
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ => source_path = Some(arg),
+        }
+    }
+
+    let source_path = source_path.unwrap_or_else(|| fail("no source given", USAGE));
+    let output_path = output_path.unwrap_or_else(|| {
+        Path::new(&source_path).with_extension("ch8").to_string_lossy().into_owned()
+    });
+
+    if output_path == source_path {
+        fail("the rom would overwrite the source, pick another --output", USAGE);
+    }
+
+    let assembly = chip_8_assembler::assemble_file(&source_path, origin).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    if let Err(e) = fs::write(&output_path, &assembly.rom) {
+        eprintln!("can't write {}: {}", output_path, e);
+        process::exit(1);
+    }
+
+    match listing_path.as_deref() {
+        Some("-") => print!("{}", assembly),
+        Some(path) => {
+            if let Err(e) = fs::write(path, assembly.to_string()) {
+                eprintln!("can't write {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+        None => (),
+    }
+
+    if let Some(path) = map_path {
+        if let Err(e) = fs::write(&path, assembly.source_map().to_string()) {
+            eprintln!("can't write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
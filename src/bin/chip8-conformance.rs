@@ -0,0 +1,135 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chip_8_emulator::chip_8_cli::fail;
+use chip_8_emulator::chip_8_conformance::{self, GlyphSet, Outcome, SUITE};
+use chip_8_emulator::chip_8_golden::Frame;
+use chip_8_emulator::chip_8_screenshot::{Image, Rgb, DEFAULT_PALETTE};
+use chip_8_emulator::{Quirks, LORES_WIDTH};
+
+const USAGE: &str = "\
+usage: chip8-conformance [options] <suite dir>
+
+runs the community test roms (2-ibm-logo.ch8, 3-corax+.ch8, 4-flags.ch8, 5-quirks.ch8 and
+6-keypad.ch8 from chip8-test-suite) under each quirks preset and reads the pass and fail marks they
+draw, roms that aren't in the directory are reported as missing
+
+options:
+  --platform <list>   comma separated quirks presets to run under (default vip,schip,xochip)
+  --test <name>       only run this test, ibm, corax, flags, quirks or keypad, can be given more
+                      than once
+  --glyphs <file>     extra glyph templates for reading the screen, in the format --learn writes
+  --learn <file>      write the glyphs that couldn't be read to file, to be named and passed back
+                      with --glyphs
+  --screens <dir>     save the final screen of every run as <test>-<platform>.png in dir
+  --json              print the report as json
+  -h, --help          show this
+
+exit status is 0 when every test passed, 1 when any failed, crashed or couldn't be read and 2 for
+bad arguments";
+
+fn main() {
+    let mut directory = None;
+    let mut presets = vec!["vip".to_string(), "schip".to_string(), "xochip".to_string()];
+    let mut tests = Vec::new();
+    let mut glyphs = GlyphSet::builtin();
+    let mut learn = None;
+    let mut screens = None;
+    let mut json = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("{} needs a value", arg), USAGE));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--platform" => {
+                presets = value().split(',').map(|name| name.trim().to_string()).collect();
+                if let Some(name) = presets.iter().find(|name| Quirks::from_name(name).is_none()) {
+                    fail(&format!("unknown platform `{}`", name), USAGE);
+                }
+            }
+            "--test" => {
+                let name = value();
+                tests.push(chip_8_conformance::find_test(&name).unwrap_or_else(|| fail(&format!("unknown test `{}`", name), USAGE)));
+            }
+            "--glyphs" => {
+                let path = value();
+                glyphs.merge(GlyphSet::load(&path).unwrap_or_else(|e| fail(&e.to_string(), USAGE)));
+            }
+            "--learn" => learn = Some(value()),
+            "--screens" => screens = Some(PathBuf::from(value())),
+            "--json" => json = true,
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ if directory.is_none() => directory = Some(PathBuf::from(arg)),
+            _ => fail(&format!("only one suite directory, got `{}` too", arg), USAGE),
+        }
+    }
+
+    let directory = directory.unwrap_or_else(|| fail("no suite directory given", USAGE));
+    if !directory.is_dir() {
+        fail(&format!("{} isn't a directory", directory.display()), USAGE);
+    }
+    if tests.is_empty() {
+        tests = SUITE.iter().collect();
+    }
+
+    let mut reports = Vec::new();
+    for test in tests {
+        for preset in &presets {
+            let report = chip_8_conformance::run_test(test, preset, &directory, &glyphs);
+            if let (Some(screens), Some(screen)) = (&screens, &report.screen) {
+                save_screen(screens, &format!("{}-{}.png", report.test, report.preset), screen);
+            }
+            if !json {
+                print!("{}", report);
+            }
+            reports.push(report);
+        }
+    }
+    if json {
+        println!("{}", chip_8_conformance::report_json(&reports));
+    }
+
+    if let Some(path) = learn {
+        let mut unknown: Vec<&String> = Vec::new();
+        for glyph in reports.iter().flat_map(|report| &report.unknown_glyphs) {
+            if !unknown.contains(&glyph) {
+                unknown.push(glyph);
+            }
+        }
+        let text: Vec<&str> = unknown.iter().map(|glyph| glyph.as_str()).collect();
+        if let Err(e) = fs::write(&path, text.join("\n")) {
+            eprintln!("couldn't write {}: {}", path, e);
+            process::exit(1);
+        }
+        eprintln!("{} unknown glyphs written to {}", unknown.len(), path);
+    }
+
+    // a missing rom isn't a failure, the suite directory might only have some of them
+    let failed = reports.iter().filter(|report| !matches!(report.outcome, Outcome::Passed | Outcome::Missing)).count();
+    if failed > 0 {
+        eprintln!("{} of {} runs didn't pass", failed, reports.len());
+        process::exit(1);
+    }
+}
+
+// lores screens get twice the scale of hires ones so every picture comes out 512 wide
+fn save_screen(directory: &Path, name: &str, screen: &Frame) {
+    let scale = if screen.width > LORES_WIDTH {4} else {8};
+    let mut pixels = Vec::new();
+    for row in &screen.pixels {
+        let row: Vec<Rgb> = row.iter().flat_map(|&planes| vec![DEFAULT_PALETTE[planes as usize & 3]; scale]).collect();
+        for _ in 0..scale {
+            pixels.extend_from_slice(&row);
+        }
+    }
+    let image = Image { width: screen.width * scale, height: screen.height * scale, pixels, background: DEFAULT_PALETTE[0] };
+    if let Err(e) = fs::create_dir_all(directory).and_then(|_| fs::write(directory.join(name), image.to_png())) {
+        eprintln!("couldn't save {}: {}", name, e);
+    }
+}
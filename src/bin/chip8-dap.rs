@@ -0,0 +1,34 @@
+use std::env;
+use std::io::{self, BufReader};
+use std::process;
+
+use chip_8_emulator::chip_8_cli::fail;
+use chip_8_emulator::chip_8_dap;
+
+const USAGE: &str = "\
+usage: chip8-dap
+
+debug adapter for editors, speaks the Debug Adapter Protocol on stdin and stdout. point the
+editor's debug configuration at this program, the launch request takes:
+  program       rom, octo source (.8o) or assembler source (.asm) to run
+  platform      quirks preset: vip, chip48, schip, xochip (default vip)
+  sourceMap     .map file for a built rom (default <rom>.map if there is one)
+  stopOnEntry   stop before the first instruction";
+
+fn main() {
+    if let Some(arg) = env::args().nth(1) {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(&format!("unknown option `{}`", arg), USAGE),
+        }
+    }
+
+    // stdout belongs to the protocol, anything for people goes to stderr
+    if let Err(e) = chip_8_dap::serve(BufReader::new(io::stdin()), io::stdout()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,253 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use chip_8_emulator::chip_8_cli::{fail, parse_hex};
+use chip_8_emulator::chip_8_debugger::{self, Debugger, OpcodePattern, StopReason, WatchKind, Watchpoint, DEFAULT_STEP_LIMIT};
+use chip_8_emulator::chip_8_observer::Location;
+use chip_8_emulator::{Chip8Hardware, Quirks};
+
+const USAGE: &str = "\
+usage: chip8-debug [options] <rom>
+
+options:
+  --platform <name>   quirks preset: vip, chip48, schip, xochip (default vip)
+  -h, --help          show this";
+
+const HELP: &str = "\
+commands (numbers and addresses are hex, an empty line repeats the last command):
+  s, step [n]             run n instructions (default 1), giving up where continue would
+  n, next                 step, running calls until they return
+  c, continue             run until a breakpoint, the program exits or something goes wrong
+  b, break [addr]         break when the program counter gets to addr, no address lists breakpoints
+  bo, break-op <pattern>  break before an opcode matching pattern, like D??? or Fx55
+  w, watch [loc] [r|w|rw] stop after an instruction reads or changes loc (default w), which is a
+                          hex memory address or v0-vf, i, dt or st, no location lists watchpoints
+  unwatch <loc>
+  d, delete <addr|pattern|all>
+  r, regs                 registers, I, program counter, stack pointer and timers
+  stack                   return addresses, innermost first
+  m, mem [addr] [len]     dump memory (default 0x40 bytes at I)
+  set <reg> <value>       set v0-vf, i, pc, dt or st
+  l, list [addr] [n]      disassemble around addr (default the program counter)
+  key <key>               toggle a key on the hex keypad, for roms waiting on input
+  q, quit";
+
+
+fn main() {
+    let mut rom_path = None;
+    let mut quirks = Quirks::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--platform" => {
+                let name = args.next().unwrap_or_default();
+                quirks = Quirks::from_name(&name).unwrap_or_else(|| fail(&format!("unknown platform `{}`", name), USAGE));
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = rom_path.unwrap_or_else(|| fail("no rom given", USAGE));
+    let mut chip_8 = Chip8Hardware::with_quirks(quirks);
+    if let Err(e) = chip_8.load_program(&rom_path) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    let mut debugger = Debugger::new();
+
+    println!("{} loaded, `help` lists the commands", rom_path);
+    print!("{}", debugger.disassemble_around(&chip_8, chip_8.get_program_counter(), 0, 1));
+
+    let stdin = io::stdin();
+    let mut last_command = String::new();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let line = if line.trim().is_empty() {last_command.clone()} else {line.trim().to_string()};
+        if line.is_empty() {
+            continue;
+        }
+        last_command = line.clone();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match run_command(&mut debugger, &mut chip_8, &words) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+// false once the user wants out
+fn run_command(debugger: &mut Debugger, chip_8: &mut Chip8Hardware, words: &[&str]) -> Result<bool, String> {
+    let argument = |index: usize| words.get(index).copied();
+    let hex_argument = |index: usize, default: usize| match argument(index) {
+        Some(text) => parse_hex(text).ok_or_else(|| format!("`{}` isn't a hex number", text)),
+        None => Ok(default),
+    };
+    // addresses get truncated to a WORD, so anything past the end of memory is a mistake
+    let address_argument = |index: usize, default: usize| match hex_argument(index, default)? {
+        address if address > 0xFFFF => Err(format!("{:X} is past the end of memory", address)),
+        address => Ok(address as u16),
+    };
+
+    match words[0] {
+        "s" | "step" => {
+            let count = hex_argument(1, 1)?;
+            let mut reason = StopReason::Stepped;
+            // a huge count gives up where continue would instead of hanging
+            for _ in 0..count.min(DEFAULT_STEP_LIMIT) {
+                reason = debugger.step(chip_8);
+                if !matches!(reason, StopReason::Stepped) {
+                    break;
+                }
+            }
+            if count > DEFAULT_STEP_LIMIT && matches!(reason, StopReason::Stepped) {
+                reason = StopReason::StepLimit(DEFAULT_STEP_LIMIT);
+            }
+            report(debugger, chip_8, reason);
+        }
+        "n" | "next" => {
+            let reason = debugger.next(chip_8, DEFAULT_STEP_LIMIT);
+            report(debugger, chip_8, reason);
+        }
+        "c" | "continue" => {
+            let reason = debugger.resume(chip_8, DEFAULT_STEP_LIMIT);
+            report(debugger, chip_8, reason);
+        }
+        "b" | "break" => match argument(1) {
+            Some(_) => {
+                let address = address_argument(1, 0)?;
+                debugger.add_breakpoint(address);
+                println!("breakpoint at {:03X}", address);
+            }
+            None => {
+                for address in debugger.breakpoints() {
+                    println!("  {:03X}", address);
+                }
+                for pattern in debugger.opcode_breakpoints() {
+                    println!("  opcode {}", pattern);
+                }
+            }
+        },
+        "bo" | "break-op" => {
+            let text = argument(1).ok_or("break-op needs a pattern like D???")?;
+            let pattern = OpcodePattern::parse(text).ok_or_else(|| format!("`{}` isn't a 4 digit opcode pattern", text))?;
+            debugger.add_opcode_breakpoint(pattern);
+            println!("breakpoint on opcode {}", pattern);
+        }
+        "w" | "watch" => match argument(1) {
+            Some(text) => {
+                let location = Location::parse(text)?;
+                let kind = match argument(2).unwrap_or("w") {
+                    "r" => WatchKind::Read,
+                    "w" => WatchKind::Write,
+                    "rw" => WatchKind::ReadWrite,
+                    other => return Err(format!("`{}` isn't r, w or rw", other)),
+                };
+                let watchpoint = Watchpoint { location, kind };
+                debugger.add_watchpoint(watchpoint);
+                println!("watching {}", watchpoint);
+            }
+            None => {
+                for watchpoint in debugger.watchpoints() {
+                    println!("  {}", watchpoint);
+                }
+            }
+        },
+        "unwatch" => {
+            let location = Location::parse(argument(1).ok_or("unwatch needs a location")?)?;
+            if !debugger.remove_watchpoint(location) {
+                return Err(format!("not watching {}", location));
+            }
+        }
+        "d" | "delete" => {
+            let text = argument(1).ok_or("delete needs an address, pattern or `all`")?;
+            let removed = if text == "all" {
+                debugger.clear_breakpoints();
+                true
+            } else {
+                // an address first, 4 hex digits could be either
+                let address = parse_hex(text).filter(|address| *address <= 0xFFFF);
+                let pattern = OpcodePattern::parse(text);
+                address.is_some_and(|address| debugger.remove_breakpoint(address as u16))
+                    || pattern.is_some_and(|pattern| debugger.remove_opcode_breakpoint(pattern))
+            };
+            if !removed {
+                return Err(format!("no breakpoint `{}`", text));
+            }
+        }
+        "r" | "regs" => print!("{}", chip_8_debugger::format_registers(chip_8)),
+        "stack" => print!("{}", chip_8_debugger::format_stack(chip_8)),
+        "m" | "mem" => {
+            let address = hex_argument(1, chip_8.get_address_i() as usize)?;
+            let length = hex_argument(2, 0x40)?;
+            print!("{}", chip_8_debugger::format_memory(chip_8, address, length));
+        }
+        "set" => {
+            let register = argument(1).ok_or("set needs a register")?.to_lowercase();
+            let text = argument(2).ok_or("set needs a value")?;
+            let value = parse_hex(text).ok_or_else(|| format!("`{}` isn't a hex number", text))?;
+            set_register(chip_8, &register, value)?;
+        }
+        "l" | "list" => {
+            let address = address_argument(1, chip_8.get_program_counter() as usize)?;
+            let count = hex_argument(2, 10)?;
+            print!("{}", debugger.disassemble_around(chip_8, address, 4, count));
+        }
+        "key" => {
+            let key = hex_argument(1, 0x10)?;
+            if key > 0xF {
+                return Err("key needs a hex key 0-F".to_string());
+            }
+            chip_8.keyboard[key] = !chip_8.keyboard[key];
+            println!("key {:X} {}", key, if chip_8.keyboard[key] {"down"} else {"up"});
+        }
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(false),
+        other => return Err(format!("unknown command `{}`, try help", other)),
+    }
+    Ok(true)
+}
+
+fn set_register(chip_8: &mut Chip8Hardware, register: &str, value: usize) -> Result<(), String> {
+    let limit = match register {
+        "i" | "pc" => 0xFFFF,
+        _ => 0xFF,
+    };
+    if value > limit {
+        return Err(format!("{:X} is too big for {}", value, register));
+    }
+    match register {
+        "i" => chip_8.set_address_i(value as u16),
+        "pc" => chip_8.set_program_counter(value as u16),
+        "dt" => chip_8.set_delay_timer(value as u8),
+        "st" => chip_8.set_sound_timer(value as u8),
+        _ => {
+            let index = register.strip_prefix('v').and_then(parse_hex).filter(|index| *index < 16);
+            let index = index.ok_or_else(|| format!("unknown register `{}`", register))?;
+            chip_8.set_register_value(index as u16, value as u16);
+        }
+    }
+    Ok(())
+}
+
+fn report(debugger: &Debugger, chip_8: &Chip8Hardware, reason: StopReason) {
+    if !matches!(reason, StopReason::Stepped) {
+        println!("{}", reason);
+    }
+    print!("{}", debugger.disassemble_around(chip_8, chip_8.get_program_counter(), 0, 1));
+}
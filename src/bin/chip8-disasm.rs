@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip_8_emulator::chip_8_cli::{fail, parse_hex};
+use chip_8_emulator::chip_8_disassembler;
+
+const USAGE: &str = "\
+usage: chip8-disasm [options] <rom>
+
+options:
+  --linear             decode every word in order instead of following jumps from the start
+  --origin <address>   where the rom gets loaded (default 0x200)
+  -h, --help           show this";
+
+
+fn main() {
+    let mut rom_path = None;
+    let mut origin = 0x200;
+    let mut follow_flow = true;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--linear" => follow_flow = false,
+            "--origin" => match args.next().as_deref().and_then(parse_hex) {
+                Some(address) => origin = address,
+                None => fail("--origin needs a hex address", USAGE),
+            },
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = rom_path.unwrap_or_else(|| fail("no rom given", USAGE));
+    let rom = fs::read(&rom_path).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", rom_path, e);
+        process::exit(1);
+    });
+
+    println!("; {} ({} bytes)", rom_path, rom.len());
+    print!("{}", chip_8_disassembler::disassemble(&rom, origin, follow_flow));
+}
@@ -0,0 +1,68 @@
+use std::env;
+use std::net::TcpListener;
+use std::process;
+
+use chip_8_emulator::chip_8_cli::fail;
+use chip_8_emulator::chip_8_gdb::{GdbStub, DEFAULT_PORT};
+use chip_8_emulator::{Chip8Hardware, Quirks};
+
+const USAGE: &str = "\
+usage: chip8-gdb [options] <rom>
+
+waits for gdb on a local port, then `target remote :<port>` in gdb connects to the rom
+
+options:
+  --platform <name>   quirks preset: vip, chip48, schip, xochip (default vip)
+  --port <port>       port to listen on (default 1234)
+  -h, --help          show this";
+
+fn main() {
+    let mut rom_path = None;
+    let mut quirks = Quirks::default();
+    let mut port = DEFAULT_PORT;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--platform" => {
+                let name = args.next().unwrap_or_default();
+                quirks = Quirks::from_name(&name).unwrap_or_else(|| fail(&format!("unknown platform `{}`", name), USAGE));
+            }
+            "--port" => {
+                let text = args.next().unwrap_or_default();
+                port = text.parse().unwrap_or_else(|_| fail(&format!("`{}` isn't a port number", text), USAGE));
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = rom_path.unwrap_or_else(|| fail("no rom given", USAGE));
+    let mut chip_8 = Chip8Hardware::with_quirks(quirks);
+    if let Err(e) = chip_8.load_program(&rom_path) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    // only ever on localhost, there's no authentication in the protocol
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| {
+        eprintln!("can't listen on port {}: {}", port, e);
+        process::exit(1);
+    });
+    println!("{} loaded, waiting for gdb on 127.0.0.1:{}", rom_path, port);
+
+    let (stream, address) = listener.accept().unwrap_or_else(|e| {
+        eprintln!("accepting a connection failed: {}", e);
+        process::exit(1);
+    });
+    println!("gdb connected from {}", address);
+    if let Err(e) = GdbStub::new(&mut chip_8, stream).serve() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    println!("gdb disconnected");
+}
@@ -0,0 +1,251 @@
+use std::env;
+use std::fs;
+use std::io::BufWriter;
+use std::process;
+
+use chip_8_emulator::chip_8_cli::fail;
+use chip_8_emulator::chip_8_debugger;
+use chip_8_emulator::chip_8_headless::{self, Condition, HeadlessRunner, KeyScript, Outcome, DEFAULT_PRESS_FRAMES};
+use chip_8_emulator::chip_8_random;
+use chip_8_emulator::chip_8_screenshot::{self, ImageFormat, DEFAULT_PALETTE};
+use chip_8_emulator::chip_8_trace::{TraceFilter, TraceFormat, Tracer};
+use chip_8_emulator::{Chip8Error, Chip8Hardware, Quirks};
+
+const USAGE: &str = "\
+usage: chip8-headless [options] <rom>
+
+runs a rom with no window and prints what state it ended up in, for ci and batch jobs
+
+options:
+  --platform <name>   quirks preset: vip, chip48, schip, xochip (default vip)
+  --cpu-hz <hz>       cpu clock, rounded down to a multiple of 60 (default 600)
+  --frames <n>        frames to run, 60 a second (default 600)
+  --seed <n>          seed for CXNN's random numbers, so runs repeat exactly
+  --rng <name>        where CXNN's random numbers come from: xorshift, or vip for the COSMAC VIP
+                      interpreter's streakier routine (default xorshift)
+  --until <cond>      stop as soon as cond holds, like pc=2A0, v3=05, i=300, dt=0 or 300=01 for a
+                      byte of memory, all hex, can be given more than once
+  --keys <file>       key script, lines of `<frame> press <keys> [frames]`, `<frame> down <keys>`
+                      or `<frame> up <keys>`, keys are hex like 5 or 5,6
+  --press <f>:<k>[:n] press key k at frame f for n frames (default 5), can be given more than once
+  --dump <list>       comma separated parts to print: screen, registers, memory (default
+                      screen,registers), or none
+  --memory <range>    hex address range for the memory dump (default 000-FFF)
+  --screenshot <file> save the screen at the end of the run as .png or .pbm, even after a crash
+  --scale <n>         size of a chip-8 pixel in the screenshot (default 1)
+  --palette <colors>  four comma separated RRGGBB screenshot colors, background first
+  --trace <file>      write every instruction run, with the registers it changed, to file
+  --trace-format <f>  text or binary (default text), chip8-trace reads either
+  --trace-addrs <r>   only trace instructions in a hex address range, like 200-2FF
+  --trace-ops <list>  only trace opcodes matching comma separated patterns, like D???,Fx55
+  --trace-frames <r>  only trace frames in a range, like 120-300, either end can be left off
+  -h, --help          show this
+
+exit status is 0 when the run finished, 1 on an emulation error, 2 for bad arguments and 3 when
+--until was given but none of the conditions held before the frames ran out";
+
+// what --dump prints, in this order
+struct Dump {
+    screen: bool,
+    registers: bool,
+    memory: bool,
+}
+
+fn parse_dump(text: &str) -> Result<Dump, String> {
+    let mut dump = Dump { screen: false, registers: false, memory: false };
+    for part in text.split(',') {
+        match part.trim() {
+            "screen" => dump.screen = true,
+            "registers" => dump.registers = true,
+            "memory" => dump.memory = true,
+            "none" => (),
+            other => return Err(format!("`{}` isn't screen, registers, memory or none", other)),
+        }
+    }
+    Ok(dump)
+}
+
+fn parse_press(text: &str) -> Result<(u64, usize, u64), String> {
+    let error = || format!("`{}` isn't a key press like 30:5 or 30:5:10", text);
+    let fields: Vec<&str> = text.split(':').collect();
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err(error());
+    }
+    let frame = fields[0].parse().map_err(|_| error())?;
+    let key = usize::from_str_radix(fields[1], 16).ok().filter(|key| *key < 16).ok_or_else(error)?;
+    let frames = match fields.get(2) {
+        Some(text) => text.parse().map_err(|_| error())?,
+        None => DEFAULT_PRESS_FRAMES,
+    };
+    Ok((frame, key, frames))
+}
+
+fn load(rom_path: &str, quirks: Quirks, cpu_hz: Option<u32>, random: &str, seed: Option<u64>) -> Result<Chip8Hardware, Chip8Error> {
+    let mut chip_8 = Chip8Hardware::with_quirks(quirks);
+    chip_8.enable_decode_cache();
+    if let Some(random) = chip_8_random::source_from_name(random, seed.unwrap_or_else(rand::random)) {
+        chip_8.set_random_source(random);
+    }
+    if let Some(hz) = cpu_hz {
+        chip_8.set_clock_hz(hz);
+    }
+    chip_8.load_program(rom_path)?;
+    Ok(chip_8)
+}
+
+fn print_dump(chip_8: &Chip8Hardware, dump: &Dump, memory: (usize, usize)) {
+    if dump.screen {
+        print!("{}", chip_8_headless::format_screen(chip_8));
+    }
+    if dump.registers {
+        print!("{}", chip_8_debugger::format_registers(chip_8));
+        print!("{}", chip_8_debugger::format_stack(chip_8));
+    }
+    if dump.memory {
+        print!("{}", chip_8_debugger::format_memory(chip_8, memory.0, memory.1 - memory.0 + 1));
+    }
+}
+
+fn main() {
+    let mut rom_path = None;
+    let mut quirks = Quirks::default();
+    let mut cpu_hz = None;
+    let mut seed = None;
+    let mut random = "xorshift".to_string();
+    let mut runner = HeadlessRunner::new(600);
+    let mut dump = Dump { screen: true, registers: true, memory: false };
+    let mut memory = (0x000, 0xFFF);
+    let mut presses = Vec::new();
+    let mut screenshot = None;
+    let mut scale = 1;
+    let mut palette = DEFAULT_PALETTE;
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("{} needs a value", arg), USAGE));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--platform" => {
+                let name = value();
+                quirks = Quirks::from_name(&name).unwrap_or_else(|| fail(&format!("unknown platform `{}`", name), USAGE));
+            }
+            "--cpu-hz" => {
+                let text = value();
+                cpu_hz = Some(text.parse().unwrap_or_else(|_| fail(&format!("`{}` isn't a clock speed", text), USAGE)));
+            }
+            "--frames" => {
+                let text = value();
+                runner.frames = text.parse().unwrap_or_else(|_| fail(&format!("`{}` isn't a number of frames", text), USAGE));
+            }
+            "--seed" => {
+                let text = value();
+                seed = Some(text.parse().unwrap_or_else(|_| fail(&format!("`{}` isn't a seed", text), USAGE)));
+            }
+            "--rng" => {
+                random = value().to_lowercase();
+                if !chip_8_random::SOURCE_NAMES.contains(&random.as_str()) {
+                    fail(&format!("unknown rng `{}`", random), USAGE);
+                }
+            }
+            "--until" => runner.conditions.push(Condition::parse(&value()).unwrap_or_else(|e| fail(&e, USAGE))),
+            "--keys" => {
+                let path = value();
+                let text = fs::read_to_string(&path).unwrap_or_else(|e| fail(&format!("can't read key script {}: {}", path, e), USAGE));
+                runner.keys = KeyScript::parse(&text).unwrap_or_else(|e| fail(&e, USAGE));
+            }
+            "--press" => presses.push(parse_press(&value()).unwrap_or_else(|e| fail(&e, USAGE))),
+            "--dump" => dump = parse_dump(&value()).unwrap_or_else(|e| fail(&e, USAGE)),
+            "--memory" => {
+                let text = value();
+                let (start, end) = TraceFilter::parse_addresses(&text)
+                    .unwrap_or_else(|| fail(&format!("`{}` isn't a hex address range like 200-2FF", text), USAGE));
+                memory = (start as usize, end as usize);
+                dump.memory = true;
+            }
+            "--screenshot" => {
+                let path = value();
+                if ImageFormat::from_path(&path).is_none() {
+                    fail(&format!("--screenshot writes .png or .pbm files, not `{}`", path), USAGE);
+                }
+                screenshot = Some(path);
+            }
+            "--scale" => {
+                let text = value();
+                scale = text.parse().unwrap_or_else(|_| fail(&format!("`{}` isn't a scale", text), USAGE));
+            }
+            "--palette" => palette = chip_8_screenshot::parse_palette(&value()).unwrap_or_else(|e| fail(&e, USAGE)),
+            "--trace" => trace_path = Some(value()),
+            "--trace-format" => {
+                let name = value();
+                trace_format = TraceFormat::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("unknown trace format `{}`, expected text or binary", name), USAGE));
+            }
+            "--trace-addrs" => {
+                let text = value();
+                trace_filter.addresses = Some(TraceFilter::parse_addresses(&text)
+                    .unwrap_or_else(|| fail(&format!("`{}` isn't a hex address range like 200-2FF", text), USAGE)));
+            }
+            "--trace-ops" => {
+                let text = value();
+                trace_filter.opcodes = TraceFilter::parse_opcodes(&text)
+                    .unwrap_or_else(|| fail(&format!("`{}` isn't a list of opcode patterns like D???,Fx55", text), USAGE));
+            }
+            "--trace-frames" => {
+                let text = value();
+                trace_filter.frames = Some(TraceFilter::parse_frames(&text)
+                    .unwrap_or_else(|| fail(&format!("`{}` isn't a frame range like 120-300", text), USAGE)));
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ => fail(&format!("only one rom at a time, got `{}` too", arg), USAGE),
+        }
+    }
+
+    let rom_path = rom_path.unwrap_or_else(|| fail("no rom given", USAGE));
+    // added after the arguments are all read so they go on top of a --keys script wherever it is
+    for (frame, key, frames) in presses {
+        runner.keys.press(frame, key, frames);
+    }
+    let mut chip_8 = load(&rom_path, quirks, cpu_hz, &random, seed).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if let Some(path) = &trace_path {
+        let file = fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("can't write the trace to {}: {}", path, e);
+            process::exit(1);
+        });
+        chip_8.enable_trace(Tracer::new(Box::new(BufWriter::new(file)), trace_format, trace_filter));
+    }
+
+    // the state still gets dumped when the rom crashes, that's when it's wanted most
+    let outcome = runner.run(&mut chip_8);
+    if let Err(e) = chip_8.disable_trace() {
+        eprintln!("couldn't write the trace: {}", e);
+        process::exit(1);
+    }
+    print_dump(&chip_8, &dump, memory);
+    if let Some(path) = &screenshot {
+        if let Err(e) = chip_8_screenshot::save_screenshot(&chip_8, path, scale, &palette) {
+            eprintln!("couldn't save the screenshot: {}", e);
+            process::exit(1);
+        }
+    }
+    match outcome {
+        Ok(Outcome::Finished) if !runner.conditions.is_empty() => {
+            eprintln!("none of the --until conditions held in {} frames", runner.frames);
+            process::exit(3);
+        }
+        Ok(outcome) => eprintln!("{}", outcome),
+        Err(e) => {
+            eprintln!("emulation stopped: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use chip_8_emulator::chip_8_cli::fail;
+use chip_8_emulator::chip_8_octo;
+
+const USAGE: &str = "\
+usage: chip8-octo [options] <source.8o>
+
+options:
+  -o, --output <path>   where to write the rom (default: the source with a .ch8 extension)
+  -m, --map <path>      also write a source map for debuggers, lines and labels by address
+  -h, --help            show this";
+
+fn main() {
+    let mut source_path = None;
+    let mut output_path = None;
+    let mut map_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-o" | "--output" => output_path = Some(args.next().unwrap_or_else(|| fail("--output needs a path", USAGE))),
+            "-m" | "--map" => map_path = Some(args.next().unwrap_or_else(|| fail("--map needs a path", USAGE))),
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ => source_path = Some(arg),
+        }
+    }
+
+    let source_path = source_path.unwrap_or_else(|| fail("no source given", USAGE));
+    let output_path = output_path.unwrap_or_else(|| {
+        Path::new(&source_path).with_extension("ch8").to_string_lossy().into_owned()
+    });
+
+    if output_path == source_path {
+        fail("the rom would overwrite the source, pick another --output", USAGE);
+    }
+
+    let (rom, map) = chip_8_octo::compile_file_with_map(&source_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    if let Err(e) = fs::write(&output_path, &rom) {
+        eprintln!("can't write {}: {}", output_path, e);
+        process::exit(1);
+    }
+
+    if let Some(path) = map_path {
+        if let Err(e) = fs::write(&path, map.to_string()) {
+            eprintln!("can't write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
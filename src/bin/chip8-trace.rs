@@ -0,0 +1,83 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip_8_emulator::chip_8_cli::fail;
+use chip_8_emulator::chip_8_trace;
+use chip_8_emulator::Chip8Error;
+
+const USAGE: &str = "\
+usage: chip8-trace <trace> [other trace]
+
+prints a trace from --trace as text, given two it shows where they first differ
+either can be text or binary, so a binary trace can be compared against a text one
+
+options:
+  -C, --context <n>   lines of matching trace to show before the difference (default 5)
+  -h, --help          show this";
+
+// lines of the trace, binary ones get turned into the same text --trace-format text writes
+fn read_trace(path: &str) -> Result<Vec<String>, Chip8Error> {
+    let data = fs::read(path)?;
+    if chip_8_trace::is_binary_trace(&data) {
+        let records = chip_8_trace::read_binary_trace(&data)?;
+        return Ok(records.iter().map(|record| record.to_string()).collect());
+    }
+    Ok(String::from_utf8_lossy(&data).lines().map(str::to_string).collect())
+}
+
+fn main() {
+    let mut paths = Vec::new();
+    let mut context = 5;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-C" | "--context" => {
+                let text = args.next().unwrap_or_default();
+                context = text.parse().unwrap_or_else(|_| fail(&format!("--context expects a number, got `{}`", text), USAGE));
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option `{}`", arg), USAGE),
+            _ => paths.push(arg),
+        }
+    }
+
+    let traces: Vec<Vec<String>> = match paths.len() {
+        1 | 2 => paths.iter().map(|path| read_trace(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        })).collect(),
+        0 => fail("no trace given", USAGE),
+        _ => fail("at most two traces", USAGE),
+    };
+
+    if traces.len() == 1 {
+        for line in &traces[0] {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    let (first, second) = (&traces[0], &traces[1]);
+    let split = first.iter().zip(second).position(|(a, b)| a != b);
+    let split = match split {
+        Some(index) => index,
+        None if first.len() == second.len() => {
+            println!("the traces match, {} instructions", first.len());
+            return;
+        }
+        None => first.len().min(second.len()),
+    };
+
+    println!("the traces match for {} instructions, then", split);
+    for line in &first[split.saturating_sub(context)..split] {
+        println!("  {}", line);
+    }
+    println!("- {}", first.get(split).map_or("(end of trace)", String::as_str));
+    println!("+ {}", second.get(split).map_or("(end of trace)", String::as_str));
+    process::exit(1);
+}
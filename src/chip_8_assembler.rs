@@ -0,0 +1,721 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip_8_emulator::{BYTE, MEMORY_SIZE, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_instruction::Instruction;
+use crate::chip_8_source_map::SourceMap;
+
+// includes can't nest deeper than this, mostly to catch a file that includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+// same for constants defined in terms of each other
+const MAX_CONSTANT_DEPTH: usize = 32;
+// bytes shown on each line of the listing, longer data carries on over the next lines
+const LISTING_BYTES_PER_LINE: usize = 4;
+
+// where a line of source came from, for errors and the listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub number: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    pub address: usize,
+    pub bytes: Vec<BYTE>,
+    pub source: SourceLine,
+}
+
+pub struct Assembly {
+    pub origin: usize,
+    pub rom: Vec<BYTE>,
+    // labels and the address each one ended up at
+    pub labels: BTreeMap<String, usize>,
+    pub listing: Vec<ListingLine>,
+}
+
+// Assembles source text, includes are looked up relative to the current directory.
+// Accepts everything the disassembler prints, so disassembling a rom and assembling it again
+// gives back the same bytes.
+pub fn assemble(source: &str, origin: usize) -> Result<Assembly, Chip8Error> {
+    let mut assembler = Assembler::new(origin);
+    assembler.load_text("<source>", source, Path::new("."), 0)?;
+    assembler.run()
+}
+
+// same as assemble, includes are relative to the file doing the including
+pub fn assemble_file(path: &str, origin: usize) -> Result<Assembly, Chip8Error> {
+    let mut assembler = Assembler::new(origin);
+    assembler.load_file(Path::new(path), None, 0)?;
+    assembler.run()
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Origin(String),
+    Constant(String, String),
+}
+
+struct ParsedLine {
+    source: SourceLine,
+    labels: Vec<String>,
+    statement: Option<Statement>,
+}
+
+enum Symbol {
+    Label(usize),
+    Constant(String),
+}
+
+// operands after the mnemonic, anything that isn't a register or keyword is an expression
+#[derive(Clone, Copy)]
+enum Operand<'a> {
+    V(BYTE),
+    I,
+    IndirectI,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(&'a str),
+    Value(&'a str),
+}
+
+struct Assembler {
+    origin: usize,
+    lines: Vec<ParsedLine>,
+    symbols: BTreeMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn new(origin: usize) -> Assembler {
+        Assembler { origin, lines: Vec::new(), symbols: BTreeMap::new() }
+    }
+
+    fn load_file(&mut self, path: &Path, included_from: Option<&SourceLine>, depth: usize) -> Result<(), Chip8Error> {
+        let text = fs::read_to_string(path).map_err(|e| match included_from {
+            Some(source) => error_at(source, format!("can't include {}: {}", path.display(), e)),
+            None => Chip8Error::Io(e),
+        })?;
+        let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.load_text(&path.display().to_string(), &text, &directory, depth)
+    }
+
+    fn load_text(&mut self, file: &str, text: &str, directory: &Path, depth: usize) -> Result<(), Chip8Error> {
+        for (index, text) in text.lines().enumerate() {
+            let source = SourceLine { file: file.to_string(), number: index + 1, text: text.to_string() };
+            let (labels, rest) = split_labels(strip_comment(text));
+
+            // include pulls the other file's lines in right here, labels in front of it still count
+            let (mnemonic, arguments) = split_mnemonic(rest);
+            if mnemonic.eq_ignore_ascii_case("include") {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(error_at(&source, "includes nested too deep".to_string()));
+                }
+                let name = unquote(arguments).ok_or_else(|| error_at(&source, "include needs a \"file name\"".to_string()))?;
+                let path: PathBuf = directory.join(name);
+                self.lines.push(ParsedLine { source: source.clone(), labels, statement: None });
+                self.load_file(&path, Some(&source), depth + 1)?;
+                continue;
+            }
+
+            let statement = parse_statement(rest).map_err(|message| error_at(&source, message))?;
+            self.lines.push(ParsedLine { source, labels, statement });
+        }
+        Ok(())
+    }
+
+    fn run(mut self) -> Result<Assembly, Chip8Error> {
+        // first pass works out where every line goes so labels can be used before they're defined
+        let lines = std::mem::take(&mut self.lines);
+        let mut address = self.origin;
+        for line in lines.iter() {
+            let located = |message: String| error_at(&line.source, message);
+            for label in line.labels.iter() {
+                self.define(label, Symbol::Label(address)).map_err(located)?;
+            }
+            match &line.statement {
+                Some(Statement::Constant(name, expression)) => {
+                    self.define(name, Symbol::Constant(expression.clone())).map_err(located)?;
+                }
+                Some(Statement::Origin(expression)) => {
+                    let target = self.evaluate(expression, 0).map_err(located)?;
+                    if target < address as i64 {
+                        return Err(located(format!("org 0x{:X} is behind the current address 0x{:X}", target, address)));
+                    }
+                    address = target as usize;
+                }
+                Some(statement) => address += statement_size(statement),
+                None => (),
+            }
+            if address > MEMORY_SIZE {
+                return Err(located(format!("program runs past the end of memory (0x{:X})", MEMORY_SIZE)));
+            }
+        }
+
+        // second pass evaluates the operands and writes out the bytes
+        let mut rom = Vec::new();
+        let mut listing = Vec::with_capacity(lines.len());
+        let mut address = self.origin;
+        for line in lines.iter() {
+            let bytes = match &line.statement {
+                Some(Statement::Instruction { mnemonic, operands }) => self.encode_instruction(mnemonic, operands),
+                Some(Statement::Bytes(items)) => self.encode_bytes(items),
+                Some(Statement::Words(items)) => self.encode_words(items),
+                Some(Statement::Origin(_)) | Some(Statement::Constant(..)) | None => Ok(Vec::new()),
+            }.map_err(|message| error_at(&line.source, message))?;
+
+            if let Some(Statement::Origin(expression)) = &line.statement {
+                // pad up to the new address, already checked in the first pass
+                let target = self.evaluate(expression, 0).map_err(|message| error_at(&line.source, message))? as usize;
+                rom.resize(target - self.origin, 0);
+                address = target;
+            }
+
+            listing.push(ListingLine { address, bytes: bytes.clone(), source: line.source.clone() });
+            rom.extend_from_slice(&bytes);
+            address += bytes.len();
+        }
+
+        let labels = self.symbols.iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Label(address) => Some((name.clone(), *address)),
+                Symbol::Constant(_) => None,
+            })
+            .collect();
+        Ok(Assembly { origin: self.origin, rom, labels, listing })
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), String> {
+        if !matches!(classify_operand(name), Operand::Value(_)) {
+            return Err(format!("`{}` is a register name and can't be used as a label or constant", name));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(format!("`{}` is defined more than once", name));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    // sums and differences of numbers, labels and constants
+    fn evaluate(&self, expression: &str, depth: usize) -> Result<i64, String> {
+        if depth > MAX_CONSTANT_DEPTH {
+            return Err(format!("constants nested too deep evaluating `{}`", expression));
+        }
+        let expression = expression.trim();
+        if expression.is_empty() {
+            return Err("missing value".to_string());
+        }
+
+        let mut total: i64 = 0;
+        let mut negative = false;
+        let mut term = String::new();
+        for c in expression.chars().chain(std::iter::once('+')) {
+            if (c == '+' || c == '-') && !term.trim().is_empty() {
+                let value = self.evaluate_term(term.trim(), depth)?;
+                let value = if negative {value.checked_neg()} else {Some(value)};
+                total = value.and_then(|value| total.checked_add(value)).ok_or_else(|| format!("value out of range in `{}`", expression))?;
+                term.clear();
+                negative = c == '-';
+            } else if c == '-' && term.trim().is_empty() {
+                negative = !negative;
+            } else if c != '+' {
+                term.push(c);
+            }
+        }
+        Ok(total)
+    }
+
+    fn evaluate_term(&self, term: &str, depth: usize) -> Result<i64, String> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        match self.symbols.get(term) {
+            Some(Symbol::Label(address)) => Ok(*address as i64),
+            Some(Symbol::Constant(expression)) => self.evaluate(expression, depth + 1),
+            None if term.starts_with(|c: char| c.is_ascii_digit()) => Err(format!("`{}` isn't a number", term)),
+            None => Err(format!("unknown label or constant `{}`", term)),
+        }
+    }
+
+    // evaluates and checks it fits in max, negative numbers count back from max + 1
+    fn value(&self, expression: &str, max: WORD) -> Result<WORD, String> {
+        let value = self.evaluate(expression, 0)?;
+        let max = max as i64;
+        if value > max || value < -(max + 1) {
+            return Err(format!("`{}` is out of range, it has to fit in 0x{:X}", expression, max));
+        }
+        Ok((value & max) as WORD)
+    }
+
+    fn encode_instruction(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<BYTE>, String> {
+        let operands: Vec<Operand> = operands.iter().map(|operand| classify_operand(operand)).collect();
+        let byte = |expression: &str| self.value(expression, 0xFF).map(|value| value as BYTE);
+        let nibble = |expression: &str| self.value(expression, 0xF).map(|value| value as BYTE);
+        let address = |expression: &str| self.value(expression, 0xFFF);
+
+        use Operand::*;
+        let instruction = match (mnemonic, operands.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n)]) => Instruction::ScrollDown(nibble(n)?),
+            ("SCU", [Value(n)]) => Instruction::ScrollUp(nibble(n)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowRes,
+            ("HIGH", []) => Instruction::HighRes,
+            ("SYS", [Value(nnn)]) => Instruction::Sys(address(nnn)?),
+            ("JP", [Value(nnn)]) => Instruction::Jump(address(nnn)?),
+            ("JP", [V(0), Value(nnn)]) => Instruction::JumpOffset(address(nnn)?),
+            ("CALL", [Value(nnn)]) => Instruction::Call(address(nnn)?),
+            ("SE", [V(x), Value(nn)]) => Instruction::SkipEqualByte(*x, byte(nn)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipEqual(*x, *y),
+            ("SNE", [V(x), Value(nn)]) => Instruction::SkipNotEqualByte(*x, byte(nn)?),
+            ("SNE", [V(x), V(y)]) => Instruction::SkipNotEqual(*x, *y),
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange(*x, *y),
+            ("LD", [V(x), Value(nn)]) => Instruction::LoadByte(*x, byte(nn)?),
+            ("LD", [V(x), V(y)]) => Instruction::Move(*x, *y),
+            ("LD", [I, Value(nnn)]) => Instruction::LoadI(address(nnn)?),
+            ("LD", [I, Long(nnnn)]) => Instruction::LoadILong(self.value(nnnn, 0xFFFF)?),
+            ("LD", [V(x), Delay]) => Instruction::GetDelay(*x),
+            ("LD", [V(x), Key]) => Instruction::WaitKey(*x),
+            ("LD", [Delay, V(x)]) => Instruction::SetDelay(*x),
+            ("LD", [Sound, V(x)]) => Instruction::SetSound(*x),
+            ("LD", [Font, V(x)]) => Instruction::Font(*x),
+            ("LD", [BigFont, V(x)]) => Instruction::BigFont(*x),
+            ("LD", [Bcd, V(x)]) => Instruction::Bcd(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::Store(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::Load(*x),
+            ("LD", [Flags, V(x)]) => Instruction::SaveFlags(*x),
+            ("LD", [V(x), Flags]) => Instruction::LoadFlags(*x),
+            ("ADD", [V(x), Value(nn)]) => Instruction::AddByte(*x, byte(nn)?),
+            ("ADD", [V(x), V(y)]) => Instruction::Add(*x, *y),
+            ("ADD", [I, V(x)]) => Instruction::AddI(*x),
+            ("OR", [V(x), V(y)]) => Instruction::Or(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::And(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::SubReverse(*x, *y),
+            // the one operand forms shift VX in place
+            ("SHR", [V(x)]) => Instruction::ShiftRight(*x, *x),
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftRight(*x, *y),
+            ("SHL", [V(x)]) => Instruction::ShiftLeft(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft(*x, *y),
+            ("RND", [V(x), Value(nn)]) => Instruction::Random(*x, byte(nn)?),
+            ("DRW", [V(x), V(y), Value(n)]) => Instruction::Draw(*x, *y, nibble(n)?),
+            ("SKP", [V(x)]) => Instruction::SkipKey(*x),
+            ("SKNP", [V(x)]) => Instruction::SkipNotKey(*x),
+            ("PLANE", [Value(n)]) => Instruction::Plane(nibble(n)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [V(x)]) => Instruction::Pitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => return Err(format!("{} can't take those operands", mnemonic)),
+            _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+        };
+
+        let opcode = instruction.encode();
+        let mut bytes = vec![(opcode >> 8) as BYTE, opcode as BYTE];
+        if let Instruction::LoadILong(nnnn) = instruction {
+            bytes.push((nnnn >> 8) as BYTE);
+            bytes.push(nnnn as BYTE);
+        }
+        Ok(bytes)
+    }
+
+    fn encode_bytes(&self, items: &[String]) -> Result<Vec<BYTE>, String> {
+        let mut bytes = Vec::new();
+        for item in items.iter() {
+            match unquote(item) {
+                Some(text) => bytes.extend_from_slice(text.as_bytes()),
+                None => bytes.push(self.value(item, 0xFF)? as BYTE),
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn encode_words(&self, items: &[String]) -> Result<Vec<BYTE>, String> {
+        let mut bytes = Vec::new();
+        for item in items.iter() {
+            let word = self.value(item, 0xFFFF)?;
+            bytes.push((word >> 8) as BYTE);
+            bytes.push(word as BYTE);
+        }
+        Ok(bytes)
+    }
+}
+
+fn error_at(source: &SourceLine, message: String) -> Chip8Error {
+    Chip8Error::Assembly { file: source.file.clone(), line: source.number, message }
+}
+
+const MNEMONICS: [&str; 32] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn statement_size(statement: &Statement) -> usize {
+    match statement {
+        Statement::Instruction { mnemonic, operands } => {
+            // LD I, LONG is the only 4 byte instruction
+            let long = mnemonic == "LD" && operands.len() == 2 && matches!(classify_operand(&operands[1]), Operand::Long(_));
+            if long {4} else {2}
+        }
+        Statement::Bytes(items) => items.iter().map(|item| unquote(item).map_or(1, str::len)).sum(),
+        Statement::Words(items) => items.len() * 2,
+        Statement::Origin(_) | Statement::Constant(..) => 0,
+    }
+}
+
+fn parse_statement(text: &str) -> Result<Option<Statement>, String> {
+    let (first, rest) = split_mnemonic(text);
+    if first.is_empty() {
+        return Ok(None);
+    }
+
+    // NAME = value and NAME EQU value define constants
+    let (second, value) = split_mnemonic(rest);
+    if second == "=" || second.eq_ignore_ascii_case("equ") {
+        return Ok(Some(Statement::Constant(first.to_string(), value.to_string())));
+    }
+    if let Some(value) = rest.strip_prefix('=') {
+        return Ok(Some(Statement::Constant(first.to_string(), value.trim().to_string())));
+    }
+    if let Some((name, value)) = first.split_once('=') {
+        let value = format!("{} {}", value, rest);
+        return Ok(Some(Statement::Constant(name.to_string(), value.trim().to_string())));
+    }
+
+    let mnemonic = first.to_uppercase();
+    let operands = split_operands(rest);
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err(format!("empty operand in `{}`", text.trim()));
+    }
+    let statement = match mnemonic.as_str() {
+        "DB" => Statement::Bytes(operands),
+        "DW" => Statement::Words(operands),
+        "ORG" if operands.len() == 1 => Statement::Origin(operands[0].clone()),
+        "ORG" => return Err("org takes one address".to_string()),
+        _ => Statement::Instruction { mnemonic, operands },
+    };
+    Ok(Some(statement))
+}
+
+fn classify_operand(text: &str) -> Operand<'_> {
+    let upper = text.to_uppercase();
+    match upper.as_str() {
+        "I" => return Operand::I,
+        "[I]" => return Operand::IndirectI,
+        "DT" => return Operand::Delay,
+        "ST" => return Operand::Sound,
+        "K" => return Operand::Key,
+        "F" => return Operand::Font,
+        "HF" => return Operand::BigFont,
+        "B" => return Operand::Bcd,
+        "R" => return Operand::Flags,
+        _ => (),
+    }
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Some(x) = upper[1..].chars().next().and_then(|c| c.to_digit(16)) {
+            return Operand::V(x as BYTE);
+        }
+    }
+    if upper.starts_with("LONG") && text[4..].starts_with(char::is_whitespace) {
+        return Operand::Long(text[4..].trim());
+    }
+    Operand::Value(text)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else if let Some(hex) = lower.strip_prefix('$') {
+        i64::from_str_radix(hex, 16).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        None
+    }
+}
+
+// `;` starts a comment unless it's inside a string
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..index],
+            _ => (),
+        }
+    }
+    text
+}
+
+// any number of `name:` at the start of the line
+fn split_labels(text: &str) -> (Vec<String>, &str) {
+    let mut labels = Vec::new();
+    let mut rest = text.trim();
+    while let Some(colon) = rest.find(':') {
+        let name = &rest[..colon];
+        if !is_identifier(name) {
+            break;
+        }
+        labels.push(name.to_string());
+        rest = rest[colon + 1..].trim_start();
+    }
+    (labels, rest)
+}
+
+fn split_mnemonic(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    }
+}
+
+// commas outside strings separate operands
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                current.push(c);
+            }
+            ',' if !in_string => {
+                operands.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    operands.push(current.trim().to_string());
+    operands
+}
+
+fn unquote(text: &str) -> Option<&str> {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        Some(&text[1..text.len() - 1])
+    } else {
+        None
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+impl Assembly {
+    // the listing without the text, for debuggers
+    pub fn source_map(&self) -> SourceMap {
+        let mut map = SourceMap::new();
+        for (name, address) in self.labels.iter() {
+            map.add_label(name, *address as WORD);
+        }
+        for line in self.listing.iter() {
+            map.add_line(line.address as WORD, line.bytes.len(), &line.source.file, line.source.number);
+        }
+        map
+    }
+}
+
+// address, bytes and the source line they came from, long data carries on over extra lines
+impl fmt::Display for Assembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.listing.iter() {
+            let mut chunks = line.bytes.chunks(LISTING_BYTES_PER_LINE);
+            let first: Vec<String> = chunks.next().unwrap_or(&[]).iter().map(|b| format!("{:02X}", b)).collect();
+            writeln!(f, "{:04X}  {:<12} {}", line.address, first.join(" "), line.source.text)?;
+            for (index, chunk) in chunks.enumerate() {
+                let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+                writeln!(f, "{:04X}  {}", line.address + (index + 1) * LISTING_BYTES_PER_LINE, bytes.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_8_disassembler::disassemble;
+
+    fn assert_round_trips(rom: &[BYTE]) {
+        for follow_flow in [false, true] {
+            let source = disassemble(rom, 0x200, follow_flow).to_string();
+            let assembly = assemble(&source, 0x200).unwrap_or_else(|e| panic!("{}\n{}", e, source));
+            assert_eq!(assembly.rom, rom, "follow_flow {}\n{}", follow_flow, source);
+        }
+    }
+
+    fn assert_error(source: &str, expected: &str) {
+        match assemble(source, 0x200) {
+            Err(Chip8Error::Assembly { message, .. }) => assert!(message.contains(expected), "`{}` isn't about `{}`", message, expected),
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("`{}` assembled", source),
+        }
+    }
+
+    // a directory of its own under the system temp dir, gone again at the end of the test
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = std::env::temp_dir().join(format!("chip_8_assembler_{}_{}", name, std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        fn write(&self, name: &str, text: &str) -> String {
+            let path = self.0.join(name);
+            fs::write(&path, text).unwrap();
+            path.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        let words: Vec<BYTE> = (0..=0xFFFFu16).flat_map(|opcode| opcode.to_be_bytes()).collect();
+        for chunk in words.chunks(0x2000) {
+            assert_round_trips(chunk);
+        }
+    }
+
+    #[test]
+    fn random_roms_round_trip() {
+        // xorshift32, so the same roms come up every run
+        let mut state = 0x2545_F491u32;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        for _ in 0..200 {
+            let length = next() as usize % 600 + 1;
+            let rom: Vec<BYTE> = (0..length).map(|_| next() as BYTE).collect();
+            assert_round_trips(&rom);
+        }
+    }
+
+    #[test]
+    fn odd_shapes_round_trip() {
+        // F000 with its operand, including one that looks like an opcode itself
+        assert_round_trips(&[0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00, 0xF0, 0x00, 0x00, 0xEE]);
+        // F000 cut off by the end of the rom
+        assert_round_trips(&[0x00, 0xE0, 0xF0, 0x00]);
+        assert_round_trips(&[0x00, 0xE0, 0xF0, 0x00, 0x12]);
+        // odd lengths leave a byte of data at the end
+        assert_round_trips(&[0x12, 0x00, 0xAB]);
+        assert_round_trips(&[0xAB]);
+        // a skip over F000 NNNN lands after the operand
+        assert_round_trips(&[0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x12, 0x06]);
+    }
+
+    #[test]
+    fn unknown_opcodes_become_dw() {
+        let rom = [0x51, 0x21, 0xE1, 0x00, 0xF1, 0xFF];
+        let source = disassemble(&rom, 0x200, false).to_string();
+        assert!(source.contains("DW 0x5121"), "{}", source);
+        assert!(source.contains("DW 0xE100"), "{}", source);
+        assert!(source.contains("DW 0xF1FF"), "{}", source);
+        assert_round_trips(&rom);
+        assert_eq!(assemble("DW 0x5121, 0xFFFF", 0x200).unwrap().rom, [0x51, 0x21, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn org_pads_forward_only() {
+        let assembly = assemble("CLS\norg 0x206\nRET", 0x200).unwrap();
+        assert_eq!(assembly.rom, [0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE]);
+        assert_eq!(assembly.listing[2].address, 0x206);
+
+        assert_error("CLS\norg 0x202\norg 0x201", "org 0x201 is behind the current address 0x202");
+        assert_error("org 0xFFFF\nCLS", "past the end of memory");
+        assert_error("org", "org takes one address");
+        assert_error("org nowhere", "nowhere");
+    }
+
+    #[test]
+    fn constants() {
+        let source = "\
+            SPRITES = 0x300
+            ROWS EQU 5
+            LAST=SPRITES + ROWS - 1
+            LD I, LAST
+            DRW V0, V1, ROWS
+            DB -1, ROWS - -2";
+        assert_eq!(assemble(source, 0x200).unwrap().rom, [0xA3, 0x04, 0xD0, 0x15, 0xFF, 0x07]);
+        // constants can be used before they're defined, labels too
+        assert_eq!(assemble("JP END + OFFSET\nOFFSET = 2\nEND:", 0x200).unwrap().rom, [0x12, 0x04]);
+    }
+
+    #[test]
+    fn expression_errors() {
+        assert_error("DB 0x7FFFFFFFFFFFFFFF + 1", "value out of range in `0x7FFFFFFFFFFFFFFF + 1`");
+        assert_error("MIN = -0x7FFFFFFFFFFFFFFF - 1\nDB -MIN", "value out of range");
+        assert_error("DB 0x100", "`0x100` is out of range, it has to fit in 0xFF");
+        assert_error("DB -0x101", "is out of range");
+        assert_error("DB nowhere", "unknown label or constant `nowhere`");
+        assert_error("DB 12ab", "`12ab` isn't a number");
+        assert_error("P = Q\nQ = P\nDB P", "constants nested too deep");
+        assert_error("X = 1\nX = 2", "`X` is defined more than once");
+        assert_error("V3 = 1", "is a register name");
+    }
+
+    #[test]
+    fn includes() {
+        let scratch = Scratch::new("includes");
+        scratch.write("font.asm", "digit: DB 0xF0, 0x90\n");
+        let main = scratch.write("main.asm", "LD I, digit\ninclude \"font.asm\"\n");
+        let assembly = assemble_file(&main, 0x200).unwrap();
+        assert_eq!(assembly.rom, [0xA2, 0x02, 0xF0, 0x90]);
+        assert_eq!(assembly.labels["digit"], 0x202);
+
+        let missing = scratch.write("missing.asm", "CLS\ninclude \"nothing.asm\"\n");
+        match assemble_file(&missing, 0x200) {
+            Err(Chip8Error::Assembly { line, message, .. }) => {
+                assert_eq!(line, 2);
+                assert!(message.starts_with("can't include"), "{}", message);
+            }
+            other => panic!("{:?}", other.map(|assembly| assembly.rom)),
+        }
+
+        let looping = scratch.write("loop.asm", "include \"loop.asm\"\n");
+        match assemble_file(&looping, 0x200) {
+            Err(Chip8Error::Assembly { message, .. }) => assert_eq!(message, "includes nested too deep"),
+            other => panic!("{:?}", other.map(|assembly| assembly.rom)),
+        }
+
+        assert_error("include nothing.asm", "include needs a \"file name\"");
+    }
+}
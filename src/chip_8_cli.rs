@@ -0,0 +1,33 @@
+use std::process;
+
+// Bits shared by the command line tools in src/bin and the frontend.
+
+// prints what was wrong with the arguments and the tool's usage text, then exits with status 2
+pub fn fail(message: &str, usage: &str) -> ! {
+    eprintln!("{}\n\n{}", message, usage);
+    process::exit(2);
+}
+
+// a hex number with or without 0x in front, how every tool takes addresses and values
+pub fn parse_hex(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_numbers_parse_with_or_without_0x() {
+        assert_eq!(parse_hex("2A0"), Some(0x2A0));
+        assert_eq!(parse_hex("0x2a0"), Some(0x2A0));
+        assert_eq!(parse_hex("0X2A0"), Some(0x2A0));
+        assert_eq!(parse_hex(" ffff "), Some(0xFFFF));
+        assert_eq!(parse_hex("0x"), None);
+        assert_eq!(parse_hex("0x0x10"), None);
+        assert_eq!(parse_hex("2G0"), None);
+        assert_eq!(parse_hex(""), None);
+    }
+}
@@ -0,0 +1,476 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::chip_8_emulator::{Chip8Hardware, BYTE};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_golden::Frame;
+use crate::chip_8_headless::{HeadlessRunner, KeyScript};
+use crate::chip_8_json::Json;
+use crate::chip_8_quirks::Quirks;
+
+const BUILTIN_GLYPHS: &str = include_str!("chip_8_conformance_glyphs.txt");
+// columns between glyphs of the same word, anything wider starts a new word
+const LETTER_SPACING: usize = 2;
+// the test roms check this byte at startup and skip their menu when it picks a test or platform
+const AUTOSTART_ADDRESS: usize = 0x1FF;
+
+// One of the community test roms, named the way Timendus' chip8-test-suite ships them.
+pub struct ConformanceTest {
+    pub name: &'static str,
+    pub file: &'static str,
+    pub frames: u64,
+    // what goes in AUTOSTART_ADDRESS for each preset, None runs the rom untouched
+    pub autostart: fn(&str) -> Option<BYTE>,
+    pub keys: &'static str,
+    // some roms don't write any marks, just drawing the right picture is the test
+    pub has_marks: bool,
+}
+
+// the platform menu of the quirks test
+fn quirks_platform(preset: &str) -> Option<BYTE> {
+    match preset {
+        "vip" => Some(1),
+        "xochip" => Some(3),
+        _ => Some(2),
+    }
+}
+
+pub const SUITE: [ConformanceTest; 5] = [
+    ConformanceTest { name: "ibm", file: "2-ibm-logo.ch8", frames: 60, autostart: |_| None, keys: "", has_marks: false },
+    ConformanceTest { name: "corax", file: "3-corax+.ch8", frames: 180, autostart: |_| None, keys: "", has_marks: true },
+    ConformanceTest { name: "flags", file: "4-flags.ch8", frames: 300, autostart: |_| None, keys: "", has_marks: true },
+    ConformanceTest { name: "quirks", file: "5-quirks.ch8", frames: 600, autostart: quirks_platform, keys: "", has_marks: true },
+    // FX0A, the key has to go down and come back up before it counts
+    ConformanceTest { name: "keypad", file: "6-keypad.ch8", frames: 240, autostart: |_| Some(3), keys: "60 press 5 10", has_marks: true },
+];
+
+pub fn find_test(name: &str) -> Option<&'static ConformanceTest> {
+    SUITE.iter().find(|test| test.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Glyph {
+    name: String,
+    rows: Vec<Vec<bool>>,
+}
+
+// Templates for reading text off the screen, see chip_8_conformance_glyphs.txt for the format.
+#[derive(Debug, Clone, Default)]
+pub struct GlyphSet {
+    glyphs: Vec<Glyph>,
+}
+
+impl GlyphSet {
+    pub fn builtin() -> GlyphSet {
+        GlyphSet::parse(BUILTIN_GLYPHS).expect("the built in glyphs don't parse")
+    }
+
+    pub fn parse(text: &str) -> Result<GlyphSet, String> {
+        let mut glyphs = Vec::new();
+        let mut current: Option<Glyph> = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('#') && current.is_none() {
+                continue;
+            }
+            if line.is_empty() {
+                glyphs.extend(current.take());
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("glyph ") {
+                glyphs.extend(current.take());
+                current = Some(Glyph { name: name.trim().to_string(), rows: Vec::new() });
+                continue;
+            }
+            let glyph = current.as_mut().ok_or_else(|| format!("glyph line {}: rows before a `glyph <name>` line", number + 1))?;
+            if line.chars().any(|c| c != '#' && c != '.') {
+                return Err(format!("glyph line {}: rows are `#` and `.`", number + 1));
+            }
+            glyph.rows.push(line.chars().map(|c| c == '#').collect());
+        }
+        glyphs.extend(current);
+        if let Some(glyph) = glyphs.iter().find(|glyph| glyph.rows.iter().any(|row| row.len() != glyph.rows[0].len())) {
+            return Err(format!("glyph `{}` has rows of different widths", glyph.name));
+        }
+        Ok(GlyphSet { glyphs })
+    }
+
+    pub fn load(path: &str) -> Result<GlyphSet, Chip8Error> {
+        GlyphSet::parse(&fs::read_to_string(path)?).map_err(|reason| Chip8Error::InvalidGolden { path: path.to_string(), reason })
+    }
+
+    // templates from other are tried first, so they can replace built in ones
+    pub fn merge(&mut self, other: GlyphSet) {
+        let mut glyphs = other.glyphs;
+        glyphs.append(&mut self.glyphs);
+        self.glyphs = glyphs;
+    }
+
+    // an exact match, or failing that one off by a pixel or two in the same size box
+    fn recognize(&self, rows: &[Vec<bool>]) -> Option<&str> {
+        let size = |rows: &[Vec<bool>]| (rows.len(), rows.first().map_or(0, |row| row.len()));
+        let wrong = |glyph: &Glyph| glyph.rows.iter().flatten().zip(rows.iter().flatten()).filter(|(a, b)| a != b).count();
+        let candidates = self.glyphs.iter().filter(|glyph| size(&glyph.rows) == size(rows));
+        let (glyph, wrong) = candidates.map(|glyph| (glyph, wrong(glyph))).min_by_key(|(_, wrong)| *wrong)?;
+        let allowed = (rows.len() * rows[0].len() / 12).min(2);
+        if wrong <= allowed {Some(glyph.name.as_str())} else {None}
+    }
+}
+
+fn format_glyph(rows: &[Vec<bool>]) -> String {
+    let mut text = String::from("glyph ?\n");
+    for row in rows {
+        text.extend(row.iter().map(|&lit| if lit {'#'} else {'.'}));
+        text.push('\n');
+    }
+    text
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Mark(bool),
+}
+
+// Splits the screen into lines of glyphs at blank rows and columns and reads each one. Glyphs
+// that aren't in the set come back as `?` and get added to `unknown`.
+fn read_screen(frame: &Frame, glyphs: &GlyphSet, unknown: &mut Vec<Vec<Vec<bool>>>) -> Vec<Vec<Token>> {
+    let frame = frame.flattened();
+    let lit = |x: usize, y: usize| frame.pixels[y][x] != 0;
+    let mut lines = Vec::new();
+    let mut y = 0;
+    while y < frame.height {
+        if !(0..frame.width).any(|x| lit(x, y)) {
+            y += 1;
+            continue;
+        }
+        let top = y;
+        while y < frame.height && (0..frame.width).any(|x| lit(x, y)) {
+            y += 1;
+        }
+        let band = top..y;
+
+        let mut tokens = Vec::new();
+        let mut word = String::new();
+        let mut gap = 0;
+        let mut x = 0;
+        while x < frame.width {
+            if !band.clone().any(|y| lit(x, y)) {
+                gap += 1;
+                x += 1;
+                continue;
+            }
+            let left = x;
+            while x < frame.width && band.clone().any(|y| lit(x, y)) {
+                x += 1;
+            }
+            // trimmed to the rows it actually uses
+            let used: Vec<usize> = band.clone().filter(|&y| (left..x).any(|x| lit(x, y))).collect();
+            let rows: Vec<Vec<bool>> = (used[0]..=used[used.len() - 1])
+                .map(|y| (left..x).map(|x| lit(x, y)).collect())
+                .collect();
+
+            if gap > LETTER_SPACING && !word.is_empty() {
+                tokens.push(Token::Word(std::mem::take(&mut word)));
+            }
+            gap = 0;
+            match glyphs.recognize(&rows) {
+                Some(mark @ "pass") | Some(mark @ "fail") => {
+                    if !word.is_empty() {
+                        tokens.push(Token::Word(std::mem::take(&mut word)));
+                    }
+                    tokens.push(Token::Mark(mark == "pass"));
+                }
+                Some(text) => word.push_str(text),
+                None => {
+                    word.push('?');
+                    if !unknown.contains(&rows) {
+                        unknown.push(rows);
+                    }
+                }
+            }
+        }
+        if !word.is_empty() {
+            tokens.push(Token::Word(word));
+        }
+        lines.push(tokens);
+    }
+    lines
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub label: String,
+    pub passed: bool,
+}
+
+// each mark labelled with the words before it on its line, since the last mark
+fn results(lines: Vec<Vec<Token>>) -> Vec<TestResult> {
+    let mut results = Vec::new();
+    for line in lines {
+        let mut label = Vec::new();
+        for token in line {
+            match token {
+                Token::Word(word) => label.push(word),
+                Token::Mark(passed) => {
+                    let label = std::mem::take(&mut label).join(" ");
+                    results.push(TestResult { label, passed });
+                }
+            }
+        }
+    }
+    results
+}
+
+// how one rom did under one preset
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    // every mark on screen was a pass
+    Passed,
+    Failed,
+    // finished without crashing but nothing on screen could be read as a result
+    Unreadable,
+    Crashed(String),
+    // the rom file isn't in the suite directory
+    Missing,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "pass"),
+            Outcome::Failed => write!(f, "fail"),
+            Outcome::Unreadable => write!(f, "unreadable"),
+            Outcome::Crashed(_) => write!(f, "crashed"),
+            Outcome::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+pub struct RomReport {
+    pub test: &'static str,
+    pub preset: String,
+    pub outcome: Outcome,
+    // each mark on screen with the text before it on the same line
+    pub results: Vec<TestResult>,
+    pub screen: Option<Frame>,
+    // glyphs on screen that aren't in the glyph set
+    pub unknown_glyphs: Vec<String>,
+}
+
+// Runs one test rom from `directory` under a quirks preset and reads the marks it leaves on
+// screen. A rom with no marks to read passes when it matches a golden `<rom>.txt` or `<rom>.pbm`
+// next to it, or just by finishing with something drawn when there isn't one.
+pub fn run_test(test: &'static ConformanceTest, preset: &str, directory: &Path, glyphs: &GlyphSet) -> RomReport {
+    let mut report = RomReport {
+        test: test.name,
+        preset: preset.to_string(),
+        outcome: Outcome::Missing,
+        results: Vec::new(),
+        screen: None,
+        unknown_glyphs: Vec::new(),
+    };
+    let path = directory.join(test.file);
+    if !path.exists() {
+        return report;
+    }
+
+    let mut chip_8 = Chip8Hardware::with_quirks(Quirks::from_name(preset).unwrap_or_default());
+    chip_8.enable_decode_cache();
+    if let Err(e) = chip_8.load_program(&path.to_string_lossy()) {
+        report.outcome = Outcome::Crashed(e.to_string());
+        return report;
+    }
+    chip_8.seed_rng(0);
+    if let Some(value) = (test.autostart)(preset) {
+        chip_8.write_memory(AUTOSTART_ADDRESS, value);
+    }
+    let mut runner = HeadlessRunner::new(test.frames);
+    runner.keys = KeyScript::parse(test.keys).unwrap_or_default();
+    let result = runner.run(&mut chip_8);
+    let screen = Frame::capture(&chip_8);
+    report.screen = Some(screen.clone());
+    if let Err(e) = result {
+        report.outcome = Outcome::Crashed(e.to_string());
+        return report;
+    }
+
+    if !test.has_marks {
+        report.outcome = match golden_for(&path) {
+            Some(Ok(golden)) if golden.flattened() == screen.flattened() => Outcome::Passed,
+            Some(Ok(_)) => Outcome::Failed,
+            Some(Err(e)) => Outcome::Crashed(e.to_string()),
+            None if screen.pixels.iter().flatten().any(|&planes| planes != 0) => Outcome::Passed,
+            None => Outcome::Unreadable,
+        };
+        return report;
+    }
+
+    let mut unknown = Vec::new();
+    report.results = results(read_screen(&screen, glyphs, &mut unknown));
+    report.unknown_glyphs = unknown.iter().map(|rows| format_glyph(rows)).collect();
+    report.outcome = if report.results.is_empty() {
+        Outcome::Unreadable
+    } else if report.results.iter().all(|result| result.passed) {
+        Outcome::Passed
+    } else {
+        Outcome::Failed
+    };
+    report
+}
+
+fn golden_for(rom: &Path) -> Option<Result<Frame, Chip8Error>> {
+    ["txt", "pbm"].iter()
+        .map(|extension| rom.with_extension(extension))
+        .find(|path| path.exists())
+        .map(|path| Frame::load(&path.to_string_lossy()))
+}
+
+impl fmt::Display for RomReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<7} {:<7} {}", self.test, self.preset, self.outcome)?;
+        if let Outcome::Crashed(e) = &self.outcome {
+            write!(f, ": {}", e)?;
+        }
+        writeln!(f)?;
+        for result in &self.results {
+            writeln!(f, "    {:<4} {}", if result.passed {"ok"} else {"FAIL"}, if result.label.is_empty() {"?"} else {&result.label})?;
+        }
+        if !self.unknown_glyphs.is_empty() {
+            writeln!(f, "    {} glyphs couldn't be read, --learn writes them out", self.unknown_glyphs.len())?;
+        }
+        Ok(())
+    }
+}
+
+// the whole run as one json object, reports grouped by test then preset
+pub fn report_json(reports: &[RomReport]) -> String {
+    let reports: Vec<Json> = reports.iter().map(|report| {
+        let results: Vec<Json> = report.results.iter()
+            .map(|result| Json::object(vec![("label", result.label.as_str().into()), ("passed", result.passed.into())]))
+            .collect();
+        let mut object = vec![
+            ("test", report.test.into()),
+            ("preset", report.preset.as_str().into()),
+            ("outcome", report.outcome.to_string().into()),
+            ("results", results.into()),
+            ("unknownGlyphs", report.unknown_glyphs.len().into()),
+        ];
+        if let Outcome::Crashed(e) = &report.outcome {
+            object.push(("error", e.as_str().into()));
+        }
+        Json::object(object)
+    }).collect();
+    let passed = reports.iter().all(|report| report.get("outcome").as_str() == Some("pass"));
+    Json::object(vec![("passed", passed.into()), ("reports", reports.into())]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyph(name: &str) -> Vec<Vec<bool>> {
+        let glyphs = GlyphSet::builtin();
+        glyphs.glyphs.iter().find(|glyph| glyph.name == name).unwrap().rows.clone()
+    }
+
+    // draws glyphs left to right from (x, y) a column apart, a " " leaves a word gap
+    fn write(frame: &mut Frame, x: usize, y: usize, names: &[&str]) {
+        let mut x = x;
+        for name in names {
+            if *name == " " {
+                x += 3;
+                continue;
+            }
+            let rows = glyph(name);
+            for (dy, row) in rows.iter().enumerate() {
+                for (dx, &lit) in row.iter().enumerate() {
+                    frame.pixels[y + dy][x + dx] = lit as BYTE;
+                }
+            }
+            x += rows[0].len() + 1;
+        }
+    }
+
+    fn blank() -> Frame {
+        Frame { width: 64, height: 32, pixels: vec![vec![0; 64]; 32] }
+    }
+
+    #[test]
+    fn builtin_glyphs_parse() {
+        let glyphs = GlyphSet::parse(BUILTIN_GLYPHS).unwrap();
+        let names: Vec<&str> = glyphs.glyphs.iter().map(|glyph| glyph.name.as_str()).collect();
+        for name in ["pass", "fail", "0", "9", "A", "Z", "-", ".", ":"] {
+            assert!(names.contains(&name), "no `{}` glyph", name);
+        }
+        // every letter has to read back as itself
+        for glyph in &glyphs.glyphs {
+            assert_eq!(glyphs.recognize(&glyph.rows), Some(glyph.name.as_str()));
+        }
+    }
+
+    #[test]
+    fn bad_glyph_files_are_errors() {
+        // rows before a name, a # line there would be a comment
+        assert!(GlyphSet::parse(".#\n").is_err());
+        assert!(GlyphSet::parse("glyph x\n#.\n#x\n").is_err());
+        assert!(GlyphSet::parse("glyph x\n#.\n###\n").is_err());
+    }
+
+    #[test]
+    fn recognize_allows_a_pixel_off() {
+        let glyphs = GlyphSet::builtin();
+        let mut rows = glyph("F");
+        rows[3][2] = true;
+        assert_eq!(glyphs.recognize(&rows), Some("F"));
+        rows[0][1] = false;
+        rows[2][2] = true;
+        assert_eq!(glyphs.recognize(&rows), None);
+        assert_eq!(glyphs.recognize(&vec![vec![true; 3]; 5]), None);
+
+        // learned glyphs win over built in ones
+        let mut merged = GlyphSet::builtin();
+        merged.merge(GlyphSet::parse(&format_glyph(&glyph("F")).replace('?', "f")).unwrap());
+        assert_eq!(merged.recognize(&glyph("F")), Some("f"));
+    }
+
+    #[test]
+    fn screen_is_read_into_labelled_results() {
+        let mut frame = blank();
+        write(&mut frame, 1, 1, &["8", "X", "Y", "4", " ", "pass"]);
+        write(&mut frame, 1, 8, &["F", "X", "5", "5", " ", "fail", " ", "F", "X", "6", "5", " ", "pass"]);
+        write(&mut frame, 1, 15, &["0", "0", "E", "E"]);
+        // a square isn't in the set
+        for y in 22..26 {
+            for x in 1..5 {
+                frame.pixels[y][x] = 1;
+            }
+        }
+
+        let mut unknown = Vec::new();
+        let lines = read_screen(&frame, &GlyphSet::builtin(), &mut unknown);
+        assert_eq!(lines, vec![
+            vec![Token::Word("8XY4".into()), Token::Mark(true)],
+            vec![Token::Word("FX55".into()), Token::Mark(false), Token::Word("FX65".into()), Token::Mark(true)],
+            vec![Token::Word("00EE".into())],
+            vec![Token::Word("?".into())],
+        ]);
+        assert_eq!(unknown, vec![vec![vec![true; 4]; 4]]);
+        assert_eq!(format_glyph(&unknown[0]), "glyph ?\n####\n####\n####\n####\n");
+
+        assert_eq!(results(lines), vec![
+            TestResult { label: "8XY4".into(), passed: true },
+            TestResult { label: "FX55".into(), passed: false },
+            TestResult { label: "FX65".into(), passed: true },
+        ]);
+    }
+
+    #[test]
+    fn words_split_on_wide_gaps() {
+        let mut frame = blank();
+        write(&mut frame, 0, 0, &["V", "F", " ", "0", "1", " ", "pass"]);
+        let lines = read_screen(&frame, &GlyphSet::builtin(), &mut Vec::new());
+        assert_eq!(results(lines), vec![TestResult { label: "VF 01".into(), passed: true }]);
+    }
+}
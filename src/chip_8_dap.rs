@@ -0,0 +1,1068 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chip_8_assembler;
+use crate::chip_8_cli::parse_hex;
+use crate::chip_8_debugger::{self, Debugger, StopReason, DEFAULT_STEP_LIMIT};
+use crate::chip_8_emulator::{Chip8Hardware, BYTE, MEMORY_SIZE, TIMER_HZ, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_instruction::Instruction;
+use crate::chip_8_json::Json;
+use crate::chip_8_octo;
+use crate::chip_8_quirks::Quirks;
+use crate::chip_8_source_map::SourceMap;
+
+// there's only ever the one thread
+const THREAD_ID: usize = 1;
+// variablesReference numbers for each scope
+const SCOPE_REGISTERS: usize = 1;
+const SCOPE_STACK: usize = 2;
+const SCOPE_MEMORY: usize = 3;
+const SCOPE_KEYPAD: usize = 4;
+const SCOPE_SCREEN: usize = 5;
+const MEMORY_SCOPE_BYTES: usize = 0x40;
+// line stepping gives up on a line that never ends after this many instructions
+const MAX_LINE_STEPS: usize = 100_000;
+// bigger messages are skipped unread, no real request is anywhere near this
+const MAX_MESSAGE_LENGTH: usize = 1 << 20;
+// the most a disassemble request gets back, enough to cover all of memory
+const MAX_DISASSEMBLE_COUNT: usize = MEMORY_SIZE / 2;
+const REGISTER_NAMES: [&str; 21] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "I", "PC", "SP", "DT", "ST",
+];
+
+// Debug Adapter Protocol server, what editors like vs code talk to for their debugging UI.
+// Messages come in on `input` and replies and events go out on `output`, normally stdin and
+// stdout of chip8-dap. A launch takes a rom, octo source (.8o) or assembler source (.asm), and
+// breakpoints can go on source lines when there's a source map, or on labels and addresses.
+// Running goes at the real speed of the rom so timers behave.
+pub fn serve<R: BufRead + Send + 'static, W: Write>(input: R, output: W) -> Result<(), Chip8Error> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || read_messages(input, sender));
+    let mut server = DapServer { output, sequence: 1, session: None };
+    server.run(receiver)
+}
+
+// messages are a Content-Length header, a blank line, then that many bytes of json
+fn read_messages<R: BufRead>(mut input: R, sender: Sender<Result<Json, String>>) {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match input.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("Content-Length") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let length = match length {
+            Some(length) if length <= MAX_MESSAGE_LENGTH => length,
+            Some(length) => {
+                if io::copy(&mut (&mut input).take(length as u64), &mut io::sink()).is_err() {
+                    return;
+                }
+                continue;
+            }
+            None => continue,
+        };
+        let mut body = vec![0; length];
+        if input.read_exact(&mut body).is_err() {
+            return;
+        }
+        let message = Json::parse(&String::from_utf8_lossy(&body));
+        if sender.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+struct Session {
+    chip_8: Chip8Hardware,
+    debugger: Debugger,
+    map: SourceMap,
+    // each kind of setBreakpoints request replaces its own set, the debugger gets all of them
+    source_breakpoints: HashMap<String, Vec<WORD>>,
+    function_breakpoints: Vec<WORD>,
+    instruction_breakpoints: Vec<WORD>,
+    stop_on_entry: bool,
+    running: bool,
+    // steps finish while handling the request, the stopped event goes out after the response
+    last_stop: Option<StopReason>,
+    // when the next 60hz frame is due while running
+    next_frame: Instant,
+}
+
+struct DapServer<W: Write> {
+    output: W,
+    sequence: usize,
+    session: Option<Session>,
+}
+
+impl<W: Write> DapServer<W> {
+    fn run(&mut self, receiver: Receiver<Result<Json, String>>) -> Result<(), Chip8Error> {
+        loop {
+            let message = match self.session.as_ref().filter(|session| session.running) {
+                Some(session) => {
+                    let wait = session.next_frame.saturating_duration_since(Instant::now());
+                    match receiver.recv_timeout(wait) {
+                        Ok(message) => Some(message),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                    }
+                }
+                None => match receiver.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                },
+            };
+            match message {
+                Some(Ok(message)) => {
+                    if !self.handle(&message)? {
+                        return Ok(());
+                    }
+                }
+                Some(Err(e)) => self.output_event("stderr", &format!("bad message: {}\n", e))?,
+                None => self.run_frame()?,
+            }
+        }
+    }
+
+    // one frame's worth of instructions while the program is running
+    fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        session.next_frame += Duration::from_secs(1) / TIMER_HZ;
+        let instructions = session.chip_8.get_instructions_per_frame();
+        match session.debugger.run(&mut session.chip_8, instructions) {
+            StopReason::StepLimit(_) => Ok(()),
+            reason => self.stopped(reason),
+        }
+    }
+
+    fn send(&mut self, mut message: Vec<(&str, Json)>) -> Result<(), Chip8Error> {
+        message.insert(0, ("seq", self.sequence.into()));
+        self.sequence += 1;
+        let body = Json::object(message).to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), Chip8Error> {
+        self.send(vec![("type", "event".into()), ("event", event.into()), ("body", body)])
+    }
+
+    fn output_event(&mut self, category: &str, text: &str) -> Result<(), Chip8Error> {
+        self.event("output", Json::object(vec![("category", category.into()), ("output", text.into())]))
+    }
+
+    fn stopped(&mut self, reason: StopReason) -> Result<(), Chip8Error> {
+        if let Some(session) = &mut self.session {
+            session.running = false;
+        }
+        let (reason, text) = match reason {
+            StopReason::Stepped | StopReason::StepLimit(_) => ("step", None),
+            StopReason::Breakpoint(_) | StopReason::OpcodeBreakpoint { .. } => ("breakpoint", None),
+            StopReason::Watchpoint(_) => ("data breakpoint", None),
+            StopReason::Halted => {
+                self.event("exited", Json::object(vec![("exitCode", 0usize.into())]))?;
+                return self.event("terminated", Json::object(vec![]));
+            }
+            StopReason::Error(e) => {
+                self.output_event("stderr", &format!("emulation stopped: {}\n", e))?;
+                ("exception", Some(e.to_string()))
+            }
+        };
+        let mut body = vec![("reason", reason.into()), ("threadId", THREAD_ID.into()), ("allThreadsStopped", true.into())];
+        if let Some(text) = text {
+            body.push(("text", text.into()));
+        }
+        self.event("stopped", Json::object(body))
+    }
+
+    // false once the client has disconnected
+    fn handle(&mut self, message: &Json) -> Result<bool, Chip8Error> {
+        if message.get("type").as_str() != Some("request") {
+            return Ok(true);
+        }
+        let command = message.get("command").as_str().unwrap_or("").to_string();
+        let arguments = message.get("arguments");
+        let result = match command.as_str() {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(arguments),
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => match &mut self.session {
+                Some(session) => session.handle(&command, arguments),
+                None => Err("nothing has been launched yet".to_string()),
+            },
+        };
+
+        let mut response = vec![
+            ("type", "response".into()),
+            ("request_seq", Json::from(message.get("seq").as_u64().unwrap_or(0) as usize)),
+            ("command", command.as_str().into()),
+        ];
+        match result {
+            Ok(body) => {
+                response.push(("success", true.into()));
+                if body != Json::Null {
+                    response.push(("body", body));
+                }
+            }
+            Err(e) => {
+                response.push(("success", false.into()));
+                response.push(("message", e.into()));
+            }
+        }
+        self.send(response)?;
+
+        match command.as_str() {
+            "initialize" => (),
+            "launch" if self.session.is_some() => self.event("initialized", Json::object(vec![]))?,
+            "configurationDone" => {
+                let stop_on_entry = self.session.as_ref().is_some_and(|session| session.stop_on_entry);
+                if stop_on_entry {
+                    self.event("stopped", Json::object(vec![("reason", "entry".into()), ("threadId", THREAD_ID.into())]))?;
+                } else if let Some(session) = &mut self.session {
+                    // no step first here, a breakpoint on the very first instruction should stop
+                    session.running = true;
+                    session.next_frame = Instant::now();
+                }
+            }
+            "continue" => self.resume()?,
+            "pause" => {
+                self.event("stopped", Json::object(vec![("reason", "pause".into()), ("threadId", THREAD_ID.into())]))?;
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let reason = self.session.as_mut().and_then(|session| session.last_stop.take());
+                if let Some(reason) = reason {
+                    self.stopped(reason)?;
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.event("terminated", Json::object(vec![]))?;
+                return Ok(false);
+            }
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    // continue, the first instruction runs on its own so continuing off a breakpoint works
+    fn resume(&mut self) -> Result<(), Chip8Error> {
+        let session = match &mut self.session {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        match session.debugger.step(&mut session.chip_8) {
+            StopReason::Stepped => {
+                session.running = true;
+                session.next_frame = Instant::now();
+                Ok(())
+            }
+            reason => self.stopped(reason),
+        }
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        let program = arguments.get("program").as_str().ok_or("launch needs a `program`")?;
+        let quirks = match arguments.get("platform").as_str() {
+            Some(name) => Quirks::from_name(name).ok_or_else(|| format!("unknown platform `{}`", name))?,
+            None => Quirks::default(),
+        };
+
+        let mut chip_8 = Chip8Hardware::with_quirks(quirks);
+        let map = load_with_map(&mut chip_8, program, arguments.get("sourceMap").as_str()).map_err(|e| e.to_string())?;
+        self.session = Some(Session {
+            chip_8,
+            debugger: Debugger::new(),
+            map,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            stop_on_entry: arguments.get("stopOnEntry").as_bool().unwrap_or(false),
+            running: false,
+            last_stop: None,
+            next_frame: Instant::now(),
+        });
+        Ok(Json::Null)
+    }
+}
+
+// Chip8Hardware::load_program, but source compiles with a map of where each instruction came
+// from, and a built rom can bring one along
+fn load_with_map(chip_8: &mut Chip8Hardware, program: &str, map_path: Option<&str>) -> Result<SourceMap, Chip8Error> {
+    let map = if program.ends_with(".8o") {
+        let (rom, map) = chip_8_octo::compile_file_with_map(program)?;
+        chip_8.cpu_reset();
+        chip_8.load_rom(&rom)?;
+        map
+    } else if program.ends_with(".asm") {
+        let assembly = chip_8_assembler::assemble_file(program, 0x200)?;
+        chip_8.cpu_reset();
+        chip_8.load_rom(&assembly.rom)?;
+        assembly.source_map()
+    } else {
+        chip_8.load_program(program)?;
+        // a built rom can bring its map along as rom.map
+        let default_path = Path::new(program).with_extension("map");
+        match map_path {
+            Some(path) => SourceMap::load(path)?,
+            None if default_path.exists() => SourceMap::load(&default_path.to_string_lossy())?,
+            None => SourceMap::new(),
+        }
+    };
+    Ok(map)
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsFunctionBreakpoints", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsDisassembleRequest", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsWriteMemoryRequest", true.into()),
+        ("supportsSetVariable", true.into()),
+        ("supportsSteppingGranularity", true.into()),
+        ("supportsEvaluateForHovers", true.into()),
+        ("supportsTerminateRequest", true.into()),
+    ])
+}
+
+impl Session {
+    fn handle(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        match command {
+            "configurationDone" | "continue" => Ok(Json::object(vec![("allThreadsContinued", true.into())])),
+            "pause" => {
+                self.running = false;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object(vec![(
+                "threads",
+                vec![Json::object(vec![("id", THREAD_ID.into()), ("name", "chip-8".into())])].into(),
+            )])),
+            "setBreakpoints" => self.set_source_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(Json::object(vec![("breakpoints", Json::Array(Vec::new()))])),
+            "next" | "stepIn" | "stepOut" => {
+                self.running = false;
+                let by_line = arguments.get("granularity").as_str() != Some("instruction");
+                self.last_stop = Some(self.step(command, by_line));
+                Ok(Json::Null)
+            }
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "writeMemory" => self.write_memory(arguments),
+            "disassemble" => self.disassemble(arguments),
+            _ => Err(format!("`{}` isn't supported", command)),
+        }
+    }
+
+    fn step(&mut self, command: &str, by_line: bool) -> StopReason {
+        let start_line = self.map.line_at(self.chip_8.get_program_counter()).cloned();
+        let mut steps = 0;
+        loop {
+            let reason = match command {
+                "next" => self.debugger.next(&mut self.chip_8, DEFAULT_STEP_LIMIT),
+                "stepOut" => return self.debugger.finish(&mut self.chip_8, DEFAULT_STEP_LIMIT),
+                _ => self.debugger.step(&mut self.chip_8),
+            };
+            steps += 1;
+            // a line like octo's `if v0 < 5 then` is more than one instruction, keep going until it's done
+            let line = self.map.line_at(self.chip_8.get_program_counter());
+            let same_line = match (&start_line, line) {
+                (Some(start), Some(line)) => start.file == line.file && start.line == line.line,
+                _ => false,
+            };
+            if !by_line || !same_line || steps >= MAX_LINE_STEPS || !matches!(reason, StopReason::Stepped) {
+                return reason;
+            }
+        }
+    }
+
+    fn sync_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        let addresses = self.source_breakpoints.values().flatten()
+            .chain(self.function_breakpoints.iter())
+            .chain(self.instruction_breakpoints.iter());
+        for &address in addresses {
+            self.debugger.add_breakpoint(address);
+        }
+    }
+
+    fn set_source_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let path = arguments.get("source").get("path").as_str().ok_or("setBreakpoints needs a source path")?.to_string();
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        for breakpoint in arguments.get("breakpoints").as_array() {
+            let line = breakpoint.get("line").as_u64().unwrap_or(0) as usize;
+            match self.map.address_of_line(|file| same_file(file, &path), line) {
+                Some(mapped) => {
+                    addresses.push(mapped.address);
+                    results.push(Json::object(vec![
+                        ("verified", true.into()),
+                        ("line", mapped.line.into()),
+                        ("instructionReference", format!("0x{:03X}", mapped.address).into()),
+                    ]));
+                }
+                None => results.push(Json::object(vec![
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "no code for this line in the source map".into()),
+                ])),
+            }
+        }
+        self.source_breakpoints.insert(path, addresses);
+        self.sync_breakpoints();
+        Ok(Json::object(vec![("breakpoints", results.into())]))
+    }
+
+    // function breakpoints are labels, or plain hex addresses
+    fn set_function_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let mut results = Vec::new();
+        self.function_breakpoints.clear();
+        for breakpoint in arguments.get("breakpoints").as_array() {
+            let name = breakpoint.get("name").as_str().unwrap_or("");
+            match self.resolve_address(name) {
+                Some(address) => {
+                    self.function_breakpoints.push(address);
+                    results.push(self.verified_breakpoint(address));
+                }
+                None => results.push(Json::object(vec![
+                    ("verified", false.into()),
+                    ("message", format!("`{}` isn't a label or hex address", name).into()),
+                ])),
+            }
+        }
+        self.sync_breakpoints();
+        Ok(Json::object(vec![("breakpoints", results.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let mut results = Vec::new();
+        self.instruction_breakpoints.clear();
+        for breakpoint in arguments.get("breakpoints").as_array() {
+            let reference = breakpoint.get("instructionReference").as_str().unwrap_or("");
+            let offset = breakpoint.get("offset").as_i64().unwrap_or(0);
+            match parse_address(reference) {
+                Some(address) => {
+                    let address = (address as i64 + offset) as WORD;
+                    self.instruction_breakpoints.push(address);
+                    results.push(self.verified_breakpoint(address));
+                }
+                None => results.push(Json::object(vec![("verified", false.into())])),
+            }
+        }
+        self.sync_breakpoints();
+        Ok(Json::object(vec![("breakpoints", results.into())]))
+    }
+
+    fn verified_breakpoint(&self, address: WORD) -> Json {
+        let mut breakpoint = vec![
+            ("verified", true.into()),
+            ("instructionReference", format!("0x{:03X}", address).into()),
+        ];
+        if let Some(mapped) = self.map.line_at(address) {
+            breakpoint.push(("source", source_json(&mapped.file)));
+            breakpoint.push(("line", mapped.line.into()));
+        }
+        Json::object(breakpoint)
+    }
+
+    fn resolve_address(&self, name: &str) -> Option<WORD> {
+        match self.map.labels().get(name) {
+            Some(&address) => Some(address),
+            None => parse_address(name),
+        }
+    }
+
+    // the program counter first, then every call site on the stack, innermost first
+    fn stack_trace(&self) -> Json {
+        let mut addresses = vec![self.chip_8.get_program_counter()];
+        addresses.extend(self.chip_8.get_stack().iter().rev().map(|address| address.wrapping_sub(2)));
+        let frames: Vec<Json> = addresses.iter().enumerate().map(|(id, &address)| {
+            let name = match self.map.label_before(address) {
+                Some((label, start)) if start == address => label.to_string(),
+                Some((label, start)) => format!("{}+0x{:X}", label, address - start),
+                None => format!("0x{:03X}", address),
+            };
+            let mut frame = vec![
+                ("id", id.into()),
+                ("name", name.into()),
+                ("instructionPointerReference", format!("0x{:03X}", address).into()),
+                ("column", 1usize.into()),
+            ];
+            match self.map.line_at(address) {
+                Some(mapped) => {
+                    frame.push(("source", source_json(&mapped.file)));
+                    frame.push(("line", mapped.line.into()));
+                }
+                None => frame.push(("line", 0usize.into())),
+            }
+            Json::object(frame)
+        }).collect();
+        Json::object(vec![("totalFrames", frames.len().into()), ("stackFrames", frames.into())])
+    }
+
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let reference = arguments.get("variablesReference").as_u64().unwrap_or(0) as usize;
+        let mut variables = Vec::new();
+        match reference {
+            SCOPE_REGISTERS => {
+                for name in REGISTER_NAMES.iter() {
+                    let value = self.register(name).unwrap_or(0);
+                    let mut variable = vec![("name", (*name).into()), ("value", format_register(name, value).into()), ("variablesReference", 0usize.into())];
+                    if *name == "I" || *name == "PC" {
+                        variable.push(("memoryReference", format!("0x{:03X}", value).into()));
+                    }
+                    variables.push(Json::object(variable));
+                }
+            }
+            SCOPE_STACK => {
+                for (depth, address) in self.chip_8.get_stack().iter().rev().enumerate() {
+                    variables.push(variable(&format!("#{}", depth), &format!("0x{:03X}", address)));
+                }
+            }
+            SCOPE_MEMORY => {
+                let start = self.chip_8.get_address_i() as usize & !0xF;
+                for row in chip_8_debugger::format_memory(&self.chip_8, start, MEMORY_SCOPE_BYTES).lines() {
+                    let (address, bytes) = row.split_once(": ").unwrap_or((row, ""));
+                    variables.push(variable(&format!("0x{}", address), bytes));
+                }
+            }
+            SCOPE_KEYPAD => {
+                for key in 0..16 {
+                    variables.push(variable(&format!("{:X}", key), if self.chip_8.keyboard[key] {"down"} else {"up"}));
+                }
+            }
+            SCOPE_SCREEN => {
+                for y in 0..self.chip_8.get_screen_height() as u16 {
+                    let row: String = (0..self.chip_8.get_screen_width() as u16)
+                        .map(|x| if self.chip_8.get_pixel_value_x_y(y, x) {'#'} else {'.'})
+                        .collect();
+                    variables.push(variable(&format!("{:02}", y), &row));
+                }
+            }
+            _ => return Err(format!("no variables with reference {}", reference)),
+        }
+        Ok(Json::object(vec![("variables", variables.into())]))
+    }
+
+    fn register(&self, name: &str) -> Option<WORD> {
+        let value = match name.to_uppercase().as_str() {
+            "I" => self.chip_8.get_address_i(),
+            "PC" => self.chip_8.get_program_counter(),
+            "SP" => self.chip_8.get_stack().len() as WORD,
+            "DT" => self.chip_8.get_delay_timer() as WORD,
+            "ST" => self.chip_8.get_sound_timer() as WORD,
+            name => {
+                let index = name.strip_prefix('V').filter(|digit| digit.len() == 1).and_then(|digit| u8::from_str_radix(digit, 16).ok())?;
+                self.chip_8.get_registers()[index as usize] as WORD
+            }
+        };
+        Some(value)
+    }
+
+    fn set_variable(&mut self, arguments: &Json) -> Result<Json, String> {
+        let reference = arguments.get("variablesReference").as_u64().unwrap_or(0) as usize;
+        let name = arguments.get("name").as_str().unwrap_or("").to_uppercase();
+        let text = arguments.get("value").as_str().unwrap_or("").trim().to_lowercase();
+        match reference {
+            SCOPE_REGISTERS => {
+                let value = parse_number(&text).ok_or_else(|| format!("`{}` isn't a number", text))?;
+                let limit = if name == "I" || name == "PC" {0xFFFF} else {0xFF};
+                if value > limit {
+                    return Err(format!("0x{:X} doesn't fit in {}", value, name));
+                }
+                match name.as_str() {
+                    "I" => self.chip_8.set_address_i(value as WORD),
+                    "PC" => self.chip_8.set_program_counter(value as WORD),
+                    "DT" => self.chip_8.set_delay_timer(value as BYTE),
+                    "ST" => self.chip_8.set_sound_timer(value as BYTE),
+                    "SP" => return Err("the stack pointer only moves with calls and returns".to_string()),
+                    _ => {
+                        let index = name.strip_prefix('V').and_then(|digit| WORD::from_str_radix(digit, 16).ok()).filter(|index| *index < 16);
+                        let index = index.ok_or_else(|| format!("unknown register `{}`", name))?;
+                        self.chip_8.set_register_value(index, value as WORD);
+                    }
+                }
+                let value = self.register(&name).unwrap_or(0);
+                Ok(Json::object(vec![("value", format_register(&name, value).into())]))
+            }
+            SCOPE_KEYPAD => {
+                let key = usize::from_str_radix(&name, 16).ok().filter(|key| *key < 16).ok_or_else(|| format!("unknown key `{}`", name))?;
+                self.chip_8.keyboard[key] = match text.as_str() {
+                    "down" | "1" | "true" => true,
+                    "up" | "0" | "false" => false,
+                    _ => return Err("a key is down or up".to_string()),
+                };
+                Ok(Json::object(vec![("value", (if self.chip_8.keyboard[key] {"down"} else {"up"}).into())]))
+            }
+            _ => Err("only registers and keys can be changed".to_string()),
+        }
+    }
+
+    // registers and labels, which is also what hovering over a name in the source shows
+    fn evaluate(&self, arguments: &Json) -> Result<Json, String> {
+        let expression = arguments.get("expression").as_str().unwrap_or("").trim();
+        let (result, reference) = match (self.register(expression), self.map.labels().get(expression)) {
+            (Some(value), _) => (format_register(&expression.to_uppercase(), value), None),
+            (None, Some(&address)) => (format!("0x{:03X}", address), Some(address)),
+            (None, None) => return Err(format!("can't evaluate `{}`", expression)),
+        };
+        let mut body = vec![("result", result.into()), ("variablesReference", 0usize.into())];
+        if let Some(address) = reference {
+            body.push(("memoryReference", format!("0x{:03X}", address).into()));
+        }
+        Ok(Json::object(body))
+    }
+
+    fn read_memory(&self, arguments: &Json) -> Result<Json, String> {
+        let start = memory_address(arguments)?;
+        let count = arguments.get("count").as_u64().unwrap_or(0).min(MEMORY_SIZE as u64) as usize;
+        let memory = self.chip_8.get_memory();
+        let start = start.min(memory.len());
+        let end = start.saturating_add(count).min(memory.len());
+        Ok(Json::object(vec![
+            ("address", format!("0x{:X}", start).into()),
+            ("data", base64_encode(&memory[start..end]).into()),
+            ("unreadableBytes", (count - (end - start)).into()),
+        ]))
+    }
+
+    fn write_memory(&mut self, arguments: &Json) -> Result<Json, String> {
+        let start = memory_address(arguments)?;
+        let data = base64_decode(arguments.get("data").as_str().unwrap_or("")).ok_or("data isn't base64")?;
+        if start.saturating_add(data.len()) > self.chip_8.get_memory().len() {
+            return Err("that runs past the end of memory".to_string());
+        }
+        for (offset, byte) in data.iter().enumerate() {
+            self.chip_8.write_memory(start + offset, *byte);
+        }
+        Ok(Json::object(vec![("bytesWritten", data.len().into())]))
+    }
+
+    // straight line from the address, instructions are 2 bytes except the 4 byte F000
+    fn disassemble(&self, arguments: &Json) -> Result<Json, String> {
+        let address = memory_address(arguments)? as i64;
+        let start = arguments.get("instructionOffset").as_i64().unwrap_or(0).checked_mul(2)
+            .and_then(|offset| offset.checked_add(address))
+            .ok_or("instructionOffset is out of range")?;
+        let count = arguments.get("instructionCount").as_u64().unwrap_or(0).min(MAX_DISASSEMBLE_COUNT as u64) as usize;
+        let memory = self.chip_8.get_memory();
+        let mut address = start;
+        let mut instructions = Vec::with_capacity(count);
+        for _ in 0..count {
+            let decoded = if address >= 0 {Instruction::decode_at(memory, address as usize)} else {None};
+            let mut line = vec![("address", format!("0x{:03X}", address.max(0)).into())];
+            match decoded {
+                Some(instruction) => {
+                    let bytes = &memory[address as usize..address as usize + instruction.size()];
+                    line.push(("instructionBytes", bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ").into()));
+                    line.push(("instruction", instruction.to_string().into()));
+                    if let Some(mapped) = self.map.line_at(address as WORD) {
+                        line.push(("location", source_json(&mapped.file)));
+                        line.push(("line", mapped.line.into()));
+                    }
+                    if let Some((label, label_address)) = self.map.label_before(address as WORD) {
+                        if label_address as i64 == address {
+                            line.push(("symbol", label.into()));
+                        }
+                    }
+                    address += instruction.size() as i64;
+                }
+                None => {
+                    line.push(("instruction", "??".into()));
+                    line.push(("presentationHint", "invalid".into()));
+                    address += 2;
+                }
+            }
+            instructions.push(Json::object(line));
+        }
+        Ok(Json::object(vec![("instructions", instructions.into())]))
+    }
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: usize, expensive: bool| Json::object(vec![
+        ("name", name.into()),
+        ("variablesReference", reference.into()),
+        ("expensive", expensive.into()),
+    ]);
+    Json::object(vec![("scopes", vec![
+        scope("Registers", SCOPE_REGISTERS, false),
+        scope("Stack", SCOPE_STACK, false),
+        scope("Memory at I", SCOPE_MEMORY, false),
+        scope("Keypad", SCOPE_KEYPAD, false),
+        scope("Screen", SCOPE_SCREEN, true),
+    ].into())])
+}
+
+fn variable(name: &str, value: &str) -> Json {
+    Json::object(vec![("name", name.into()), ("value", value.into()), ("variablesReference", 0usize.into())])
+}
+
+fn format_register(name: &str, value: WORD) -> String {
+    match name {
+        "I" | "PC" => format!("0x{:03X}", value),
+        "SP" => value.to_string(),
+        _ => format!("0x{:02X}", value),
+    }
+}
+
+fn source_json(path: &str) -> Json {
+    let name = Path::new(path).file_name().map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+    Json::object(vec![("name", name.into()), ("path", path.into())])
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn parse_address(text: &str) -> Option<WORD> {
+    parse_hex(text).filter(|address| *address <= 0xFFFF).map(|address| address as WORD)
+}
+
+// registers can be set in hex with 0x or in decimal
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(digits) => u32::from_str_radix(digits, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn memory_address(arguments: &Json) -> Result<usize, String> {
+    let reference = arguments.get("memoryReference").as_str().unwrap_or("");
+    let base = parse_address(reference).ok_or_else(|| format!("`{}` isn't a memory reference", reference))?;
+    let address = (base as i64).checked_add(arguments.get("offset").as_i64().unwrap_or(0)).ok_or("offset is out of range")?;
+    if address < 0 {
+        return Err("that's before the start of memory".to_string());
+    }
+    Ok(address as usize)
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64_ALPHABET[(group >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut group = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|&c| c != b'=' && !c.is_ascii_whitespace()) {
+        let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+        group = group << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+            group &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::path::PathBuf;
+
+    // both ends of a loopback connection, the client's side first
+    fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = std::env::temp_dir().join(format!("chip_8_dap_{}_{}", name, std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        fn write(&self, name: &str, contents: &[u8]) -> String {
+            let path = self.0.join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // the editor's side, events that turn up while waiting for a response are kept for later
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+        sequence: usize,
+        events: Vec<Json>,
+    }
+
+    impl Client {
+        fn start() -> Client {
+            let (client, server) = connected();
+            let input = BufReader::new(server.try_clone().unwrap());
+            thread::spawn(move || serve(input, server));
+            client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let reader = BufReader::new(client.try_clone().unwrap());
+            Client { stream: client, reader, sequence: 1, events: Vec::new() }
+        }
+
+        fn send_raw(&mut self, body: &str) {
+            write!(self.stream, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+
+        fn receive(&mut self) -> Json {
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                self.reader.read_line(&mut header).unwrap();
+                let header = header.trim();
+                if header.is_empty() {
+                    break;
+                }
+                length = header.trim_start_matches("Content-Length:").trim().parse().unwrap();
+            }
+            let mut body = vec![0; length];
+            self.reader.read_exact(&mut body).unwrap();
+            Json::parse(&String::from_utf8(body).unwrap()).unwrap()
+        }
+
+        fn request(&mut self, command: &str, arguments: Json) -> Json {
+            let seq = self.sequence;
+            self.sequence += 1;
+            let message = Json::object(vec![
+                ("seq", seq.into()),
+                ("type", "request".into()),
+                ("command", command.into()),
+                ("arguments", arguments),
+            ]);
+            self.send_raw(&message.to_string());
+            loop {
+                let message = self.receive();
+                if message.get("type").as_str() == Some("response") && message.get("request_seq").as_u64() == Some(seq as u64) {
+                    assert_eq!(message.get("command").as_str(), Some(command));
+                    return message;
+                }
+                self.events.push(message);
+            }
+        }
+
+        fn body(&mut self, command: &str, arguments: Json) -> Json {
+            let response = self.request(command, arguments);
+            assert_eq!(response.get("success").as_bool(), Some(true), "{} failed: {}", command, response);
+            response.get("body").clone()
+        }
+
+        fn error(&mut self, command: &str, arguments: Json) -> String {
+            let response = self.request(command, arguments);
+            assert_eq!(response.get("success").as_bool(), Some(false), "{} worked: {}", command, response);
+            response.get("message").as_str().unwrap().to_string()
+        }
+
+        fn event(&mut self, name: &str) -> Json {
+            if let Some(index) = self.events.iter().position(|event| event.get("event").as_str() == Some(name)) {
+                return self.events.remove(index).get("body").clone();
+            }
+            loop {
+                let message = self.receive();
+                if message.get("type").as_str() == Some("event") && message.get("event").as_str() == Some(name) {
+                    return message.get("body").clone();
+                }
+                self.events.push(message);
+            }
+        }
+
+        fn launch(&mut self, program: &str) {
+            self.body("initialize", Json::object(vec![("adapterID", "chip-8".into())]));
+            self.body("launch", Json::object(vec![("program", program.into())]));
+            self.event("initialized");
+        }
+    }
+
+    impl Drop for Client {
+        fn drop(&mut self) {
+            let _ = self.stream.shutdown(Shutdown::Write);
+        }
+    }
+
+    #[test]
+    fn breakpoints_on_source_lines_stop_the_program() {
+        let scratch = Scratch::new("breakpoints");
+        let program = scratch.write("spin.8o", b": main\n  v0 := 1\n  v1 := 2\n  # nothing here\n: spin\n  jump spin\n");
+        let mut client = Client::start();
+        client.launch(&program);
+
+        let source = Json::object(vec![("path", program.as_str().into())]);
+        let body = client.body("setBreakpoints", Json::object(vec![
+            ("source", source.clone()),
+            ("breakpoints", vec![Json::object(vec![("line", 3usize.into())])].into()),
+        ]));
+        let breakpoint = &body.get("breakpoints").as_array()[0];
+        assert_eq!(breakpoint.get("verified").as_bool(), Some(true));
+        assert_eq!(breakpoint.get("line").as_u64(), Some(3));
+
+        // a line without code moves down to the next one that has some
+        let body = client.body("setBreakpoints", Json::object(vec![
+            ("source", source),
+            ("breakpoints", vec![
+                Json::object(vec![("line", 3usize.into())]),
+                Json::object(vec![("line", 4usize.into())]),
+                Json::object(vec![("line", 99usize.into())]),
+            ].into()),
+        ]));
+        let breakpoints = body.get("breakpoints").as_array();
+        assert_eq!(breakpoints[1].get("line").as_u64(), Some(6));
+        assert_eq!(breakpoints[2].get("verified").as_bool(), Some(false));
+
+        client.body("configurationDone", Json::Null);
+        let stopped = client.event("stopped");
+        assert_eq!(stopped.get("reason").as_str(), Some("breakpoint"));
+        assert_eq!(stopped.get("threadId").as_u64(), Some(THREAD_ID as u64));
+
+        let trace = client.body("stackTrace", Json::object(vec![("threadId", THREAD_ID.into())]));
+        let frame = &trace.get("stackFrames").as_array()[0];
+        assert_eq!(frame.get("line").as_u64(), Some(3));
+        assert_eq!(frame.get("name").as_str(), Some("main+0x2"));
+
+        // continuing goes off the breakpoint and round to the next one
+        client.body("continue", Json::object(vec![("threadId", THREAD_ID.into())]));
+        assert_eq!(client.event("stopped").get("reason").as_str(), Some("breakpoint"));
+        let trace = client.body("stackTrace", Json::object(vec![("threadId", THREAD_ID.into())]));
+        assert_eq!(trace.get("stackFrames").as_array()[0].get("line").as_u64(), Some(6));
+    }
+
+    #[test]
+    fn read_memory_and_disassemble_stay_in_bounds() {
+        let scratch = Scratch::new("bounds");
+        let program = scratch.write("rom.ch8", &[0x60, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x12, 0x00]);
+        let mut client = Client::start();
+        client.launch(&program);
+
+        let body = client.body("readMemory", Json::object(vec![("memoryReference", "0x200".into()), ("count", 4usize.into())]));
+        assert_eq!(body.get("address").as_str(), Some("0x200"));
+        assert_eq!(base64_decode(body.get("data").as_str().unwrap()), Some(vec![0x60, 0x01, 0xF0, 0x00]));
+        assert_eq!(body.get("unreadableBytes").as_u64(), Some(0));
+
+        // reading off the end gives what's there and counts the rest as unreadable
+        let last = MEMORY_SIZE - 2;
+        let body = client.body("readMemory", Json::object(vec![
+            ("memoryReference", format!("0x{:X}", last).into()),
+            ("count", 1_000_000_000_000usize.into()),
+        ]));
+        assert_eq!(base64_decode(body.get("data").as_str().unwrap()).unwrap().len(), 2);
+        assert_eq!(body.get("unreadableBytes").as_u64(), Some((MEMORY_SIZE - 2) as u64));
+        let body = client.body("readMemory", Json::object(vec![
+            ("memoryReference", "0x200".into()),
+            ("offset", Json::from(MEMORY_SIZE as i64)),
+            ("count", 4usize.into()),
+        ]));
+        assert_eq!(body.get("data").as_str(), Some(""));
+        assert_eq!(body.get("unreadableBytes").as_u64(), Some(4));
+
+        let message = client.error("readMemory", Json::object(vec![("memoryReference", "0x200".into()), ("offset", Json::from(-0x201i64))]));
+        assert_eq!(message, "that's before the start of memory");
+        let message = client.error("readMemory", Json::object(vec![("memoryReference", "main".into()), ("count", 1usize.into())]));
+        assert_eq!(message, "`main` isn't a memory reference");
+        let message = client.error("readMemory", Json::object(vec![("memoryReference", "0x200".into()), ("offset", Json::from(i64::MAX))]));
+        assert_eq!(message, "offset is out of range");
+
+        let body = client.body("disassemble", Json::object(vec![("memoryReference", "0x200".into()), ("instructionCount", 3usize.into())]));
+        let instructions = body.get("instructions").as_array();
+        let addresses: Vec<_> = instructions.iter().map(|line| line.get("address").as_str().unwrap()).collect();
+        // F000 takes four bytes
+        assert_eq!(addresses, ["0x200", "0x202", "0x206"]);
+        assert_eq!(instructions[1].get("instructionBytes").as_str(), Some("F0 00 12 34"));
+
+        // before the start of memory is filler, and a huge count is cut down
+        let body = client.body("disassemble", Json::object(vec![
+            ("memoryReference", "0x0".into()),
+            ("instructionOffset", Json::from(-2i64)),
+            ("instructionCount", 1_000_000_000_000_000usize.into()),
+        ]));
+        let instructions = body.get("instructions").as_array();
+        assert_eq!(instructions.len(), MAX_DISASSEMBLE_COUNT);
+        assert_eq!(instructions[0].get("presentationHint").as_str(), Some("invalid"));
+        assert_eq!(instructions[2].get("address").as_str(), Some("0x000"));
+        let body = client.body("disassemble", Json::object(vec![
+            ("memoryReference", format!("0x{:X}", MEMORY_SIZE - 2).into()),
+            ("instructionCount", 4usize.into()),
+        ]));
+        assert_eq!(body.get("instructions").as_array()[3].get("instruction").as_str(), Some("??"));
+
+        let message = client.error("disassemble", Json::object(vec![
+            ("memoryReference", "0x200".into()),
+            ("instructionOffset", Json::from(i64::MAX)),
+            ("instructionCount", 1usize.into()),
+        ]));
+        assert_eq!(message, "instructionOffset is out of range");
+    }
+
+    #[test]
+    fn bad_messages_are_reported_and_skipped() {
+        let mut client = Client::start();
+        assert_eq!(client.error("threads", Json::Null), "nothing has been launched yet");
+
+        // too long to read is skipped without reading it as json
+        let huge = " ".repeat(MAX_MESSAGE_LENGTH + 1);
+        client.send_raw(&huge);
+        client.send_raw("{\"seq\": 1,");
+        let output = client.event("output");
+        assert_eq!(output.get("category").as_str(), Some("stderr"));
+        assert!(output.get("output").as_str().unwrap().starts_with("bad message: "));
+
+        let body = client.body("initialize", Json::Null);
+        assert_eq!(body.get("supportsDisassembleRequest").as_bool(), Some(true));
+        assert!(client.error("launch", Json::Null).contains("program"));
+    }
+}
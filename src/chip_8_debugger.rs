@@ -0,0 +1,535 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::chip_8_emulator::{Chip8Hardware, BYTE, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_instruction::Instruction;
+use crate::chip_8_observer::{Access, Location, Observer};
+
+// continue gives up after this many instructions so a rom stuck waiting on a key doesn't hang the debugger
+pub const DEFAULT_STEP_LIMIT: usize = 10_000_000;
+
+// An opcode with wildcards like `D??5` or `Fx55`, hex digits have to match and anything else
+// matches any nibble.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodePattern {
+    value: WORD,
+    mask: WORD,
+}
+
+impl OpcodePattern {
+    pub fn parse(text: &str) -> Option<OpcodePattern> {
+        if text.chars().count() != 4 {
+            return None;
+        }
+        let mut pattern = OpcodePattern { value: 0, mask: 0 };
+        for c in text.chars() {
+            pattern.value <<= 4;
+            pattern.mask <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                pattern.value |= digit as WORD;
+                pattern.mask |= 0xF;
+            } else if !c.is_ascii_alphabetic() && c != '?' && c != '_' {
+                return None;
+            }
+        }
+        Some(pattern)
+    }
+
+    pub fn matches(&self, opcode: WORD) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+// Stops after the instruction that touched `location`. Writes only count when they change the
+// value, so `V5 changed` is a write watch on V5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub location: Location,
+    pub kind: WatchKind,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::ReadWrite => "read/write",
+        };
+        write!(f, "{} {}", self.location, kind)
+    }
+}
+
+// the first watchpoint an instruction hit, `address` is the instruction that did it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub address: WORD,
+    pub location: Location,
+    pub access: Access,
+    pub old: WORD,
+    pub new: WORD,
+}
+
+// shared between the Debugger and the observer it puts on the chip while running
+#[derive(Default)]
+struct WatchState {
+    watchpoints: Vec<Watchpoint>,
+    hit: Option<WatchHit>,
+}
+
+struct WatchObserver {
+    state: Rc<RefCell<WatchState>>,
+}
+
+impl Observer for WatchObserver {
+    fn access(&mut self, pc: WORD, location: Location, access: Access, old: WORD, new: WORD) {
+        let mut state = self.state.borrow_mut();
+        if state.hit.is_some() || (access == Access::Write && old == new) {
+            return;
+        }
+        let hit = state.watchpoints.iter().any(|w| w.location == location && w.kind.matches(access));
+        if hit {
+            state.hit = Some(WatchHit { address: pc, location, access, old, new });
+        }
+    }
+}
+
+// why running stopped, the program counter is always at the next instruction to run
+#[derive(Debug)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(WORD),
+    OpcodeBreakpoint { address: WORD, opcode: WORD },
+    Watchpoint(WatchHit),
+    Halted,
+    StepLimit(usize),
+    Error(Chip8Error),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Stepped => write!(f, "stepped"),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at {:03X}", address),
+            StopReason::OpcodeBreakpoint { address, opcode } => write!(f, "opcode breakpoint, {:04X} at {:03X}", opcode, address),
+            StopReason::Watchpoint(hit) => match hit.access {
+                Access::Read => write!(f, "watchpoint, {} read as {:02X} by {:03X}", hit.location, hit.new, hit.address),
+                Access::Write => write!(f, "watchpoint, {} changed {:02X} -> {:02X} by {:03X}", hit.location, hit.old, hit.new, hit.address),
+            },
+            StopReason::Halted => write!(f, "the program exited"),
+            StopReason::StepLimit(steps) => write!(f, "still running after {} instructions", steps),
+            StopReason::Error(e) => write!(f, "emulation stopped: {}", e),
+        }
+    }
+}
+
+// Breakpoints and the stepping commands, frontends drive it and print whatever they like.
+// Timers tick once per instructions_per_frame instructions, so delays count down the same
+// as they would running normally.
+pub struct Debugger {
+    breakpoints: BTreeSet<WORD>,
+    opcode_breakpoints: Vec<OpcodePattern>,
+    watches: Rc<RefCell<WatchState>>,
+    // instructions run since the timers last ticked
+    frame_cycles: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: Vec::new(),
+            watches: Rc::new(RefCell::new(WatchState::default())),
+            frame_cycles: 0,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: WORD) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: WORD) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = WORD> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        if !self.opcode_breakpoints.contains(&pattern) {
+            self.opcode_breakpoints.push(pattern);
+        }
+    }
+
+    pub fn remove_opcode_breakpoint(&mut self, pattern: OpcodePattern) -> bool {
+        let count = self.opcode_breakpoints.len();
+        self.opcode_breakpoints.retain(|p| *p != pattern);
+        self.opcode_breakpoints.len() != count
+    }
+
+    pub fn opcode_breakpoints(&self) -> &[OpcodePattern] {
+        &self.opcode_breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        let mut watches = self.watches.borrow_mut();
+        watches.watchpoints.retain(|w| w.location != watchpoint.location);
+        watches.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, location: Location) -> bool {
+        let mut watches = self.watches.borrow_mut();
+        let count = watches.watchpoints.len();
+        watches.watchpoints.retain(|w| w.location != location);
+        watches.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        self.watches.borrow().watchpoints.clone()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.opcode_breakpoints.clear();
+        self.watches.borrow_mut().watchpoints.clear();
+    }
+
+    // exactly one instruction, breakpoints don't matter
+    pub fn step(&mut self, chip_8: &mut Chip8Hardware) -> StopReason {
+        self.run_until(chip_8, 1, false, |_| true)
+    }
+
+    // like step, but a call runs until it returns
+    pub fn next(&mut self, chip_8: &mut Chip8Hardware, limit: usize) -> StopReason {
+        let pc = chip_8.get_program_counter();
+        match Instruction::decode_at(chip_8.get_memory(), pc as usize) {
+            Some(Instruction::Call(_)) => {
+                let return_address = pc.wrapping_add(2);
+                let depth = chip_8.get_stack().len();
+                self.run_until(chip_8, limit, false, |chip_8| {
+                    chip_8.get_program_counter() == return_address && chip_8.get_stack().len() == depth
+                })
+            }
+            _ => self.step(chip_8),
+        }
+    }
+
+    // runs until the current subroutine returns
+    pub fn finish(&mut self, chip_8: &mut Chip8Hardware, limit: usize) -> StopReason {
+        let depth = chip_8.get_stack().len();
+        if depth == 0 {
+            return self.step(chip_8);
+        }
+        self.run_until(chip_8, limit, false, |chip_8| chip_8.get_stack().len() < depth)
+    }
+
+    // runs until a breakpoint, the first instruction always runs so continuing off a breakpoint works
+    pub fn resume(&mut self, chip_8: &mut Chip8Hardware, limit: usize) -> StopReason {
+        self.run_until(chip_8, limit, false, |_| false)
+    }
+
+    // like resume but a breakpoint on the very first instruction counts too, for carrying on
+    // after resume ran out of steps without stepping over a breakpoint it stopped right before
+    pub fn run(&mut self, chip_8: &mut Chip8Hardware, limit: usize) -> StopReason {
+        self.run_until(chip_8, limit, true, |_| false)
+    }
+
+    fn run_until<F: Fn(&Chip8Hardware) -> bool>(&mut self, chip_8: &mut Chip8Hardware, limit: usize, check_first: bool, done: F) -> StopReason {
+        if chip_8.is_halted() {
+            return StopReason::Halted;
+        }
+        if self.watches.borrow().watchpoints.is_empty() {
+            return self.run_steps(chip_8, limit, check_first, done);
+        }
+        // watch only while we're running, and put back anything else that was watching the chip
+        self.watches.borrow_mut().hit = None;
+        let observer = WatchObserver { state: Rc::clone(&self.watches) };
+        let previous = chip_8.set_observer(Some(Box::new(observer)));
+        let reason = self.run_steps(chip_8, limit, check_first, done);
+        chip_8.set_observer(previous);
+        reason
+    }
+
+    fn run_steps<F: Fn(&Chip8Hardware) -> bool>(&mut self, chip_8: &mut Chip8Hardware, limit: usize, check_first: bool, done: F) -> StopReason {
+        for count in 0..limit {
+            if count > 0 || check_first {
+                if let Some(reason) = self.check_breakpoints(chip_8) {
+                    return reason;
+                }
+            }
+            if let Err(e) = self.step_once(chip_8) {
+                return StopReason::Error(e);
+            }
+            if chip_8.is_halted() {
+                return StopReason::Halted;
+            }
+            if let Some(hit) = self.watches.borrow_mut().hit.take() {
+                return StopReason::Watchpoint(hit);
+            }
+            if done(chip_8) {
+                return StopReason::Stepped;
+            }
+        }
+        StopReason::StepLimit(limit)
+    }
+
+    fn check_breakpoints(&self, chip_8: &Chip8Hardware) -> Option<StopReason> {
+        let pc = chip_8.get_program_counter();
+        if self.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        if self.opcode_breakpoints.is_empty() {
+            return None;
+        }
+        let memory = chip_8.get_memory();
+        let pc = pc as usize;
+        if pc + 1 >= memory.len() {
+            return None;
+        }
+        let opcode = (memory[pc] as WORD) << 8 | memory[pc + 1] as WORD;
+        if self.opcode_breakpoints.iter().any(|pattern| pattern.matches(opcode)) {
+            return Some(StopReason::OpcodeBreakpoint { address: pc as WORD, opcode });
+        }
+        None
+    }
+
+    // frames end where run_frame_until ends them, after instructions_per_frame instructions or at
+    // a draw waiting on the vblank, so timers run down at the same pace under the debugger
+    fn step_once(&mut self, chip_8: &mut Chip8Hardware) -> Result<(), Chip8Error> {
+        chip_8.step()?;
+        self.frame_cycles += 1;
+        if self.frame_cycles >= chip_8.get_instructions_per_frame() || chip_8.waiting_for_vblank {
+            self.frame_cycles = 0;
+            chip_8.waiting_for_vblank = false;
+            chip_8.tick_timers();
+        }
+        Ok(())
+    }
+
+    // `count` instructions decoded in a straight line from a little before `address`, the line at
+    // the program counter gets `=>` and breakpoints get `*`
+    pub fn disassemble_around(&self, chip_8: &Chip8Hardware, address: WORD, before: usize, count: usize) -> String {
+        let memory = chip_8.get_memory();
+        let pc = chip_8.get_program_counter() as usize;
+        let mut address = (address as usize).saturating_sub(before * 2);
+        let mut text = String::new();
+        for _ in 0..count {
+            let instruction = match Instruction::decode_at(memory, address) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let marker = if address == pc {"=>"} else {"  "};
+            let breakpoint = if self.breakpoints.contains(&(address as WORD)) {"*"} else {" "};
+            let raw = match instruction {
+                Instruction::LoadILong(long) => format!("F000 {:04X}", long),
+                _ => format!("{:04X}", instruction.encode()),
+            };
+            text.push_str(&format!("{}{} {:03X}: {:<9} {}\n", marker, breakpoint, address, raw, instruction));
+            address += instruction.size();
+        }
+        text
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+pub fn format_registers(chip_8: &Chip8Hardware) -> String {
+    let registers = chip_8.get_registers();
+    let mut text = String::new();
+    for (index, value) in registers.iter().enumerate() {
+        text.push_str(&format!("V{:X}={:02X}", index, value));
+        text.push(if index % 8 == 7 {'\n'} else {' '});
+    }
+    text.push_str(&format!("PC={:03X} I={:03X} SP={} DT={:02X} ST={:02X}\n",
+        chip_8.get_program_counter(), chip_8.get_address_i(), chip_8.get_stack().len(),
+        chip_8.get_delay_timer(), chip_8.get_sound_timer()));
+    text
+}
+
+pub fn format_stack(chip_8: &Chip8Hardware) -> String {
+    // innermost call first, like a backtrace
+    let stack = chip_8.get_stack();
+    if stack.is_empty() {
+        return "stack is empty\n".to_string();
+    }
+    let mut text = String::new();
+    for (depth, address) in stack.iter().rev().enumerate() {
+        text.push_str(&format!("#{:<2} returns to {:03X}\n", depth, address));
+    }
+    text
+}
+
+// hex dump, 16 bytes a row
+pub fn format_memory(chip_8: &Chip8Hardware, address: usize, length: usize) -> String {
+    let memory = chip_8.get_memory();
+    let end = address.saturating_add(length).min(memory.len());
+    let mut text = String::new();
+    for row in (address..end).step_by(16) {
+        let bytes: &[BYTE] = &memory[row..(row + 16).min(end)];
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes.iter().map(|&b| if (0x20..0x7F).contains(&b) {b as char} else {'.'}).collect();
+        text.push_str(&format!("{:04X}: {:<47}  {}\n", row, hex.join(" "), ascii));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_8_quirks::Quirks;
+
+    // v0 := 60, delay := v0, then draw and jump back forever
+    const DRAW_LOOP: [BYTE; 8] = [0x60, 0x3C, 0xF0, 0x15, 0xD0, 0x05, 0x12, 0x04];
+
+    // call 206 then spin at 204, 206 calls 210 on the way to returning
+    const CALLS: [BYTE; 20] = [
+        0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x22, 0x10, 0x61, 0x02,
+        0x00, 0xEE, 0x00, 0x00, 0x00, 0x00, 0x62, 0x03, 0x00, 0xEE,
+    ];
+
+    fn chip_8_with(program: &[BYTE]) -> Chip8Hardware {
+        let mut chip_8 = Chip8Hardware::with_quirks(Quirks::cosmac_vip());
+        chip_8.cpu_reset();
+        chip_8.load_rom(program).unwrap();
+        chip_8
+    }
+
+    fn chip_8() -> Chip8Hardware {
+        chip_8_with(&DRAW_LOOP)
+    }
+
+    #[test]
+    fn stepping_ticks_timers_at_the_same_pace_as_running() {
+        let mut running = chip_8();
+        for _ in 0..10 {
+            running.run_frame().unwrap();
+        }
+
+        // the first frame is the setup and a draw, every other one a jump and a draw
+        let mut stepping = chip_8();
+        let mut debugger = Debugger::new();
+        for _ in 0..3 + 9 * 2 {
+            assert!(matches!(debugger.step(&mut stepping), StopReason::Stepped));
+        }
+        assert_eq!(stepping.get_delay_timer(), 50);
+        assert_eq!(stepping.get_delay_timer(), running.get_delay_timer());
+        assert_eq!(stepping.get_program_counter(), running.get_program_counter());
+    }
+
+    #[test]
+    fn next_runs_a_whole_call() {
+        let mut chip_8 = chip_8_with(&CALLS);
+        let mut debugger = Debugger::new();
+        assert!(matches!(debugger.next(&mut chip_8, DEFAULT_STEP_LIMIT), StopReason::Stepped));
+        assert_eq!(chip_8.get_program_counter(), 0x202);
+        assert!(chip_8.get_stack().is_empty());
+        assert_eq!(&chip_8.get_registers()[..3], [0, 2, 3]);
+
+        // anything that isn't a call is a single step
+        assert!(matches!(debugger.next(&mut chip_8, DEFAULT_STEP_LIMIT), StopReason::Stepped));
+        assert_eq!(chip_8.get_program_counter(), 0x204);
+
+        // breakpoints inside the call still stop it
+        let mut chip_8 = chip_8_with(&CALLS);
+        debugger.add_breakpoint(0x210);
+        assert!(matches!(debugger.next(&mut chip_8, DEFAULT_STEP_LIMIT), StopReason::Breakpoint(0x210)));
+        assert_eq!(chip_8.get_stack().len(), 2);
+    }
+
+    #[test]
+    fn finish_runs_until_the_call_returns() {
+        let mut chip_8 = chip_8_with(&CALLS);
+        let mut debugger = Debugger::new();
+        debugger.step(&mut chip_8);
+        debugger.step(&mut chip_8);
+        assert_eq!((chip_8.get_program_counter(), chip_8.get_stack().len()), (0x210, 2));
+
+        assert!(matches!(debugger.finish(&mut chip_8, DEFAULT_STEP_LIMIT), StopReason::Stepped));
+        assert_eq!((chip_8.get_program_counter(), chip_8.get_stack().len()), (0x208, 1));
+        assert!(matches!(debugger.finish(&mut chip_8, DEFAULT_STEP_LIMIT), StopReason::Stepped));
+        assert_eq!((chip_8.get_program_counter(), chip_8.get_stack().len()), (0x202, 0));
+        assert_eq!(&chip_8.get_registers()[..3], [0, 2, 3]);
+
+        // outside any call it's a single step
+        assert!(matches!(debugger.finish(&mut chip_8, DEFAULT_STEP_LIMIT), StopReason::Stepped));
+        assert_eq!(chip_8.get_program_counter(), 0x204);
+    }
+
+    #[test]
+    fn running_gives_up_at_the_step_limit() {
+        // a call into the spin loop never comes back
+        let mut chip_8 = chip_8_with(&[0x22, 0x04, 0x00, 0xE0, 0x12, 0x04]);
+        let mut debugger = Debugger::new();
+        assert!(matches!(debugger.next(&mut chip_8, 100), StopReason::StepLimit(100)));
+        assert!(matches!(debugger.finish(&mut chip_8, 50), StopReason::StepLimit(50)));
+        assert!(matches!(debugger.resume(&mut chip_8, 25), StopReason::StepLimit(25)));
+        assert_eq!((chip_8.get_program_counter(), chip_8.get_stack().len()), (0x204, 1));
+        assert!(matches!(debugger.resume(&mut chip_8, 0), StopReason::StepLimit(0)));
+    }
+
+    #[test]
+    fn opcode_patterns() {
+        let store = OpcodePattern::parse("Fx55").unwrap();
+        assert!(store.matches(0xF355));
+        assert!(store.matches(0xFA55));
+        assert!(!store.matches(0xF365));
+        assert!(!store.matches(0xE355));
+        assert_eq!(store.to_string(), "F?55");
+
+        let draw = OpcodePattern::parse("D???").unwrap();
+        assert!(draw.matches(0xD000) && draw.matches(0xDFFF));
+        assert!(!draw.matches(0xC123));
+        assert_eq!(OpcodePattern::parse("d1_5"), OpcodePattern::parse("D1x5"));
+        assert_eq!(OpcodePattern::parse("????").map(|pattern| pattern.matches(0x1234)), Some(true));
+
+        for bad in ["", "F55", "F0555", "F-55", "F 55", "Fé55"] {
+            assert_eq!(OpcodePattern::parse(bad), None, "{}", bad);
+        }
+
+        // and as a breakpoint, stopping before the matching instruction runs
+        let mut chip_8 = chip_8();
+        let mut debugger = Debugger::new();
+        debugger.add_opcode_breakpoint(draw);
+        assert!(matches!(
+            debugger.resume(&mut chip_8, DEFAULT_STEP_LIMIT),
+            StopReason::OpcodeBreakpoint { address: 0x204, opcode: 0xD005 }
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::chip_8_emulator::BYTE;
+use crate::chip_8_instruction::Instruction;
+
+// most data runs get split up into lines this long
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineContents {
+    Code(Instruction),
+    Data(Vec<BYTE>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassemblyLine {
+    pub address: usize,
+    pub contents: LineContents,
+}
+
+pub struct Disassembly {
+    pub origin: usize,
+    pub lines: Vec<DisassemblyLine>,
+    // addresses something jumps to, calls or points I at that start a line
+    pub labels: BTreeSet<usize>,
+}
+
+// Walks the rom from its first byte following jumps, calls and skips, everything reached that
+// way is code and the rest is data. With follow_flow off every word is decoded in order instead.
+pub fn disassemble(rom: &[BYTE], origin: usize, follow_flow: bool) -> Disassembly {
+    let mut code_starts = vec![false; rom.len()];
+    let mut targets = BTreeSet::new();
+
+    if follow_flow {
+        trace_code(rom, origin, &mut code_starts, &mut targets);
+    } else {
+        let mut index = 0;
+        while index + 1 < rom.len() {
+            code_starts[index] = true;
+            let instruction = Instruction::decode_at(rom, index).unwrap_or(Instruction::Unknown(0));
+            if let Some(target) = instruction.target() {
+                targets.insert(target as usize);
+            }
+            index += instruction.size().min(rom.len() - index);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut index = 0;
+    while index < rom.len() {
+        let address = origin + index;
+        if code_starts[index] {
+            if let Some(instruction) = Instruction::decode_at(rom, index) {
+                lines.push(DisassemblyLine { address, contents: LineContents::Code(instruction) });
+                index += instruction.size();
+                continue;
+            }
+        }
+
+        // data runs stop at code, at anything with a label and at the line length
+        let mut bytes = vec![rom[index]];
+        index += 1;
+        while index < rom.len() && !code_starts[index] && !targets.contains(&(origin + index)) && bytes.len() < DATA_BYTES_PER_LINE {
+            bytes.push(rom[index]);
+            index += 1;
+        }
+        lines.push(DisassemblyLine { address, contents: LineContents::Data(bytes) });
+    }
+
+    let labels = lines.iter().map(|line| line.address).filter(|address| targets.contains(address)).collect();
+    Disassembly { origin, lines, labels }
+}
+
+fn trace_code(rom: &[BYTE], origin: usize, code_starts: &mut [bool], targets: &mut BTreeSet<usize>) {
+    let mut claimed = vec![false; rom.len()];
+    let mut pending = vec![origin];
+
+    while let Some(start) = pending.pop() {
+        let mut address = start;
+        loop {
+            if address < origin || address - origin >= rom.len() {
+                break;
+            }
+            let index = address - origin;
+            if code_starts[index] {
+                break;
+            }
+            let instruction = match Instruction::decode_at(rom, index) {
+                Some(Instruction::Unknown(_)) | Some(Instruction::Sys(_)) | None => break,
+                Some(instruction) => instruction,
+            };
+            // running into the middle of other code means this path is really data
+            if claimed[index..index + instruction.size()].iter().any(|c| *c) {
+                break;
+            }
+            code_starts[index] = true;
+            for c in claimed[index..index + instruction.size()].iter_mut() {
+                *c = true;
+            }
+            if let Some(target) = instruction.target() {
+                targets.insert(target as usize);
+            }
+
+            let next = address + instruction.size();
+            match instruction {
+                Instruction::Jump(target) => {
+                    pending.push(target as usize);
+                    break;
+                }
+                Instruction::Call(target) => pending.push(target as usize),
+                // computed jumps and the end of subroutines, nowhere we can follow
+                Instruction::JumpOffset(_) | Instruction::Ret | Instruction::Exit => break,
+                _ if instruction.is_skip() => {
+                    // the skipped instruction might be F000 NNNN
+                    let skipped = Instruction::decode_at(rom, next - origin).map(|i| i.size()).unwrap_or(2);
+                    pending.push(next + skipped);
+                }
+                _ => (),
+            }
+            address = next;
+        }
+    }
+}
+
+pub fn label_name(address: usize) -> String {
+    format!("L{:03X}", address)
+}
+
+impl Disassembly {
+    fn operand_label(&self, instruction: &Instruction) -> Option<String> {
+        let target = instruction.target()? as usize;
+        if self.labels.contains(&target) {
+            Some(label_name(target))
+        } else {
+            None
+        }
+    }
+}
+
+// assembler source, labels on their own lines and the address / raw bytes in a comment
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines.iter() {
+            if self.labels.contains(&line.address) {
+                writeln!(f, "{}:", label_name(line.address))?;
+            }
+            let (text, raw) = match &line.contents {
+                LineContents::Code(instruction) => {
+                    let text = match self.operand_label(instruction) {
+                        Some(label) => instruction.to_string_with_label(&label),
+                        None => instruction.to_string(),
+                    };
+                    (text, raw_words(instruction))
+                }
+                LineContents::Data(bytes) => {
+                    let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                    (format!("DB {}", values.join(", ")), String::new())
+                }
+            };
+            if raw.is_empty() {
+                writeln!(f, "    {:<28} ; {:03X}", text, line.address)?;
+            } else {
+                writeln!(f, "    {:<28} ; {:03X}: {}", text, line.address, raw)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn raw_words(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::LoadILong(address) => format!("F000 {:04X}", address),
+        _ => format!("{:04X}", instruction.encode()),
+    }
+}
@@ -1,39 +1,138 @@
 use std::fs::File;
 use std::io::prelude::*;
+use std::time::Duration;
 use rand::Rng;
 
-type BYTE = u8;     // 8bit -> 1 byte
-type WORD = u16;    // 16 bit -> 1 word
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_instruction::Instruction;
+use crate::chip_8_octo;
+use crate::chip_8_observer::{Access, Location, Observer};
+use crate::chip_8_trace::{TraceSnapshot, Tracer};
+use crate::chip_8_quirks::{IndexIncrement, Quirks};
+use crate::chip_8_random::{RandomSource, Xorshift};
+use crate::chip_8_rewind::RewindBuffer;
+
+pub type BYTE = u8;     // 8bit -> 1 byte
+pub type WORD = u16;    // 16 bit -> 1 word
+
+// default for how many opcodes run_frame() executes, 10 per frame is a 600hz cpu
+pub const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+// delay and sound timers count down at 60hz no matter how fast the cpu is going
+pub const TIMER_HZ: u32 = 60;
+
+// xo-chip gets the whole 64k, plain chip-8 roms just never touch the top of it
+pub const MEMORY_SIZE: usize = 0x10000;
+
+// xo-chip has two bitplanes, each screen_data cell holds a bit per plane
+pub const PLANE_COUNT: usize = 2;
+pub const ALL_PLANES: BYTE = 0b11;
+
+// xo-chip audio, pitch 64 plays the 16 byte pattern at 4000 bits per second
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_AUDIO_PITCH: BYTE = 64;
+// until a rom loads its own pattern with F002 the buzzer plays a plain 500hz square wave
+pub const DEFAULT_AUDIO_PATTERN: [BYTE; AUDIO_PATTERN_SIZE] = [0xF0; AUDIO_PATTERN_SIZE];
+
+// screen sizes, schip's 00FF switches to the high resolution one
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// the 10 byte tall schip digits live right after the normal font
+pub const BIG_FONT_ADDRESS: usize = 0x50;
+const BIG_FONTSET: [BYTE; 160] =
+[
+  0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+  0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+  0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+  0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+  0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+  0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+  0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+  0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+  0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+  0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+  0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+  0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+  0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
 
 pub struct Chip8Hardware {
     pub keyboard: [bool; 16],
-    address_i: WORD,
-    program_counter: WORD,
-    registers: [BYTE; 16],
-    memory: [BYTE; 0xFFF],
-    stack: [WORD; 16],
-    stack_pointer: usize,
-    fontset: [BYTE; 80],
-    // 32 is length, 64 is height
-    screen_data: [[BYTE; 64]; 32],
-    delay_timer: BYTE,
-    sound_timer: BYTE,
-    timer_counter: BYTE,
-    draw_enabled: bool,
+    pub(crate) address_i: WORD,
+    pub(crate) program_counter: WORD,
+    pub(crate) registers: [BYTE; 16],
+    pub(crate) memory: [BYTE; MEMORY_SIZE],
+    pub(crate) stack: [WORD; 16],
+    pub(crate) stack_pointer: usize,
+    pub(crate) fontset: [BYTE; 80],
+    // sized for the schip high resolution mode, low resolution only uses the top left 64x32
+    // indexed [y][x], bit 0 is plane 1 and bit 1 is plane 2
+    pub(crate) screen_data: [[BYTE; HIRES_WIDTH]; HIRES_HEIGHT],
+    // FN01, which planes draw / clear / scroll touch
+    pub(crate) selected_planes: BYTE,
+    // F002 / FX3A
+    pub(crate) audio_pattern: [BYTE; AUDIO_PATTERN_SIZE],
+    pub(crate) audio_pitch: BYTE,
+    pub(crate) high_resolution: bool,
+    // schip RPL user flags, FX75 / FX85
+    pub(crate) rpl_flags: [BYTE; 16],
+    // 00FD, the program asked to quit
+    pub(crate) halted: bool,
+    pub(crate) delay_timer: BYTE,
+    pub(crate) sound_timer: BYTE,
+    // emulated time that hasn't added up to a whole timer tick yet
+    pub(crate) timer_remainder: Duration,
+    pub(crate) draw_enabled: bool,
+    // cpu speed, opcodes per 60hz frame
+    pub(crate) instructions_per_frame: usize,
+    pub(crate) quirks: Quirks,
+    // display wait quirk, set by DXYN and cleared when the next frame starts
+    pub(crate) waiting_for_vblank: bool,
+    // history for rewinding, None until enable_rewind
+    pub(crate) rewind: Option<RewindBuffer>,
+    // where the instruction being executed started and its first word, for error reporting
+    pub(crate) current_address: WORD,
+    pub(crate) current_opcode: WORD,
+    // decoded instructions by address, None until enable_decode_cache
+    // writes to memory throw away any entry that overlaps them
+    pub(crate) decode_cache: Option<Vec<Option<Instruction>>>,
+    // hears about every read and write the opcodes make, for watchpoints
+    pub(crate) observer: Option<Box<dyn Observer>>,
+    // execution trace, None until enable_trace
+    pub(crate) trace: Option<Tracer>,
+    // CXNN's numbers, seeded from the thread rng until seed_rng picks a seed
+    pub(crate) random: Box<dyn RandomSource>,
 }
 
 impl Chip8Hardware{
     pub fn cpu_reset(&mut self){
-        self.memory = [0; 0xFFF];
-        self.screen_data = [[0; 64]; 32];
+        self.memory = [0; MEMORY_SIZE];
+        self.screen_data = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        self.high_resolution = false;
+        self.halted = false;
+        self.selected_planes = 1;
+        self.audio_pattern = DEFAULT_AUDIO_PATTERN;
+        self.audio_pitch = DEFAULT_AUDIO_PITCH;
         self.stack = [0; 16];
         self.stack_pointer = 0;
         self.keyboard = [false; 16];        // true if pressed, false if not pressed
         self.address_i = 0;
         self.registers = [0; 16];       // set all registers to 0
         self.program_counter = 0x200;
-        self.timer_counter = 30;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.timer_remainder = Duration::from_secs(0);
         self.draw_enabled = false;
+        self.waiting_for_vblank = false;
+        self.current_address = 0x200;
+        self.current_opcode = 0;
+        self.clear_decode_cache();
 
         self.fontset =
         [ 
@@ -58,12 +157,19 @@ impl Chip8Hardware{
         for i in 0..80{
             self.memory[i] = self.fontset[i];
         }
+        self.memory[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONTSET.len()].copy_from_slice(&BIG_FONTSET);
     }
 
     pub fn new() -> Chip8Hardware{
         Chip8Hardware{
-            memory: [0; 0xFFF],
-            screen_data: [[0; 64]; 32],
+            memory: [0; MEMORY_SIZE],
+            screen_data: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            high_resolution: false,
+            selected_planes: 1,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            audio_pitch: DEFAULT_AUDIO_PITCH,
+            rpl_flags: [0; 16],
+            halted: false,
             stack: [0; 16],
             stack_pointer: 0,
             keyboard: [false; 16],        // 1 if pressed, 0 if not pressed
@@ -71,6 +177,16 @@ impl Chip8Hardware{
             registers: [0; 16],       // set all registers to 0
             program_counter: 0x200,
             draw_enabled: false,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            quirks: Quirks::default(),
+            waiting_for_vblank: false,
+            rewind: None,
+            current_address: 0x200,
+            current_opcode: 0,
+            decode_cache: None,
+            observer: None,
+            trace: None,
+            random: Box::new(Xorshift::new(rand::thread_rng().gen())),
             fontset:
             [ 
               0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -92,148 +208,461 @@ impl Chip8Hardware{
             ],
             delay_timer: 0,
             sound_timer: 0,
-            timer_counter: 30,
+            timer_remainder: Duration::from_secs(0),
         }
     }
 
-    pub fn load_game(&mut self, game_file: String){
-        let mut f = File::open(game_file).unwrap();
-        let mut file_buffer = [0; 0xFFF - 0x200];
-        let buffer_size = f.read(&mut file_buffer[..]).expect("Error reading file");
+    pub fn seed_rng(&mut self, seed: u64){
+        // same seed, same CXNN numbers, so runs can be repeated exactly
+        self.random.seed(seed);
+    }
+
+    pub fn set_random_source(&mut self, random: Box<dyn RandomSource>) -> Box<dyn RandomSource>{
+        // hands back the old one, like set_observer
+        return std::mem::replace(&mut self.random, random);
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Chip8Hardware{
+        let mut chip_8 = Chip8Hardware::new();
+        chip_8.quirks = quirks;
+        return chip_8;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks){
+        self.quirks = quirks;
+    }
+
+    pub fn get_quirks(&self) -> Quirks{
+        return self.quirks;
+    }
+
+    pub fn load_game(&mut self, game_file: String) -> Result<(), Chip8Error>{
+        let mut f = File::open(game_file)?;
+        let mut file_buffer = Vec::new();
+        f.read_to_end(&mut file_buffer)?;
+
+        self.load_rom(&file_buffer)
+    }
+
+    pub fn load_program(&mut self, path: &str) -> Result<(), Chip8Error>{
+        // a fresh machine with the rom in it, octo source gets compiled on the way in
+        self.cpu_reset();
+        if path.ends_with(".8o"){
+            self.load_rom(&chip_8_octo::compile_file(path)?)
+        } else {
+            self.load_game(path.to_string())
+        }
+    }
 
-        for i in 0..buffer_size {
-            self.memory[i + 0x200] = file_buffer[i];
+    pub fn load_rom(&mut self, rom: &[BYTE]) -> Result<(), Chip8Error>{
+        // roms get loaded at 0x200, everything below that is reserved for the interpreter / font
+        let max_size = self.memory.len() - 0x200;
+        if rom.len() > max_size {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max: max_size });
         }
+
+        self.memory[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        self.clear_decode_cache();
+        Ok(())
     }
 
-    pub fn fetch_opcode(&mut self, opcode_value: &mut WORD){
-        *opcode_value = self.memory[self.program_counter as usize] as u16;
+    pub fn fetch_opcode(&mut self, opcode_value: &mut WORD) -> Result<(), Chip8Error>{
+        let pc = self.program_counter as usize;
+        if pc + 1 >= self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { pc: self.program_counter, opcode: 0, address: pc + 1 });
+        }
+
+        *opcode_value = self.memory[pc] as u16;
         *opcode_value <<= 8;
-        *opcode_value |= (self.memory[self.program_counter as usize + 1]) as u16;
-        self.program_counter += 2;
-    }
-
-    pub fn decode_and_execute_opcode(&mut self, opcode: WORD){
-        match opcode & 0xF000{                              // switch on first segment of opcode X _ _ _
-            0x1000 => Chip8Hardware::opcode_1NNN(self, opcode),   // jump opcode
-            0x0000 => {
-                match opcode & 0x000F{                      // see which opcode that starts with 00 we will need
-                    0x0000 => Chip8Hardware::opcode_00E0(self),
-                    0x000E => Chip8Hardware::opcode_00EE(self),
-                    _ => println!("no opcode that matches!1"),
-                }
+        *opcode_value |= (self.memory[pc + 1]) as u16;
+        self.program_counter = self.program_counter.wrapping_add(2);
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<(), Chip8Error>{
+        // one full cycle: fetch, decode, execute
+        // timers are separate, see tick_timers / advance_timers
+        if self.halted{
+            return Ok(());
+        }
+        let address = self.program_counter;
+        let instruction = self.fetch_instruction(address)?;
+        self.current_address = address;
+        self.current_opcode = instruction.encode();
+        self.program_counter = address.wrapping_add(instruction.size() as WORD);
+        if self.trace.is_none(){
+            return self.execute(instruction);
+        }
+
+        let before = TraceSnapshot::take(self);
+        self.execute(instruction)?;
+        let after = TraceSnapshot::take(self);
+        if let Some(trace) = &mut self.trace{
+            trace.record(address, instruction, &before, &after)?;
+        }
+        Ok(())
+    }
+
+    pub fn fetch_instruction(&mut self, address: WORD) -> Result<Instruction, Chip8Error>{
+        // decode the instruction at address, or take it from the cache if it's turned on
+        let address = address as usize;
+        if let Some(cache) = &self.decode_cache{
+            if let Some(instruction) = cache[address]{
+                return Ok(instruction);
             }
-            0x2000 => Chip8Hardware::opcode_2NNN(self, opcode),
-            0x3000 => Chip8Hardware::opcode_3XNN(self, opcode),
-            0x4000 => Chip8Hardware::opcode_4XNN(self, opcode),
-            0x5000 => Chip8Hardware::opcode_5XY0(self, opcode),                
-            0x6000 => Chip8Hardware::opcode_6XNN(self, opcode),            
-            0x7000 => Chip8Hardware::opcode_7XNN(self, opcode),   
-            0x8000 => {
-                match opcode & 0x000F{
-                    0x0000 => Chip8Hardware::opcode_8XY0(self, opcode),
-                    0x0001 => Chip8Hardware::opcode_8XY1(self, opcode),
-                    0x0002 => Chip8Hardware::opcode_8XY2(self, opcode),
-                    0x0003 => Chip8Hardware::opcode_8XY3(self, opcode),
-                    0x0004 => Chip8Hardware::opcode_8XY4(self, opcode),
-                    0x0005 => Chip8Hardware::opcode_8XY5(self, opcode),
-                    0x0006 => Chip8Hardware::opcode_8XY6(self, opcode),
-                    0x0007 => Chip8Hardware::opcode_8XY7(self, opcode),
-                    0x000E => Chip8Hardware::opcode_8XYE(self, opcode),
-                    _ => panic!("{} not covered", opcode),
-                }
-            },
-            0x9000 => Chip8Hardware::opcode_9XY0(self, opcode),
-            0xA000 => Chip8Hardware::opcode_ANNN(self, opcode),
-            0xB000 => Chip8Hardware::opcode_BNNN(self, opcode),
-            0xC000 => Chip8Hardware::opcode_CXNN(self, opcode),
-            0xD000 => Chip8Hardware::opcode_DXYN(self, opcode),
-            0xE000 => {
-                match opcode & 0x000F{
-                    0x000E => Chip8Hardware::opcode_EX9E(self, opcode),
-                    0x0001 => Chip8Hardware::opcode_EXA1(self, opcode),
-                    _ => println!("Not covered"),
-                }
-            },
-            0xF000 => {
-                match opcode & 0x00FF{
-                    0x0007 => Chip8Hardware::opcode_FX07(self, opcode),
-                    0x000A => Chip8Hardware::opcode_FX0A(self, opcode),
-                    0x0015 => Chip8Hardware::opcode_FX15(self, opcode),
-                    0x0018 => Chip8Hardware::opcode_FX18(self, opcode),
-                    0x001E => Chip8Hardware::opcode_FX1E(self, opcode),
-                    0x0029 => Chip8Hardware::opcode_FX29(self, opcode),
-                    0x0033 => Chip8Hardware::opcode_FX33(self, opcode),
-                    0x0055 => Chip8Hardware::opcode_FX55(self, opcode),
-                    0x0065 => Chip8Hardware::opcode_FX65(self, opcode),
-                    _ => println!("not covered"),
-                }
+        }
+
+        let instruction = match Instruction::decode_at(&self.memory, address){
+            Some(instruction) => instruction,
+            None => {
+                let last = (address + 3).min(self.memory.len());
+                return Err(Chip8Error::MemoryOutOfBounds { pc: address as WORD, opcode: 0, address: last });
+            }
+        };
+        if let Some(cache) = &mut self.decode_cache{
+            cache[address] = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    pub fn enable_decode_cache(&mut self){
+        // hot loops skip decoding, costs a slot per byte of memory
+        if self.decode_cache.is_none(){
+            self.decode_cache = Some(vec![None; MEMORY_SIZE]);
+        }
+    }
+
+    pub fn disable_decode_cache(&mut self){
+        self.decode_cache = None;
+    }
+
+    pub fn is_decode_cache_enabled(&self) -> bool{
+        return self.decode_cache.is_some();
+    }
+
+    pub(crate) fn clear_decode_cache(&mut self){
+        // for when the whole of memory changes at once, rom loads and save states
+        if let Some(cache) = &mut self.decode_cache{
+            for entry in cache.iter_mut(){
+                *entry = None;
+            }
+        }
+    }
+
+    pub fn set_observer(&mut self, observer: Option<Box<dyn Observer>>) -> Option<Box<dyn Observer>>{
+        // hands back whatever was watching before so it can be put back later
+        return std::mem::replace(&mut self.observer, observer);
+    }
+
+    fn notify(&mut self, location: Location, access: Access, old: WORD, new: WORD){
+        let pc = self.current_address;
+        if let Some(observer) = &mut self.observer{
+            observer.access(pc, location, access, old, new);
+        }
+    }
+
+    fn read_address_i(&mut self) -> WORD{
+        let value = self.address_i;
+        self.notify(Location::AddressI, Access::Read, value, value);
+        return value;
+    }
+
+    fn write_address_i(&mut self, value: WORD){
+        let old = self.address_i;
+        self.address_i = value;
+        self.notify(Location::AddressI, Access::Write, old, value);
+    }
+
+    fn write_timer(&mut self, location: Location, value: BYTE){
+        let timer = if location == Location::DelayTimer {&mut self.delay_timer} else {&mut self.sound_timer};
+        let old = *timer;
+        *timer = value;
+        self.notify(location, Access::Write, old as WORD, value as WORD);
+    }
+
+    pub(crate) fn read_memory(&mut self, address: usize) -> BYTE{
+        let value = self.memory[address];
+        self.notify(Location::Memory(address), Access::Read, value as WORD, value as WORD);
+        return value;
+    }
+
+    pub(crate) fn write_memory(&mut self, address: usize, value: BYTE){
+        // every opcode that stores to memory goes through here so the decode cache stays right
+        // an instruction is at most 4 bytes, so only the ones starting up to 3 bytes back can overlap
+        let old = self.memory[address];
+        self.memory[address] = value;
+        self.notify(Location::Memory(address), Access::Write, old as WORD, value as WORD);
+        if let Some(cache) = &mut self.decode_cache{
+            for entry in cache[address.saturating_sub(3)..=address].iter_mut(){
+                *entry = None;
             }
-            _ => println!("{} no opcode that matches!2", opcode),
         }
     }
 
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<(), Chip8Error>{
+        for _ in 0..cycles{
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error>{
+        self.run_frame_until(|_| false)?;
+        Ok(())
+    }
+
+    pub fn run_frame_until<F: FnMut(&Chip8Hardware) -> bool>(&mut self, mut done: F) -> Result<bool, Chip8Error>{
+        // a frame is 1/60th of a second, so the timers go down exactly once
+        // with the display wait quirk a draw ends the frame early, like waiting on the vblank interrupt
+        // done gets a look after every instruction, if it says stop the frame is cut short right
+        // there without ticking the timers and this returns true
+        self.waiting_for_vblank = false;
+        for _ in 0..self.instructions_per_frame{
+            self.step()?;
+            if done(self){
+                return Ok(true);
+            }
+            if self.waiting_for_vblank || self.halted{
+                break;
+            }
+        }
+        self.tick_timers();
+        self.record_rewind_frame();
+        Ok(false)
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: usize){
+        // always run at least one opcode or the game would freeze
+        self.instructions_per_frame = instructions.max(1);
+    }
+
+    pub fn get_instructions_per_frame(&self) -> usize{
+        return self.instructions_per_frame;
+    }
+
+    pub fn set_clock_hz(&mut self, hz: u32){
+        // rounds down to a whole number of opcodes per frame
+        self.set_instructions_per_frame((hz / TIMER_HZ) as usize);
+    }
+
+    pub fn get_clock_hz(&self) -> u32{
+        return self.instructions_per_frame as u32 * TIMER_HZ;
+    }
+
+    pub fn decode_and_execute_opcode(&mut self, opcode: WORD) -> Result<(), Chip8Error>{
+        // for callers that did their own fetch_opcode, the program counter is already past the opcode
+        // F000 still needs its address, which is the word the program counter points at now
+        self.current_address = self.program_counter.wrapping_sub(2);
+        self.current_opcode = opcode;
+        let instruction = match Instruction::decode(opcode){
+            Instruction::LoadILong(_) => {
+                let pc = self.program_counter as usize;
+                self.check_memory_range(pc, 2)?;
+                self.program_counter = self.program_counter.wrapping_add(2);
+                Instruction::LoadILong((self.memory[pc] as WORD) << 8 | self.memory[pc + 1] as WORD)
+            }
+            instruction => instruction,
+        };
+        self.execute(instruction)
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), Chip8Error>{
+        // the program counter has to already point past the instruction
+        let x = |index: BYTE| index as WORD;
+        match instruction{
+            Instruction::Cls => Chip8Hardware::opcode_00E0(self),
+            Instruction::Ret => Chip8Hardware::opcode_00EE(self)?,
+            Instruction::ScrollDown(n) => Chip8Hardware::opcode_00CN(self, x(n)),
+            Instruction::ScrollUp(n) => Chip8Hardware::opcode_00DN(self, x(n)),
+            Instruction::ScrollRight => Chip8Hardware::opcode_00FB(self),
+            Instruction::ScrollLeft => Chip8Hardware::opcode_00FC(self),
+            Instruction::Exit => Chip8Hardware::opcode_00FD(self),
+            Instruction::LowRes => Chip8Hardware::opcode_00FE(self),
+            Instruction::HighRes => Chip8Hardware::opcode_00FF(self),
+            Instruction::Jump(nnn) => Chip8Hardware::opcode_1NNN(self, nnn),
+            Instruction::Call(nnn) => Chip8Hardware::opcode_2NNN(self, nnn)?,
+            Instruction::SkipEqualByte(vx, nn) => Chip8Hardware::opcode_3XNN(self, x(vx), x(nn)),
+            Instruction::SkipNotEqualByte(vx, nn) => Chip8Hardware::opcode_4XNN(self, x(vx), x(nn)),
+            Instruction::SkipEqual(vx, vy) => Chip8Hardware::opcode_5XY0(self, x(vx), x(vy)),
+            Instruction::SaveRange(vx, vy) => Chip8Hardware::opcode_5XY2(self, x(vx), x(vy))?,
+            Instruction::LoadRange(vx, vy) => Chip8Hardware::opcode_5XY3(self, x(vx), x(vy))?,
+            Instruction::LoadByte(vx, nn) => Chip8Hardware::opcode_6XNN(self, x(vx), x(nn)),
+            Instruction::AddByte(vx, nn) => Chip8Hardware::opcode_7XNN(self, x(vx), x(nn)),
+            Instruction::Move(vx, vy) => Chip8Hardware::opcode_8XY0(self, x(vx), x(vy)),
+            Instruction::Or(vx, vy) => Chip8Hardware::opcode_8XY1(self, x(vx), x(vy)),
+            Instruction::And(vx, vy) => Chip8Hardware::opcode_8XY2(self, x(vx), x(vy)),
+            Instruction::Xor(vx, vy) => Chip8Hardware::opcode_8XY3(self, x(vx), x(vy)),
+            Instruction::Add(vx, vy) => Chip8Hardware::opcode_8XY4(self, x(vx), x(vy)),
+            Instruction::Sub(vx, vy) => Chip8Hardware::opcode_8XY5(self, x(vx), x(vy)),
+            Instruction::ShiftRight(vx, vy) => Chip8Hardware::opcode_8XY6(self, x(vx), x(vy)),
+            Instruction::SubReverse(vx, vy) => Chip8Hardware::opcode_8XY7(self, x(vx), x(vy)),
+            Instruction::ShiftLeft(vx, vy) => Chip8Hardware::opcode_8XYE(self, x(vx), x(vy)),
+            Instruction::SkipNotEqual(vx, vy) => Chip8Hardware::opcode_9XY0(self, x(vx), x(vy)),
+            Instruction::LoadI(nnn) => Chip8Hardware::opcode_ANNN(self, nnn),
+            Instruction::JumpOffset(nnn) => Chip8Hardware::opcode_BNNN(self, nnn),
+            Instruction::Random(vx, nn) => Chip8Hardware::opcode_CXNN(self, x(vx), x(nn)),
+            Instruction::Draw(vx, vy, n) => Chip8Hardware::opcode_DXYN(self, x(vx), x(vy), x(n))?,
+            Instruction::SkipKey(vx) => Chip8Hardware::opcode_EX9E(self, x(vx)),
+            Instruction::SkipNotKey(vx) => Chip8Hardware::opcode_EXA1(self, x(vx)),
+            Instruction::LoadILong(nnnn) => Chip8Hardware::opcode_F000(self, nnnn),
+            Instruction::Plane(n) => Chip8Hardware::opcode_FN01(self, x(n)),
+            Instruction::Audio => Chip8Hardware::opcode_F002(self)?,
+            Instruction::GetDelay(vx) => Chip8Hardware::opcode_FX07(self, x(vx)),
+            Instruction::WaitKey(vx) => Chip8Hardware::opcode_FX0A(self, x(vx)),
+            Instruction::SetDelay(vx) => Chip8Hardware::opcode_FX15(self, x(vx)),
+            Instruction::SetSound(vx) => Chip8Hardware::opcode_FX18(self, x(vx)),
+            Instruction::AddI(vx) => Chip8Hardware::opcode_FX1E(self, x(vx)),
+            Instruction::Font(vx) => Chip8Hardware::opcode_FX29(self, x(vx)),
+            Instruction::BigFont(vx) => Chip8Hardware::opcode_FX30(self, x(vx)),
+            Instruction::Bcd(vx) => Chip8Hardware::opcode_FX33(self, x(vx))?,
+            Instruction::Pitch(vx) => Chip8Hardware::opcode_FX3A(self, x(vx)),
+            Instruction::Store(vx) => Chip8Hardware::opcode_FX55(self, x(vx))?,
+            Instruction::Load(vx) => Chip8Hardware::opcode_FX65(self, x(vx))?,
+            Instruction::SaveFlags(vx) => Chip8Hardware::opcode_FX75(self, x(vx)),
+            Instruction::LoadFlags(vx) => Chip8Hardware::opcode_FX85(self, x(vx)),
+            // 0NNN machine code routines can't run here
+            Instruction::Sys(_) | Instruction::Unknown(_) => return Err(self.unknown_opcode()),
+        }
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
-    pub fn opcode_1NNN(&mut self, opcode: WORD){
-        self.program_counter = opcode & 0x0FFF;
+    pub fn opcode_1NNN(&mut self, nnn: WORD){
+        self.program_counter = nnn;
     }
 
     #[allow(non_snake_case)]
     pub fn opcode_00E0(& mut self){
+        // only the selected planes get cleared
+        self.draw_enabled = true;
+        let planes = self.selected_planes;
+        for row in self.screen_data.iter_mut(){
+            for pixel in row.iter_mut(){
+                *pixel &= !planes;
+            }
+        }
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00CN(&mut self, n: WORD){
+        // scroll the display down N lines, new lines at the top are blank
+        let lines = n as usize;
+        let (width, height) = (self.get_screen_width(), self.get_screen_height());
+        for y in (0..height).rev(){
+            for x in 0..width{
+                let moved = if y >= lines {self.screen_data[y - lines][x]} else {0};
+                self.set_selected_planes(x, y, moved);
+            }
+        }
+        self.draw_enabled = true;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00DN(&mut self, n: WORD){
+        // xo-chip scroll up N lines, new lines at the bottom are blank
+        let lines = n as usize;
+        let (width, height) = (self.get_screen_width(), self.get_screen_height());
+        for y in 0..height{
+            for x in 0..width{
+                let moved = if y + lines < height {self.screen_data[y + lines][x]} else {0};
+                self.set_selected_planes(x, y, moved);
+            }
+        }
+        self.draw_enabled = true;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00FB(&mut self){
+        // scroll right 4 pixels
+        let (width, height) = (self.get_screen_width(), self.get_screen_height());
+        for y in 0..height{
+            for x in (0..width).rev(){
+                let moved = if x >= 4 {self.screen_data[y][x - 4]} else {0};
+                self.set_selected_planes(x, y, moved);
+            }
+        }
         self.draw_enabled = true;
-        for i in 0..32{
-            for j in 0..64{
-                self.screen_data[i][j] = 0;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00FC(&mut self){
+        // scroll left 4 pixels
+        let (width, height) = (self.get_screen_width(), self.get_screen_height());
+        for y in 0..height{
+            for x in 0..width{
+                let moved = if x + 4 < width {self.screen_data[y][x + 4]} else {0};
+                self.set_selected_planes(x, y, moved);
             }
         }
+        self.draw_enabled = true;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00FD(&mut self){
+        // exit the interpreter, step() does nothing from here on until cpu_reset
+        self.halted = true;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00FE(&mut self){
+        // back to 64x32, the screen gets cleared either way
+        self.high_resolution = false;
+        self.opcode_00E0();
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_00FF(&mut self){
+        // 128x64 mode
+        self.high_resolution = true;
+        self.opcode_00E0();
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_00EE(&mut self){
+    pub fn opcode_00EE(&mut self) -> Result<(), Chip8Error>{
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.current_address, opcode: self.current_opcode });
+        }
         self.stack_pointer -= 1;
-        self.program_counter = self.stack[self.stack_pointer as usize];
+        self.program_counter = self.stack[self.stack_pointer];
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_2NNN(&mut self, opcode: WORD){
-        self.stack[self.stack_pointer as usize] = (self.program_counter + 2) as WORD;
+    pub fn opcode_2NNN(&mut self, nnn: WORD) -> Result<(), Chip8Error>{
+        if self.stack_pointer >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow { pc: self.current_address, opcode: self.current_opcode });
+        }
+        // the program counter is already past the call, that's where 00EE comes back to
+        self.stack[self.stack_pointer] = self.program_counter;
         self.stack_pointer += 1;
-        self.program_counter = opcode & 0x0FFF;
+        self.program_counter = nnn;
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_3XNN(&mut self, opcode: WORD){
+    pub fn opcode_3XNN(&mut self, index_x: WORD, nn: WORD){
     //  skip next instruction if registers[X] == NN
-        let mut index: WORD = opcode & 0x0F00;
-        index = index >> 8;
-        let reg_value: WORD = self.registers[index as usize] as WORD;
-        let opcode_value: WORD = opcode & 0x00FF;
-        if reg_value == opcode_value{
+        let reg_value: WORD = Chip8Hardware::get_register_value(self, index_x);
+        if reg_value == nn{
             Chip8Hardware::skip_instruction(self);
         }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_4XNN(&mut self, opcode: WORD){
+    pub fn opcode_4XNN(&mut self, index_x: WORD, nn: WORD){
         //skip next instruction if registers[x] != NN
-        let mut index: WORD = opcode & 0x0F00;
-        index = index >> 8;
-        let reg_value: WORD = self.registers[index as usize] as WORD;
-        let opcode_value: WORD = opcode & 0x00FF;
-        if reg_value != opcode_value{
+        let reg_value: WORD = Chip8Hardware::get_register_value(self, index_x);
+        if reg_value != nn{
             Chip8Hardware::skip_instruction(self);
         }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_5XY0(&mut self, opcode: WORD){
+    pub fn opcode_5XY0(&mut self, index_x: WORD, index_y: WORD){
         //skip next instruction if registers[x] == registers[y]
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
-        let mut index_y: WORD = Chip8Hardware::get_second_arg(opcode);
-        index_y = index_y >> 4;
 
-        let reg_value_x: WORD = self.registers[index_x as usize] as WORD;
-        let reg_value_y: WORD = self.registers[index_y as usize] as WORD;
+        let reg_value_x: WORD = Chip8Hardware::get_register_value(self, index_x);
+        let reg_value_y: WORD = Chip8Hardware::get_register_value(self, index_y);
 
         if reg_value_x == reg_value_y{
             Chip8Hardware::skip_instruction(self);
@@ -241,31 +670,54 @@ impl Chip8Hardware{
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_6XNN(&mut self, opcode: WORD){
-        // set registers[x] = NN
-        let mut index_x: WORD = opcode & 0x0F00;
-        index_x = index_x >> 8;
+    pub fn opcode_5XY2(&mut self, index_x: WORD, index_y: WORD) -> Result<(), Chip8Error>{
+        // xo-chip, save registers VX..VY to memory at I, works in either direction and leaves I alone
+        let (index_x, index_y) = (index_x as usize, index_y as usize);
+        let count = index_x.abs_diff(index_y) + 1;
+        let start = self.read_address_i() as usize;
+        self.check_memory_range(start, count)?;
+
+        for i in 0..count{
+            let index = if index_x > index_y {index_x - i} else {index_x + i};
+            let value = Chip8Hardware::get_register_value(self, index as WORD);
+            self.write_memory(start + i, value as BYTE);
+        }
+        Ok(())
+    }
 
-        let opcode_value: WORD = opcode & 0x00FF;
+    #[allow(non_snake_case)]
+    pub fn opcode_5XY3(&mut self, index_x: WORD, index_y: WORD) -> Result<(), Chip8Error>{
+        // xo-chip, load registers VX..VY from memory at I
+        let (index_x, index_y) = (index_x as usize, index_y as usize);
+        let count = index_x.abs_diff(index_y) + 1;
+        let start = self.read_address_i() as usize;
+        self.check_memory_range(start, count)?;
+
+        for i in 0..count{
+            let index = if index_x > index_y {index_x - i} else {index_x + i};
+            let value = self.read_memory(start + i);
+            Chip8Hardware::set_register_value(self, index as WORD, value as WORD);
+        }
+        Ok(())
+    }
 
-        self.registers[index_x as usize] = opcode_value as BYTE;
+    #[allow(non_snake_case)]
+    pub fn opcode_6XNN(&mut self, index_x: WORD, nn: WORD){
+        // set registers[x] = NN
+        Chip8Hardware::set_register_value(self, index_x, nn);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_7XNN(&mut self, opcode: WORD){
+    pub fn opcode_7XNN(&mut self, index_x: WORD, nn: WORD){
         // set registers[x] += NN
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
         let value_x: WORD = Chip8Hardware::get_register_value(self, index_x);
-        let NN: WORD = Chip8Hardware::get_nn(opcode);
 
-        Chip8Hardware::set_register_value(self, index_x, value_x + NN);
+        Chip8Hardware::set_register_value(self, index_x, value_x + nn);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY0(&mut self, opcode: WORD){
+    pub fn opcode_8XY0(&mut self, index_x: WORD, index_y: WORD){
         // set value of registers[x] = registers[y]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_y = Chip8Hardware::get_register_value(self, index_y);
 
@@ -273,10 +725,8 @@ impl Chip8Hardware{
     }
     
     #[allow(non_snake_case)]
-    pub fn opcode_8XY1(&mut self, opcode: WORD){
+    pub fn opcode_8XY1(&mut self, index_x: WORD, index_y: WORD){
         // set value of register[x] = register[x] | register[y]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
@@ -284,13 +734,14 @@ impl Chip8Hardware{
         let or_x_y = value_x | value_y;
 
         Chip8Hardware::set_register_value(self, index_x, or_x_y);
+        if self.quirks.logic_resets_vf{
+            Chip8Hardware::set_register_value(self, 0xF, 0);
+        }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY2(&mut self, opcode: WORD){
+    pub fn opcode_8XY2(&mut self, index_x: WORD, index_y: WORD){
         // set value of register[x] = register[x] & register[y]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
@@ -298,13 +749,14 @@ impl Chip8Hardware{
         let and_x_y = value_x & value_y;
 
         Chip8Hardware::set_register_value(self, index_x, and_x_y);
+        if self.quirks.logic_resets_vf{
+            Chip8Hardware::set_register_value(self, 0xF, 0);
+        }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY3(&mut self, opcode: WORD){
+    pub fn opcode_8XY3(&mut self, index_x: WORD, index_y: WORD){
         // set value of register[x] = register[x] ^ register[y]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
@@ -312,30 +764,28 @@ impl Chip8Hardware{
         let xor_x_y = value_x ^ value_y;
 
         Chip8Hardware::set_register_value(self, index_x, xor_x_y);
+        if self.quirks.logic_resets_vf{
+            Chip8Hardware::set_register_value(self, 0xF, 0);
+        }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY4(&mut self, opcode: WORD){
+    pub fn opcode_8XY4(&mut self, index_x: WORD, index_y: WORD){
         // set value of register[x] = register[x] + register[y]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
 
         let sum_x_y = value_x + value_y;
 
-        // psuedo ternary for if x + y overflows
-        self.registers[0xF] = if sum_x_y > 255 {1} else {0};
-
-        Chip8Hardware::set_register_value(self, index_x, sum_x_y);  
+        // psuedo ternary for if x + y overflows, VF goes last so 8FY4 keeps the flag
+        Chip8Hardware::set_register_value(self, index_x, sum_x_y);
+        Chip8Hardware::set_register_value(self, 0xF, if sum_x_y > 255 {1} else {0});
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY5(&mut self, opcode: WORD){
+    pub fn opcode_8XY5(&mut self, index_x: WORD, index_y: WORD){
         // set value of register[x] = register[x] - register[y]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
@@ -343,59 +793,53 @@ impl Chip8Hardware{
         let diff_x_y = value_x.wrapping_sub(value_y);
 
         // psuedo ternary for if x - y < 0
-        self.registers[0xF] = if value_y > value_x {0} else {1};
-
-        Chip8Hardware::set_register_value(self, index_x, diff_x_y);         
+        Chip8Hardware::set_register_value(self, index_x, diff_x_y);
+        Chip8Hardware::set_register_value(self, 0xF, if value_y > value_x {0} else {1});
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY6(&mut self, opcode: WORD){
+    pub fn opcode_8XY6(&mut self, index_x: WORD, index_y: WORD){
         // set registers[0xFF] = leastSignificantBit(registers[X])
         // then registers[X] = registers[X] >> 1
 
-        // it's the index we need to reference, but also the value
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        // index_x & 0x01 gives least significant bit
-        Chip8Hardware::set_register_value(self, 0xF, index_x & 0x01);
-        let shifted_index_x = index_x >> 1;
-        Chip8Hardware::set_register_value(self, index_x, shifted_index_x);
+        // the vip shifted VY into VX, later interpreters shift VX in place
+        let index_source = if self.quirks.shift_uses_vy {index_y} else {index_x};
+        let value = Chip8Hardware::get_register_value(self, index_source);
+
+        // value & 0x01 gives least significant bit, VF goes last so 8FY6 keeps the flag
+        Chip8Hardware::set_register_value(self, index_x, value >> 1);
+        Chip8Hardware::set_register_value(self, 0xF, value & 0x01);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XY7(&mut self, opcode: WORD){
+    pub fn opcode_8XY7(&mut self, index_x: WORD, index_y: WORD){
         // set value of register[x] = register[y] - register[x]
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
 
-        let diff_x_y = value_y - value_x;
+        let diff_x_y = value_y.wrapping_sub(value_x);
 
         // psuedo ternary for if y - x < 0
-        self.registers[0xF] = if value_y < value_x {0} else {1};
-
-        Chip8Hardware::set_register_value(self, index_x, diff_x_y);      
+        Chip8Hardware::set_register_value(self, index_x, diff_x_y);
+        Chip8Hardware::set_register_value(self, 0xF, if value_y < value_x {0} else {1});
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_8XYE(&mut self, opcode: WORD){
+    pub fn opcode_8XYE(&mut self, index_x: WORD, index_y: WORD){
         // set registers[0xFF] = mostSignificantBit(registers[X])
         // then registers[X] = registers[X] << 1
 
-        // it's the index we need to reference, but also the value
-        let index_x = Chip8Hardware::get_first_arg(opcode);
+        let index_source = if self.quirks.shift_uses_vy {index_y} else {index_x};
+        let value = Chip8Hardware::get_register_value(self, index_source);
 
-        // >> 7 gets most significant bit
-        Chip8Hardware::set_register_value(self, 0xF, index_x >> 7);
-        let shifted_index_x = index_x << 1;
-        Chip8Hardware::set_register_value(self, index_x, shifted_index_x);        
+        // >> 7 gets most significant bit, set_register_value drops the bit shifted out the top
+        Chip8Hardware::set_register_value(self, index_x, value << 1);
+        Chip8Hardware::set_register_value(self, 0xF, value >> 7);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_9XY0(&mut self, opcode: WORD){
-        let index_x = Chip8Hardware::get_first_arg(opcode);
-        let index_y = Chip8Hardware::get_second_arg(opcode);
+    pub fn opcode_9XY0(&mut self, index_x: WORD, index_y: WORD){
 
         let value_x = Chip8Hardware::get_register_value(self, index_x);
         let value_y = Chip8Hardware::get_register_value(self, index_y);
@@ -406,117 +850,185 @@ impl Chip8Hardware{
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_ANNN(&mut self, opcode: WORD){
-        let nnn: WORD = Chip8Hardware::get_nnn(opcode);
-        self.address_i = nnn;
+    pub fn opcode_ANNN(&mut self, nnn: WORD){
+        self.write_address_i(nnn);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_BNNN(&mut self, opcode: WORD){
-        let nnn: WORD = Chip8Hardware::get_nnn(opcode);
-        let register_value_0: WORD = Chip8Hardware::get_register_value(self, 0);
+    pub fn opcode_BNNN(&mut self, nnn: WORD){
+        // chip-48 and schip read this as BXNN, the high nibble of the address picks the register
+        let index_x: WORD = if self.quirks.jump_uses_vx {nnn >> 8} else {0};
+        let register_value: WORD = Chip8Hardware::get_register_value(self, index_x);
 
-        self.program_counter = nnn + register_value_0;
+        self.program_counter = nnn + register_value;
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_CXNN(& mut self, opcode: WORD){
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
+    pub fn opcode_CXNN(&mut self, index_x: WORD, nn: WORD){
 
-        let nn: WORD = Chip8Hardware::get_nn(opcode);
 
-        let random_number: WORD = rand::thread_rng().gen_range(0,256);
+        let random_number: WORD = self.random.next_byte() as WORD;
 
         Chip8Hardware::set_register_value(self, index_x, nn & random_number);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_DXYN(&mut self, opcode: WORD){
+    pub fn opcode_DXYN(&mut self, index_x: WORD, index_y: WORD, n: WORD) -> Result<(), Chip8Error>{
         self.draw_enabled = true;
         // drawing to screen, if any bits are flipped, set registers[15] = 1
 
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
         let value_x: WORD = Chip8Hardware::get_register_value(self, index_x);
-        let index_y: WORD = Chip8Hardware::get_second_arg(opcode);
         let value_y: WORD = Chip8Hardware::get_register_value(self, index_y);
-        let height: WORD = Chip8Hardware::get_n(opcode);
+
+        // DXY0 is a schip 16x16 sprite, two bytes per row
+        let (height, sprite_width): (WORD, WORD) = if n == 0 {(16, 16)} else {(n, 8)};
+        let bytes_per_row: WORD = sprite_width / 8;
+        let screen_width = self.get_screen_width() as WORD;
+        let screen_height = self.get_screen_height() as WORD;
 
         // set registers[15] = 0
         Chip8Hardware::set_register_value(self, 0xF, 0);
 
-        // how many lines of height we are rendering
-        for y in 0..height{
+        // xo-chip draws the sprite once per selected plane, each plane reads the next chunk of memory
+        // make sure the whole sprite is inside memory before we start flipping pixels
+        let planes = self.selected_planes;
+        let sprite_bytes = (height * bytes_per_row) as usize;
+        let mut sprite_address = self.read_address_i() as usize;
+        self.check_memory_range(sprite_address, sprite_bytes * planes.count_ones() as usize)?;
+
+        // the starting position always wraps, only the pixels that run off the edge can get clipped
+        let start_x = value_x % screen_width;
+        let start_y = value_y % screen_height;
+        let clip = self.quirks.clip_sprites;
+
+        for plane in 0..PLANE_COUNT{
+            let plane_mask: BYTE = 1 << plane;
+            if planes & plane_mask == 0{
+                continue;
+            }
 
-            // get the pixel which is a byte which is 8 bit _ _ _ _ _ _ _ _ -> these are 0s and 1s
-            // this pixel is stored in the  game memory array starting at address i
-            // and goes on until the height of the thing you're rendering is reached
-            let pixel = self.memory[(self.address_i + y) as usize];
-
-            // for each of the 8 bytes in the pixel we found above
-            for x in 0..8{
-
-                // we're going to do a bitwise and on that pixel with the value 0x80
-                // this is to say _ _ _ _ _ _ _ _ & 1 0 0 0 0 0 0 0
-                // but this 0x80 will be right shifted on each iteration
-                // so itll look like this
-                // 10000000
-                // 01000000
-                // 00100000
-                //  ...
-                // 00000001
-                // which is to say we're going to do an and for each bit in this byte of pixel data 
-                // if that &'ed pixel is not equal to 0, there is something there
-                if (pixel & (0x80 >> x)) != 0{
-
-                    // I found this online, this is for index out of bounds I guess
-                    // if index_x + x >= 32{
-                    //     continue;
-                    // }
-
-                    // if the screen data for that pixel == 1, we set register[15] to 1
-                    if self.screen_data[((value_y + y) % 32) as usize][((value_x + x) % 64) as usize] == 1{
-                        // set registers[15] = 1
-                        Chip8Hardware::set_register_value(self, 0xF, 1);
+            // how many lines of height we are rendering
+            for y in 0..height{
+
+                // get the pixel which is a byte which is 8 bit _ _ _ _ _ _ _ _ -> these are 0s and 1s
+                // this pixel is stored in the  game memory array starting at address i
+                // and goes on until the height of the thing you're rendering is reached
+                // 16 wide sprites glue two bytes together into one row
+                let row_address = sprite_address + (y * bytes_per_row) as usize;
+                let pixel: WORD = if bytes_per_row == 2 {
+                    (self.read_memory(row_address) as WORD) << 8 | self.read_memory(row_address + 1) as WORD
+                } else {
+                    self.read_memory(row_address) as WORD
+                };
+                let top_bit: WORD = 1 << (sprite_width - 1);
+
+                // for each of the bits in the pixel we found above
+                for x in 0..sprite_width{
+
+                    // we're going to do a bitwise and on that pixel with the value 0x80
+                    // this is to say _ _ _ _ _ _ _ _ & 1 0 0 0 0 0 0 0
+                    // but this 0x80 will be right shifted on each iteration
+                    // so itll look like this
+                    // 10000000
+                    // 01000000
+                    // 00100000
+                    //  ...
+                    // 00000001
+                    // which is to say we're going to do an and for each bit in this byte of pixel data 
+                    // if that &'ed pixel is not equal to 0, there is something there
+                    // (for 16 wide sprites it's the same thing starting from 0x8000)
+                    if (pixel & (top_bit >> x)) != 0{
+
+                        // off the edge of the screen, either drop it or wrap to the other side
+                        if clip && (start_x + x >= screen_width || start_y + y >= screen_height){
+                            continue;
+                        }
+                        let screen_x = ((start_x + x) % screen_width) as usize;
+                        let screen_y = ((start_y + y) % screen_height) as usize;
+
+                        // if the screen data for that pixel is already set on this plane, we set register[15] to 1
+                        if self.screen_data[screen_y][screen_x] & plane_mask != 0{
+                            // set registers[15] = 1
+                            Chip8Hardware::set_register_value(self, 0xF, 1);
+                        }
+
+                        // flip the bit by xor'ing the screen data we have for it here
+                        self.screen_data[screen_y][screen_x] ^= plane_mask;
                     }
-
-                    // flip the bit by xor'ing the screen data we have for it here
-                    self.screen_data[((value_y + y) % 32) as usize][((value_x + x) % 64) as usize] ^= 1;
                 }
             }
+
+            sprite_address += sprite_bytes;
         }
+
+        if self.quirks.display_wait{
+            self.waiting_for_vblank = true;
+        }
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_EX9E(&mut self, opcode: WORD){
-        let index: WORD = Chip8Hardware::get_first_arg(opcode);
-        let value_x = self.get_register_value(index);
-        if self.keyboard[value_x as usize] {
+    pub fn opcode_EX9E(&mut self, index_x: WORD){
+        let value_x = self.get_register_value(index_x);
+        // only the low nibble picks a key, anything bigger would be off the end of the keypad
+        if self.keyboard[(value_x & 0x0F) as usize] {
             Chip8Hardware::skip_instruction(self);
         }
 
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_EXA1(&mut self, opcode: WORD){
-        let index: WORD = Chip8Hardware::get_first_arg(opcode);
-        let value_x = self.get_register_value(index);
-        if !self.keyboard[value_x as usize] {
+    pub fn opcode_EXA1(&mut self, index_x: WORD){
+        let value_x = self.get_register_value(index_x);
+        if !self.keyboard[(value_x & 0x0F) as usize] {
             Chip8Hardware::skip_instruction(self);
         }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX07(&mut self, opcode: WORD){
+    pub fn opcode_F000(&mut self, nnnn: WORD){
+        // xo-chip F000 NNNN, the next word is a full 16 bit address for I
+        // it was read along with the opcode, the program counter is already past it
+        self.write_address_i(nnnn);
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_FN01(&mut self, n: WORD){
+        // xo-chip, pick which planes the drawing opcodes work on
+        self.selected_planes = n as BYTE & ALL_PLANES;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_F002(&mut self) -> Result<(), Chip8Error>{
+        // xo-chip, copy 16 bytes at I into the audio pattern buffer
+        let start = self.read_address_i() as usize;
+        self.check_memory_range(start, AUDIO_PATTERN_SIZE)?;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        for (i, byte) in pattern.iter_mut().enumerate(){
+            *byte = self.read_memory(start + i);
+        }
+        self.audio_pattern = pattern;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_FX3A(&mut self, index_x: WORD){
+        // xo-chip, set the playback pitch of the audio pattern
+        self.audio_pitch = Chip8Hardware::get_register_value(self, index_x) as BYTE;
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_FX07(&mut self, index_x: WORD){
         // set register[X] = delay_timer
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
-        Chip8Hardware::set_register_value(self, index_x, self.delay_timer as WORD);
+        let delay = self.delay_timer as WORD;
+        self.notify(Location::DelayTimer, Access::Read, delay, delay);
+        Chip8Hardware::set_register_value(self, index_x, delay);
 
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX0A(&mut self, opcode: WORD){
+    pub fn opcode_FX0A(&mut self, index_x: WORD){
         // wait for keypress, 
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
         let mut key_pressed: bool = false;
 
         for i in 0..16{
@@ -527,49 +1039,58 @@ impl Chip8Hardware{
         }
 
         if !key_pressed {
-            self.program_counter -= 2;
+            // run this same instruction again next step
+            self.program_counter = self.current_address;
             return;
         }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX15(&mut self, opcode: WORD){
+    pub fn opcode_FX15(&mut self, index_x: WORD){
         // set delay timer to register[X]
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
         let x: WORD = Chip8Hardware::get_register_value(self, index_x);
-        self.delay_timer = x as BYTE;
+        self.write_timer(Location::DelayTimer, x as BYTE);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX18(&mut self, opcode: WORD){
+    pub fn opcode_FX18(&mut self, index_x: WORD){
         // set sound timer to register[X]
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
         let x: WORD = Chip8Hardware::get_register_value(self, index_x);
-        self.sound_timer = x as BYTE;
+        self.write_timer(Location::SoundTimer, x as BYTE);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX1E(&mut self, opcode: WORD){
+    pub fn opcode_FX1E(&mut self, index_x: WORD){
         // Add register[x] to address_i, set register[F] to 1 if overflow, 0 otherwise
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
+        let value_x: WORD = Chip8Hardware::get_register_value(self, index_x);
 
         // set V[15] = 0 or 1
-        Chip8Hardware::set_register_value(self, 0xF, if self.address_i + index_x > 0xFFF { 1 } else { 0 });
-        self.address_i += index_x;
+        let address_i = self.read_address_i();
+        Chip8Hardware::set_register_value(self, 0xF, if address_i as usize + value_x as usize > 0xFFF { 1 } else { 0 });
+        self.write_address_i(address_i.wrapping_add(value_x));
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX29(&mut self, opcode: WORD){
+    pub fn opcode_FX29(&mut self, index_x: WORD){
         // set address_i to location of the sprite for character in register[X]
-        let index: WORD = Chip8Hardware::get_first_arg(opcode);
-        self.address_i = self.memory[index as usize] as WORD;
+        // the font starts at 0 and every character is 5 bytes
+        let character: WORD = Chip8Hardware::get_register_value(self, index_x) & 0x0F;
+        self.write_address_i(character * 5);
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX33(&mut self, opcode: WORD){
-        let index: WORD = Chip8Hardware::get_first_arg(opcode);
+    pub fn opcode_FX30(&mut self, index_x: WORD){
+        // same as FX29 but for the 10 byte schip font
+        let character: WORD = Chip8Hardware::get_register_value(self, index_x) & 0x0F;
+        self.write_address_i(BIG_FONT_ADDRESS as WORD + character * 10);
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_FX33(&mut self, index_x: WORD) -> Result<(), Chip8Error>{
+        let address = self.read_address_i() as usize;
+        self.check_memory_range(address, 3)?;
 
-        let mut value_x: WORD = Chip8Hardware::get_register_value(self, index);
+        let mut value_x: WORD = Chip8Hardware::get_register_value(self, index_x);
 
         let ones: WORD = value_x % 10;
         value_x = value_x / 10;
@@ -577,31 +1098,63 @@ impl Chip8Hardware{
         value_x = value_x / 10;
         let hundreds: WORD = value_x % 10;
 
-        self.memory[self.address_i as usize] = hundreds as BYTE;
-        self.memory[(self.address_i + 1) as usize] = tens as BYTE;
-        self.memory[(self.address_i + 2) as usize] = ones as BYTE;
+        self.write_memory(address, hundreds as BYTE);
+        self.write_memory(address + 1, tens as BYTE);
+        self.write_memory(address + 2, ones as BYTE);
+        Ok(())
+    }
+
+    fn increment_address_i_after_load_store(&mut self, start: WORD, index_x: WORD){
+        match self.quirks.load_store_index{
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::IncrementX => self.write_address_i(start.wrapping_add(index_x)),
+            IndexIncrement::IncrementXPlus1 => self.write_address_i(start.wrapping_add(index_x + 1)),
+        }
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX55(&mut self, opcode: WORD){
+    pub fn opcode_FX55(&mut self, index_x: WORD) -> Result<(), Chip8Error>{
         //dump value of registers into memory starting at address i
         
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);        
+        let start = self.read_address_i();
+        self.check_memory_range(start as usize, index_x as usize + 1)?;
 
         for i in 0..index_x + 1 {
             let register_value_i: WORD = Chip8Hardware::get_register_value(self, i);
-            self.memory[(self.address_i + i) as usize] = register_value_i as BYTE;
+            self.write_memory(start as usize + i as usize, register_value_i as BYTE);
         }
+        self.increment_address_i_after_load_store(start, index_x);
+        Ok(())
     }
 
     #[allow(non_snake_case)]
-    pub fn opcode_FX65(&mut self, opcode: WORD){
-        let index_x: WORD = Chip8Hardware::get_first_arg(opcode);
+    pub fn opcode_FX65(&mut self, index_x: WORD) -> Result<(), Chip8Error>{
+        let start = self.read_address_i();
+        self.check_memory_range(start as usize, index_x as usize + 1)?;
 
         for i in 0..index_x + 1{
-            let memory_value_i: WORD = self.memory[(self.address_i + i) as usize] as WORD;
+            let memory_value_i: WORD = self.read_memory((start + i) as usize) as WORD;
             Chip8Hardware::set_register_value(self, i, memory_value_i);        
         }
+        self.increment_address_i_after_load_store(start, index_x);
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_FX75(&mut self, index_x: WORD){
+        // save V0..VX into the RPL user flags
+        for i in 0..=index_x{
+            self.rpl_flags[i as usize] = Chip8Hardware::get_register_value(self, i) as BYTE;
+        }
+    }
+
+    #[allow(non_snake_case)]
+    pub fn opcode_FX85(&mut self, index_x: WORD){
+        // load V0..VX back out of the RPL user flags
+        for i in 0..=index_x{
+            let flag = self.rpl_flags[i as usize];
+            Chip8Hardware::set_register_value(self, i, flag as WORD);
+        }
     }
 
     #[allow(non_snake_case)]
@@ -633,41 +1186,152 @@ impl Chip8Hardware{
     }
 
     pub fn set_register_value(&mut self, index: WORD, opcode_value: WORD){
+        let old = self.registers[index as usize];
         self.registers[index as usize] = opcode_value as BYTE;
+        self.notify(Location::Register(index as BYTE), Access::Write, old as WORD, opcode_value & 0xFF);
     }
 
     pub fn get_register_value(&mut self, index: WORD) -> WORD {
-        return self.registers[index as usize] as WORD;
+        let value = self.registers[index as usize] as WORD;
+        self.notify(Location::Register(index as BYTE), Access::Read, value, value);
+        return value;
+    }
+
+    pub fn get_registers(&self) -> [BYTE; 16]{
+        return self.registers;
+    }
+
+    pub fn get_program_counter(&self) -> WORD{
+        return self.program_counter;
+    }
+
+    pub fn set_program_counter(&mut self, address: WORD){
+        self.program_counter = address;
+    }
+
+    pub fn get_address_i(&self) -> WORD{
+        return self.address_i;
+    }
+
+    pub fn set_address_i(&mut self, address: WORD){
+        self.address_i = address;
+    }
+
+    pub fn get_stack(&self) -> &[WORD]{
+        // only the return addresses that are actually in use, oldest first
+        return &self.stack[..self.stack_pointer];
+    }
+
+    pub fn get_memory(&self) -> &[BYTE]{
+        return &self.memory;
     }
 
     pub fn skip_instruction(&mut self){
-        self.program_counter += 2;
+        // F000 NNNN is 4 bytes long, skipping it has to jump over the address too
+        let pc = self.program_counter as usize;
+        let next_is_long = pc + 1 < self.memory.len() && self.memory[pc] == 0xF0 && self.memory[pc + 1] == 0x00;
+        self.program_counter = self.program_counter.wrapping_add(if next_is_long {4} else {2});
+    }
+
+    fn unknown_opcode(&self) -> Chip8Error{
+        return Chip8Error::UnknownOpcode { pc: self.current_address, opcode: self.current_opcode };
+    }
+
+    fn check_memory_range(&self, start: usize, length: usize) -> Result<(), Chip8Error>{
+        if start + length > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { pc: self.current_address, opcode: self.current_opcode, address: start + length - 1 });
+        }
+        Ok(())
     }
 
     pub fn get_pixel_value_x_y(&self, x: u16, y: u16) -> bool{
-        return if self.screen_data[x as usize][y as usize] == 1 {true} else {false};
+        return self.screen_data[x as usize][y as usize] != 0;
     }
 
-    pub fn decrement_timer_counter(&mut self){
-        self.timer_counter -= 1;
-        if self.timer_counter == 0{
-            self.timer_counter = 30;
+    pub fn get_pixel_planes_x_y(&self, x: u16, y: u16) -> BYTE{
+        // which planes are lit at this pixel, 0-3, frontends use it to pick from a 4 color palette
+        return self.screen_data[x as usize][y as usize];
+    }
 
-            self.sound_timer += 1;
-            self.delay_timer += 1;
+    fn set_selected_planes(&mut self, x: usize, y: usize, value: BYTE){
+        // overwrite only the bits of the selected planes, used by the scroll opcodes
+        let planes = self.selected_planes;
+        self.screen_data[y][x] = (self.screen_data[y][x] & !planes) | (value & planes);
+    }
 
-            if self.sound_timer > 10{
-                self.sound_timer = 0;
-                // do a sound here?
-            }
-            if self.delay_timer > 10{
-                self.delay_timer = 0;
-                // do something else here...?
-            }
+    pub fn get_audio_pattern(&self) -> [BYTE; AUDIO_PATTERN_SIZE]{
+        return self.audio_pattern;
+    }
+
+    pub fn get_audio_pitch(&self) -> BYTE{
+        return self.audio_pitch;
+    }
+
+    pub fn get_audio_sample_rate(&self) -> f64{
+        // playback rate of the pattern bits in hz, from the xo-chip spec
+        return 4000.0 * 2f64.powf((self.audio_pitch as f64 - 64.0) / 48.0);
+    }
+
+    pub fn get_screen_width(&self) -> usize{
+        return if self.high_resolution {HIRES_WIDTH} else {LORES_WIDTH};
+    }
+
+    pub fn get_screen_height(&self) -> usize{
+        return if self.high_resolution {HIRES_HEIGHT} else {LORES_HEIGHT};
+    }
+
+    pub fn is_high_resolution(&self) -> bool{
+        return self.high_resolution;
+    }
+
+    pub fn is_halted(&self) -> bool{
+        return self.halted;
+    }
+
+    pub fn tick_timers(&mut self){
+        // one 60hz tick, both timers stop at 0
+        if let Some(trace) = &mut self.trace{
+            trace.next_frame();
+        }
+        // only an actual change counts as a write, a timer sitting at 0 stays quiet
+        if self.delay_timer > 0{
+            self.write_timer(Location::DelayTimer, self.delay_timer - 1);
+        }
+        if self.sound_timer > 0{
+            self.write_timer(Location::SoundTimer, self.sound_timer - 1);
+        }
+    }
 
+    pub fn advance_timers(&mut self, elapsed: Duration){
+        // tick once for every full 1/60th of a second, leftover time carries into the next call
+        let tick = Duration::from_secs(1) / TIMER_HZ;
+        self.timer_remainder += elapsed;
+        while self.timer_remainder >= tick{
+            self.timer_remainder -= tick;
+            self.tick_timers();
         }
     }
 
+    pub fn get_delay_timer(&self) -> BYTE{
+        return self.delay_timer;
+    }
+
+    pub fn get_sound_timer(&self) -> BYTE{
+        return self.sound_timer;
+    }
+
+    pub fn set_delay_timer(&mut self, value: BYTE){
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: BYTE){
+        self.sound_timer = value;
+    }
+
+    pub fn is_sound_playing(&self) -> bool{
+        return self.sound_timer > 0;
+    }
+
     pub fn get_draw_enabled(&self) -> bool{
         return self.draw_enabled;
     }
@@ -675,4 +1339,82 @@ impl Chip8Hardware{
     pub fn disable_draw_enabled(&mut self){
         self.draw_enabled = false;
     }
-}
\ No newline at end of file
+}
+
+impl Default for Chip8Hardware{
+    fn default() -> Chip8Hardware{
+        Chip8Hardware::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a machine with the rom loaded and every address in it already sitting in the decode cache
+    fn cached(rom: &[BYTE]) -> Chip8Hardware {
+        let mut chip_8 = Chip8Hardware::new();
+        chip_8.cpu_reset();
+        chip_8.enable_decode_cache();
+        chip_8.load_rom(rom).unwrap();
+        for address in 0x200..0x200 + rom.len() {
+            chip_8.fetch_instruction(address as WORD).unwrap();
+        }
+        chip_8
+    }
+
+    fn assert_cache_matches_memory(chip_8: &mut Chip8Hardware) {
+        for address in 0x200..0x240 {
+            let fresh = Instruction::decode_at(&chip_8.memory, address).unwrap();
+            assert_eq!(chip_8.fetch_instruction(address as WORD).unwrap(), fresh, "stale decode at {:03X}", address);
+        }
+    }
+
+    #[test]
+    fn fx55_over_code_already_run() {
+        let mut chip_8 = cached(&[
+            0x22, 0x10, // 200: call 210, v2 := 0 the first time
+            0xA2, 0x10, // 202: i := 210
+            0x60, 0x62, // 204: v0 := 62
+            0x61, 0x2A, // 206: v1 := 2A
+            0xF1, 0x55, // 208: save v1, 210 is now v2 := 2A
+            0x22, 0x10, // 20A: call 210 again
+            0x12, 0x0C, // 20C: jump 20C
+            0x00, 0x00,
+            0x62, 0x00, // 210: v2 := 0
+            0x00, 0xEE, // 212: return
+        ]);
+        chip_8.run_cycles(10).unwrap();
+        assert_eq!(chip_8.get_register_value(2), 0x2A);
+        assert_cache_matches_memory(&mut chip_8);
+    }
+
+    #[test]
+    fn fx33_over_the_next_instruction() {
+        let mut chip_8 = cached(&[
+            0x60, 0x7B, // 200: v0 := 123
+            0xA2, 0x09, // 202: i := 209
+            0xF0, 0x33, // 204: bcd v0 over 209-20B
+            0x12, 0x06, // 206: jump 206
+            0x61, 0x00, // 208: v1 := 0, becomes v1 := 1
+        ]);
+        chip_8.run_cycles(3).unwrap();
+        assert_eq!(chip_8.fetch_instruction(0x208).unwrap(), Instruction::decode(0x6101));
+        assert_cache_matches_memory(&mut chip_8);
+    }
+
+    #[test]
+    fn writes_to_the_f000_operand() {
+        let mut chip_8 = cached(&[
+            0x60, 0x04, // 200: v0 := 04
+            0x61, 0x56, // 202: v1 := 56
+            0xA2, 0x0A, // 204: i := 20A
+            0xF1, 0x55, // 206: save v1 over the operand below
+            0xF0, 0x00, // 208: i := long 0300, becomes 0456
+            0x03, 0x00,
+        ]);
+        chip_8.run_cycles(5).unwrap();
+        assert_eq!(chip_8.get_address_i(), 0x456);
+        assert_cache_matches_memory(&mut chip_8);
+    }
+}
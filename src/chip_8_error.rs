@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::chip_8_emulator::WORD;
+
+// everything that can go wrong while loading or running a rom
+// pc is the address of the instruction that failed, not where the program counter ended up
+#[derive(Debug)]
+pub enum Chip8Error {
+    UnknownOpcode { pc: WORD, opcode: WORD },
+    StackOverflow { pc: WORD, opcode: WORD },
+    StackUnderflow { pc: WORD, opcode: WORD },
+    MemoryOutOfBounds { pc: WORD, opcode: WORD, address: usize },
+    RomTooLarge { size: usize, max: usize },
+    InvalidSaveState { reason: String },
+    InvalidTrace { reason: String },
+    InvalidGolden { path: String, reason: String },
+    // assembler source that doesn't make sense, line counts from 1
+    Assembly { file: String, line: usize, message: String },
+    Io(io::Error),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { pc, opcode } =>
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc),
+            Chip8Error::StackOverflow { pc, opcode } =>
+                write!(f, "stack overflow on {:04X} at {:03X}", opcode, pc),
+            Chip8Error::StackUnderflow { pc, opcode } =>
+                write!(f, "stack underflow on {:04X} at {:03X}", opcode, pc),
+            Chip8Error::MemoryOutOfBounds { pc, opcode, address } =>
+                write!(f, "memory access out of bounds ({:X}) on {:04X} at {:03X}", address, opcode, pc),
+            Chip8Error::RomTooLarge { size, max } =>
+                write!(f, "rom is {} bytes but only {} fit in memory", size, max),
+            Chip8Error::InvalidSaveState { reason } =>
+                write!(f, "invalid save state: {}", reason),
+            Chip8Error::InvalidTrace { reason } =>
+                write!(f, "invalid trace: {}", reason),
+            Chip8Error::InvalidGolden { path, reason } =>
+                write!(f, "invalid golden frame {}: {}", path, reason),
+            Chip8Error::Assembly { file, line, message } =>
+                write!(f, "{}:{}: {}", file, line, message),
+            Chip8Error::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Chip8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Chip8Error {
+        Chip8Error::Io(e)
+    }
+}
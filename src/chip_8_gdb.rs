@@ -0,0 +1,546 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::chip_8_debugger::{Debugger, StopReason, WatchKind, Watchpoint};
+use crate::chip_8_emulator::{Chip8Hardware, BYTE, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_observer::{Access, Location};
+
+pub const DEFAULT_PORT: u16 = 1234;
+// continue runs this many instructions between looks at the socket for a ctrl-c
+const CONTINUE_CHUNK: usize = 10_000;
+// register numbers as the target description lays them out, V0-VF are 0-15
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+const REGISTER_COUNT: usize = 21;
+// stop signals, SIGINT for ctrl-c, SIGTRAP for breakpoints and steps, SIGILL for opcodes we
+// don't know and SIGSEGV for running off the end of memory or the stack
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// Lets gdb (or anything else speaking the remote serial protocol) drive a Chip8Hardware over
+// a socket: registers, memory, breakpoints, watchpoints, stepping and continuing. Point gdb at
+// it with `target remote :1234`, it picks up the registers from the target description.
+pub struct GdbStub<'a> {
+    chip_8: &'a mut Chip8Hardware,
+    debugger: Debugger,
+    connection: Connection,
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(chip_8: &'a mut Chip8Hardware, stream: TcpStream) -> GdbStub<'a> {
+        GdbStub { chip_8, debugger: Debugger::new(), connection: Connection::new(stream) }
+    }
+
+    // answers packets until gdb detaches, kills the target or hangs up
+    pub fn serve(&mut self) -> Result<(), Chip8Error> {
+        while let Some(packet) = self.connection.read_packet()? {
+            let reply = match self.handle(&packet) {
+                Some(reply) => reply,
+                None => {
+                    self.connection.send("OK")?;
+                    break;
+                }
+            };
+            self.connection.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.connection.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    // the reply to a packet, None once the session's over
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(arguments),
+            "p" => self.read_register(arguments),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "s" => {
+                self.jump_to(arguments);
+                let reason = self.debugger.step(self.chip_8);
+                self.stop_reply(reason)
+            }
+            "c" => {
+                self.jump_to(arguments);
+                self.continue_running()
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", arguments),
+            "H" => "OK".to_string(),
+            "D" | "k" => return None,
+            "q" | "Q" => self.query(packet),
+            // vCont, X and anything else we don't do, an empty reply makes gdb fall back or give up
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_pair(range, ',') {
+                Some((offset, length)) => read_chunk(&target_description(), offset, length),
+                None => "E01".to_string(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    fn register(&self, number: usize) -> Option<Vec<BYTE>> {
+        let registers = self.chip_8.get_registers();
+        let value = match number {
+            0..=15 => vec![registers[number]],
+            REGISTER_I => self.chip_8.get_address_i().to_le_bytes().to_vec(),
+            REGISTER_PC => self.chip_8.get_program_counter().to_le_bytes().to_vec(),
+            REGISTER_SP => vec![self.chip_8.get_stack().len() as BYTE],
+            REGISTER_DT => vec![self.chip_8.get_delay_timer()],
+            REGISTER_ST => vec![self.chip_8.get_sound_timer()],
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // false for registers that can't be written, the stack pointer only moves with calls
+    fn set_register(&mut self, number: usize, bytes: &[BYTE]) -> bool {
+        let word = || bytes[0] as WORD | (*bytes.get(1).unwrap_or(&0) as WORD) << 8;
+        match number {
+            0..=15 => self.chip_8.set_register_value(number as WORD, bytes[0] as WORD),
+            REGISTER_I => self.chip_8.set_address_i(word()),
+            REGISTER_PC => self.chip_8.set_program_counter(word()),
+            REGISTER_DT => self.chip_8.set_delay_timer(bytes[0]),
+            REGISTER_ST => self.chip_8.set_sound_timer(bytes[0]),
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT).filter_map(|number| self.register(number)).map(|bytes| to_hex(&bytes)).collect()
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        let bytes = match from_hex(arguments) {
+            Some(bytes) => bytes,
+            None => return "E01".to_string(),
+        };
+        let mut offset = 0;
+        for number in 0..REGISTER_COUNT {
+            let size = self.register(number).map_or(0, |value| value.len());
+            if offset + size > bytes.len() {
+                break;
+            }
+            // the stack pointer comes back unchanged with everything else, skip it quietly
+            if number != REGISTER_SP {
+                self.set_register(number, &bytes[offset..offset + size]);
+            }
+            offset += size;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, arguments: &str) -> String {
+        let value = usize::from_str_radix(arguments, 16).ok().and_then(|number| self.register(number));
+        value.map_or("E01".to_string(), |bytes| to_hex(&bytes))
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let (number, value) = match arguments.split_once('=') {
+            Some(pair) => pair,
+            None => return "E01".to_string(),
+        };
+        let number = usize::from_str_radix(number, 16).ok();
+        let size = number.and_then(|number| self.register(number)).map(|value| value.len());
+        match (number, size, from_hex(value)) {
+            (Some(number), Some(size), Some(bytes)) if bytes.len() == size && self.set_register(number, &bytes) => "OK",
+            _ => "E01",
+        }
+        .to_string()
+    }
+
+    fn read_memory(&self, arguments: &str) -> String {
+        let memory = self.chip_8.get_memory();
+        match parse_pair(arguments, ',') {
+            Some((address, length)) if address < memory.len() => {
+                to_hex(&memory[address..address.saturating_add(length).min(memory.len())])
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let (range, data) = match arguments.split_once(':') {
+            Some(pair) => pair,
+            None => return "E01".to_string(),
+        };
+        let memory_size = self.chip_8.get_memory().len();
+        match (parse_pair(range, ','), from_hex(data)) {
+            (Some((address, length)), Some(bytes)) if bytes.len() == length && address.saturating_add(length) <= memory_size => {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    self.chip_8.write_memory(address + offset, *byte);
+                }
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // `Z0,addr,kind` software breakpoints, Z2 / Z3 / Z4 write, read and access watchpoints
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut parts = arguments.split(',');
+        let kind = parts.next().unwrap_or("");
+        let address = parts.next().and_then(|text| usize::from_str_radix(text, 16).ok());
+        let length = parts.next().and_then(|text| usize::from_str_radix(text, 16).ok()).unwrap_or(1);
+        let address = match address {
+            Some(address) if address <= 0xFFFF => address,
+            _ => return "E01".to_string(),
+        };
+
+        let watch_kind = match kind {
+            "0" => {
+                if insert {
+                    self.debugger.add_breakpoint(address as WORD);
+                } else {
+                    self.debugger.remove_breakpoint(address as WORD);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+        // one watchpoint a byte, a 2 byte watch on a word covers both halves
+        let end = match address.checked_add(length.max(1)) {
+            Some(end) if end <= self.chip_8.get_memory().len() => end,
+            _ => return "E01".to_string(),
+        };
+        for location in (address..end).map(Location::Memory) {
+            if insert {
+                self.debugger.add_watchpoint(Watchpoint { location, kind: watch_kind });
+            } else {
+                self.debugger.remove_watchpoint(location);
+            }
+        }
+        "OK".to_string()
+    }
+
+    // `s addr` and `c addr` carry on from addr instead of the program counter
+    fn jump_to(&mut self, arguments: &str) {
+        if let Ok(address) = WORD::from_str_radix(arguments, 16) {
+            self.chip_8.set_program_counter(address);
+        }
+    }
+
+    fn continue_running(&mut self) -> String {
+        // the first instruction runs on its own so continuing off a breakpoint doesn't stop right away
+        let mut reason = self.debugger.step(self.chip_8);
+        while let StopReason::Stepped | StopReason::StepLimit(_) = reason {
+            match self.connection.interrupted() {
+                Ok(false) => reason = self.debugger.run(self.chip_8, CONTINUE_CHUNK),
+                _ => return format!("S{:02x}", SIGINT),
+            }
+        }
+        self.stop_reply(reason)
+    }
+
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Stepped | StopReason::StepLimit(_) => format!("S{:02x}", SIGTRAP),
+            StopReason::Breakpoint(_) | StopReason::OpcodeBreakpoint { .. } => format!("T{:02x}swbreak:;", SIGTRAP),
+            StopReason::Watchpoint(hit) => {
+                let kind = match hit.access {
+                    Access::Read => "rwatch",
+                    Access::Write => "watch",
+                };
+                match hit.location {
+                    Location::Memory(address) => format!("T{:02x}{}:{:x};", SIGTRAP, kind, address),
+                    _ => format!("S{:02x}", SIGTRAP),
+                }
+            }
+            StopReason::Halted => "W00".to_string(),
+            StopReason::Error(Chip8Error::UnknownOpcode { .. }) => format!("S{:02x}", SIGILL),
+            StopReason::Error(_) => format!("S{:02x}", SIGSEGV),
+        }
+    }
+}
+
+// the packet framing, `$data#checksum` with + / - acks until gdb turns them off
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Connection {
+        Connection { stream, buffer: Vec::new(), no_ack: false }
+    }
+
+    // the next packet's data, None when gdb hangs up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // acks and stray ctrl-c's between packets don't mean anything to us
+            let start = self.buffer.iter().position(|&b| b == b'$');
+            if let Some(start) = start {
+                if let Some(end) = self.buffer[start..].iter().position(|&b| b == b'#').map(|end| start + end) {
+                    if self.buffer.len() >= end + 3 {
+                        let data = self.buffer[start + 1..end].to_vec();
+                        let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        self.buffer.drain(..end + 3);
+                        let valid = checksum == Some(checksum_of(&data));
+                        if !self.no_ack {
+                            self.stream.write_all(if valid {b"+"} else {b"-"})?;
+                        }
+                        if valid {
+                            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                self.buffer.clear();
+            }
+
+            let mut chunk = [0; 4096];
+            let count = self.stream.read(&mut chunk)?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..count]);
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        if self.no_ack {
+            return Ok(());
+        }
+        // gdb acks every packet, on a - send it again
+        let mut ack = [0; 1];
+        loop {
+            if self.stream.read(&mut ack)? == 0 {
+                return Ok(());
+            }
+            match ack[0] {
+                b'+' => return Ok(()),
+                b'-' => self.stream.write_all(packet.as_bytes())?,
+                other => self.buffer.push(other),
+            }
+        }
+    }
+
+    // whether gdb sent a ctrl-c while the program was running, without waiting for one
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut chunk = [0; 64];
+        let result = self.stream.read(&mut chunk);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            // hung up, stop running so read_packet sees it
+            Ok(0) => Ok(true),
+            Ok(count) => {
+                let interrupted = chunk[..count].contains(&0x03);
+                self.buffer.extend(chunk[..count].iter().filter(|&&b| b != 0x03));
+                Ok(interrupted)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn to_hex(bytes: &[BYTE]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<BYTE>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// `addr,length` style pairs, both hex
+fn parse_pair(text: &str, separator: char) -> Option<(usize, usize)> {
+    let (first, second) = text.split_once(separator)?;
+    Some((usize::from_str_radix(first, 16).ok()?, usize::from_str_radix(second, 16).ok()?))
+}
+
+// qXfer replies, `m` when there's more to come and `l` for the last piece
+fn read_chunk(document: &str, offset: usize, length: usize) -> String {
+    let bytes = document.as_bytes();
+    let start = offset.min(bytes.len());
+    let end = start.saturating_add(length).min(bytes.len());
+    let more = if end < bytes.len() {"m"} else {"l"};
+    format!("{}{}", more, String::from_utf8_lossy(&bytes[start..end]))
+}
+
+fn target_description() -> String {
+    let mut registers = String::new();
+    for index in 0..16 {
+        registers.push_str(&format!("    <reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n", index, index));
+    }
+    registers.push_str("    <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n");
+    registers.push_str("    <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n");
+    registers.push_str("    <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\n");
+    registers.push_str("    <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\n");
+    registers.push_str("    <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\n");
+    format!(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <feature name=\"org.chip8.core\">\n{}  </feature>\n</target>\n",
+        registers
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // both ends of a loopback connection, gdb's side first
+    fn connected() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    fn chip_8_with(program: &[BYTE]) -> Chip8Hardware {
+        let mut chip_8 = Chip8Hardware::new();
+        chip_8.cpu_reset();
+        chip_8.load_rom(program).unwrap();
+        chip_8
+    }
+
+    fn framed(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    fn read_exactly(stream: &mut TcpStream, count: usize) -> String {
+        let mut bytes = vec![0; count];
+        stream.read_exact(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn packets_are_acked_and_bad_checksums_rejected() {
+        let (mut gdb, stream) = connected();
+        let mut connection = Connection::new(stream);
+        gdb.write_all(b"+\x03$g#00").unwrap();
+        gdb.write_all(framed("m200,2").as_bytes()).unwrap();
+        assert_eq!(connection.read_packet().unwrap().as_deref(), Some("m200,2"));
+        assert_eq!(read_exactly(&mut gdb, 2), "-+");
+    }
+
+    #[test]
+    fn packets_split_across_reads_come_back_whole() {
+        let (mut gdb, stream) = connected();
+        let mut connection = Connection::new(stream);
+        let packet = framed("qSupported");
+        gdb.write_all(&packet.as_bytes()[..5]).unwrap();
+        gdb.flush().unwrap();
+        gdb.write_all(&packet.as_bytes()[5..]).unwrap();
+        assert_eq!(connection.read_packet().unwrap().as_deref(), Some("qSupported"));
+        gdb.shutdown(std::net::Shutdown::Write).unwrap();
+        assert_eq!(connection.read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn replies_are_sent_again_on_a_nack() {
+        let (mut gdb, stream) = connected();
+        let mut connection = Connection::new(stream);
+        gdb.write_all(b"-+").unwrap();
+        connection.send("OK").unwrap();
+        assert_eq!(read_exactly(&mut gdb, 12), "$OK#9a$OK#9a");
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (_gdb, stream) = connected();
+        let mut chip_8 = chip_8_with(&[0x60, 0x2A, 0xA3, 0x00]);
+        let mut stub = GdbStub::new(&mut chip_8, stream);
+        assert_eq!(stub.handle("?").as_deref(), Some("S05"));
+        assert_eq!(stub.handle("s").as_deref(), Some("S05"));
+        assert_eq!(stub.handle("p0").as_deref(), Some("2a"));
+        assert_eq!(stub.handle("p11").as_deref(), Some("0202"));
+        assert_eq!(stub.handle("P1=07").as_deref(), Some("OK"));
+        assert_eq!(stub.handle("p1").as_deref(), Some("07"));
+        assert_eq!(stub.handle("P12=01").as_deref(), Some("E01"));
+        assert_eq!(stub.handle("g").unwrap().len(), (16 + 2 + 2 + 1 + 1 + 1) * 2);
+
+        assert_eq!(stub.handle("m200,4").as_deref(), Some("602aa300"));
+        assert_eq!(stub.handle("M300,2:beef").as_deref(), Some("OK"));
+        assert_eq!(stub.handle("m300,2").as_deref(), Some("beef"));
+        assert_eq!(stub.handle("Mffff,2:beef").as_deref(), Some("E01"));
+        assert_eq!(stub.handle("Mffffffffffffffff,2:beef").as_deref(), Some("E01"));
+    }
+
+    #[test]
+    fn huge_lengths_are_clamped_or_rejected() {
+        let (_gdb, stream) = connected();
+        let mut chip_8 = chip_8_with(&[0x12, 0x00]);
+        let mut stub = GdbStub::new(&mut chip_8, stream);
+        assert_eq!(stub.handle("mfffe,ffffffffffffffff").as_deref(), Some("0000"));
+        assert_eq!(stub.handle("mffffffffffffffff,1").as_deref(), Some("E01"));
+        assert_eq!(stub.handle("Z2,300,ffffffffffffffff").as_deref(), Some("E01"));
+        assert_eq!(stub.handle("Z2,ffff,2").as_deref(), Some("E01"));
+        assert!(stub.debugger.watchpoints().is_empty());
+        assert!(stub.handle("qXfer:features:read:target.xml:10,ffffffffffffffff").unwrap().starts_with('l'));
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let (_gdb, stream) = connected();
+        // 200: v0 := 1, 202: i := 300, 204: save v0, 206: jump 206
+        let mut chip_8 = chip_8_with(&[0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]);
+        let mut stub = GdbStub::new(&mut chip_8, stream);
+        assert_eq!(stub.handle("Z0,202,2").as_deref(), Some("OK"));
+        assert_eq!(stub.handle("c").as_deref(), Some("T05swbreak:;"));
+        assert_eq!(stub.handle("p11").as_deref(), Some("0202"));
+        assert_eq!(stub.handle("z0,202,2").as_deref(), Some("OK"));
+
+        assert_eq!(stub.handle("Z2,300,2").as_deref(), Some("OK"));
+        assert_eq!(stub.debugger.watchpoints().len(), 2);
+        assert_eq!(stub.handle("c").as_deref(), Some("T05watch:300;"));
+        assert_eq!(stub.handle("z2,300,2").as_deref(), Some("OK"));
+        assert!(stub.debugger.watchpoints().is_empty());
+        assert_eq!(stub.handle("Z9,300,1").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn queries_and_detaching() {
+        let (_gdb, stream) = connected();
+        let mut chip_8 = chip_8_with(&[0x12, 0x00]);
+        let mut stub = GdbStub::new(&mut chip_8, stream);
+        assert!(stub.handle("qSupported:swbreak+").unwrap().contains("qXfer:features:read+"));
+        let start = stub.handle("qXfer:features:read:target.xml:0,10").unwrap();
+        assert_eq!(start, format!("m{}", &target_description()[..0x10]));
+        assert_eq!(stub.handle("qAttached").as_deref(), Some("1"));
+        assert_eq!(stub.handle("vMustReplyEmpty").as_deref(), Some(""));
+        assert_eq!(stub.handle("D"), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::chip_8_emulator::{Chip8Hardware, BYTE};
+use crate::chip_8_error::Chip8Error;
+
+// text golden characters by which planes are lit, plain chip-8 only ever has the first two
+const PLANE_CHARS: [char; 4] = ['.', '#', '+', '@'];
+
+// A copy of the display to check against a golden file. Golden files are either text, a row of
+// PLANE_CHARS per line, or PBM where anything lit is black and the planes all look the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    // rows top to bottom, which planes are lit at each pixel
+    pub pixels: Vec<Vec<BYTE>>,
+}
+
+impl Frame {
+    pub fn capture(chip_8: &Chip8Hardware) -> Frame {
+        let width = chip_8.get_screen_width();
+        let height = chip_8.get_screen_height();
+        let pixels = (0..height as u16)
+            .map(|y| (0..width as u16).map(|x| chip_8.get_pixel_planes_x_y(y, x)).collect())
+            .collect();
+        Frame { width, height, pixels }
+    }
+
+    pub fn parse_text(text: &str) -> Result<Frame, String> {
+        let mut pixels = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let row = line.chars()
+                .map(|c| PLANE_CHARS.iter().position(|&p| p == c).map(|planes| planes as BYTE))
+                .collect::<Option<Vec<BYTE>>>()
+                .ok_or_else(|| format!("line {}: pixels are one of {}", number + 1, PLANE_CHARS.iter().collect::<String>()))?;
+            pixels.push(row);
+        }
+        Frame::from_rows(pixels)
+    }
+
+    // P1 (plain) or P4 (raw)
+    pub fn parse_pbm(data: &[BYTE]) -> Result<Frame, String> {
+        let mut position = 0;
+        let next_token = |position: &mut usize| -> Option<String> {
+            // whitespace and # comments separate the header fields
+            loop {
+                while data.get(*position).is_some_and(|b| b.is_ascii_whitespace()) {
+                    *position += 1;
+                }
+                if data.get(*position) != Some(&b'#') {
+                    break;
+                }
+                while data.get(*position).is_some_and(|&b| b != b'\n') {
+                    *position += 1;
+                }
+            }
+            let start = *position;
+            while data.get(*position).is_some_and(|b| !b.is_ascii_whitespace()) {
+                *position += 1;
+            }
+            if start == *position {None} else {Some(String::from_utf8_lossy(&data[start..*position]).into_owned())}
+        };
+
+        let magic = next_token(&mut position).unwrap_or_default();
+        let dimension = |position: &mut usize| next_token(position).and_then(|text| text.parse::<usize>().ok())
+            .ok_or_else(|| "bad PBM size".to_string());
+        let width = dimension(&mut position)?;
+        let height = dimension(&mut position)?;
+        let mut pixels = Vec::with_capacity(height);
+        match magic.as_str() {
+            "P1" => {
+                let bits: Vec<BYTE> = data[position..].iter()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .map(|&b| (b == b'1') as BYTE)
+                    .collect();
+                if bits.len() < width * height {
+                    return Err("PBM is missing pixels".to_string());
+                }
+                for row in bits.chunks(width.max(1)).take(height) {
+                    pixels.push(row.to_vec());
+                }
+            }
+            "P4" => {
+                // exactly one whitespace byte between the header and the bits
+                let data = &data[(position + 1).min(data.len())..];
+                let row_bytes = width.div_ceil(8);
+                if data.len() < row_bytes * height {
+                    return Err("PBM is missing pixels".to_string());
+                }
+                for row in data.chunks(row_bytes.max(1)).take(height) {
+                    pixels.push((0..width).map(|x| (row[x / 8] >> (7 - x % 8)) & 1).collect());
+                }
+            }
+            _ => return Err("not a P1 or P4 PBM".to_string()),
+        }
+        Ok(Frame { width, height, pixels })
+    }
+
+    // by extension, .pbm or anything else as text
+    pub fn load(path: &str) -> Result<Frame, Chip8Error> {
+        let data = fs::read(path)?;
+        let frame = if is_pbm(path) {
+            Frame::parse_pbm(&data)
+        } else {
+            Frame::parse_text(&String::from_utf8_lossy(&data))
+        };
+        frame.map_err(|reason| Chip8Error::InvalidGolden { path: path.to_string(), reason })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Chip8Error> {
+        let data = if is_pbm(path) {self.to_pbm()} else {self.to_string().into_bytes()};
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    // plain P1, so the file diffs nicely in version control
+    pub fn to_pbm(&self) -> Vec<BYTE> {
+        let mut text = format!("P1\n{} {}\n", self.width, self.height);
+        for row in &self.pixels {
+            let bits: Vec<&str> = row.iter().map(|&planes| if planes != 0 {"1"} else {"0"}).collect();
+            text.push_str(&bits.concat());
+            text.push('\n');
+        }
+        text.into_bytes()
+    }
+
+    // every lit pixel as plane 1, what a frame looks like after a trip through PBM
+    pub fn flattened(&self) -> Frame {
+        let pixels = self.pixels.iter().map(|row| row.iter().map(|&planes| (planes != 0) as BYTE).collect()).collect();
+        Frame { width: self.width, height: self.height, pixels }
+    }
+
+    // Everything lit in either frame, the same in both unless marked. `-` is only lit in the
+    // expected frame, `x` only in the actual one, `*` is lit in both but in different planes.
+    // None when they match.
+    pub fn diff(&self, actual: &Frame) -> Option<String> {
+        if self == actual {
+            return None;
+        }
+        if (self.width, self.height) != (actual.width, actual.height) {
+            return Some(format!("expected a {}x{} screen, got {}x{}\n{}", self.width, self.height, actual.width, actual.height, actual));
+        }
+
+        let mut text = String::new();
+        let mut wrong = 0;
+        for (y, (expected_row, actual_row)) in self.pixels.iter().zip(&actual.pixels).enumerate() {
+            let row: String = expected_row.iter().zip(actual_row).map(|(&expected, &actual)| match (expected, actual) {
+                (e, a) if e == a => PLANE_CHARS[e as usize & 3],
+                (_, 0) => '-',
+                (0, _) => 'x',
+                _ => '*',
+            }).collect();
+            let count = expected_row.iter().zip(actual_row).filter(|(e, a)| e != a).count();
+            wrong += count;
+            text.push_str(&row);
+            if count > 0 {
+                text.push_str(&format!("  < row {}", y));
+            }
+            text.push('\n');
+        }
+        Some(format!("{} pixels differ (- only expected, x only actual, * different planes)\n{}", wrong, text))
+    }
+
+    fn from_rows(pixels: Vec<Vec<BYTE>>) -> Result<Frame, String> {
+        let width = pixels.first().map_or(0, |row| row.len());
+        if let Some(row) = pixels.iter().position(|row| row.len() != width) {
+            return Err(format!("row {} is {} pixels wide, the first is {}", row + 1, pixels[row].len(), width));
+        }
+        Ok(Frame { width, height: pixels.len(), pixels })
+    }
+}
+
+fn is_pbm(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pbm"))
+}
+
+// the text golden format
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in &self.pixels {
+            let line: String = row.iter().map(|&planes| PLANE_CHARS[planes as usize & 3]).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
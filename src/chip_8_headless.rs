@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::chip_8_cli::parse_hex;
+use crate::chip_8_emulator::{Chip8Hardware, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_observer::Location;
+
+// how long `press` holds a key down when the script doesn't say
+pub const DEFAULT_PRESS_FRAMES: u64 = 5;
+
+// Something that ends a headless run early, checked after every instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    ProgramCounter(WORD),
+    Equals(Location, WORD),
+}
+
+impl Condition {
+    // `pc=2A0`, `v3=05`, `i=300`, `dt=0` or `300=01` for a byte of memory, all hex
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let (name, value) = text.split_once('=').ok_or_else(|| format!("`{}` isn't a condition like pc=2A0 or v3=05", text))?;
+        let value = parse_hex(value).filter(|value| *value <= 0xFFFF)
+            .ok_or_else(|| format!("`{}` isn't a hex value", value))? as WORD;
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("pc") {
+            return Ok(Condition::ProgramCounter(value));
+        }
+        Ok(Condition::Equals(Location::parse(name)?, value))
+    }
+
+    pub fn is_met(&self, chip_8: &Chip8Hardware) -> bool {
+        match *self {
+            Condition::ProgramCounter(address) => chip_8.get_program_counter() == address,
+            Condition::Equals(location, value) => value_at(chip_8, location) == value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::ProgramCounter(address) => write!(f, "PC={:03X}", address),
+            Condition::Equals(location, value) => write!(f, "{}={:02X}", location, value),
+        }
+    }
+}
+
+// reads without going through the opcode helpers, so nothing watching the chip hears about it
+fn value_at(chip_8: &Chip8Hardware, location: Location) -> WORD {
+    match location {
+        Location::Memory(address) => chip_8.get_memory().get(address).copied().unwrap_or(0) as WORD,
+        Location::Register(index) => chip_8.get_registers()[index as usize & 0xF] as WORD,
+        Location::AddressI => chip_8.get_address_i(),
+        Location::DelayTimer => chip_8.get_delay_timer() as WORD,
+        Location::SoundTimer => chip_8.get_sound_timer() as WORD,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyEvent {
+    frame: u64,
+    key: usize,
+    down: bool,
+}
+
+// Keypad input for a run with nobody at the keyboard, keys go up or down at the start of a frame.
+// The script is a line per event, frames counted from 0:
+//   30 press 5       hold 5 for DEFAULT_PRESS_FRAMES frames
+//   60 press 5,6 10  hold 5 and 6 for 10 frames
+//   90 down A        until an `up`
+//   120 up A
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyScript {
+    // in frame order, ups before downs on the same frame so a release and a new press both happen
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    pub fn new() -> KeyScript {
+        KeyScript::default()
+    }
+
+    pub fn parse(text: &str) -> Result<KeyScript, String> {
+        let mut script = KeyScript::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("key script line {}: {}", number + 1, message);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 3 {
+                return Err(error("expected `<frame> press|down|up <keys>`".to_string()));
+            }
+            let frame = fields[0].parse().map_err(|_| error(format!("`{}` isn't a frame number", fields[0])))?;
+            let keys = parse_keys(fields[2]).map_err(error)?;
+            match (fields[1], fields.get(3)) {
+                ("press", length) => {
+                    let length = match length {
+                        Some(text) => text.parse().map_err(|_| error(format!("`{}` isn't a number of frames", text)))?,
+                        None => DEFAULT_PRESS_FRAMES,
+                    };
+                    for key in keys {
+                        script.press(frame, key, length);
+                    }
+                }
+                ("down", None) | ("up", None) => {
+                    for key in keys {
+                        script.add(KeyEvent { frame, key, down: fields[1] == "down" });
+                    }
+                }
+                (action, _) => return Err(error(format!("`{}` should be press, down or up", action))),
+            }
+        }
+        Ok(script)
+    }
+
+    // hold key down for `frames` frames starting at `frame`
+    pub fn press(&mut self, frame: u64, key: usize, frames: u64) {
+        self.add(KeyEvent { frame, key, down: true });
+        self.add(KeyEvent { frame: frame + frames.max(1), key, down: false });
+    }
+
+    fn add(&mut self, event: KeyEvent) {
+        let index = self.events.partition_point(|e| (e.frame, e.down) <= (event.frame, event.down));
+        self.events.insert(index, event);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // sets the keypad for the start of `frame`
+    pub fn apply(&self, frame: u64, chip_8: &mut Chip8Hardware) {
+        let start = self.events.partition_point(|e| e.frame < frame);
+        for event in self.events[start..].iter().take_while(|e| e.frame == frame) {
+            chip_8.keyboard[event.key] = event.down;
+        }
+    }
+}
+
+// `5` or `5,6,A`
+fn parse_keys(text: &str) -> Result<Vec<usize>, String> {
+    text.split(',')
+        .map(|key| usize::from_str_radix(key, 16).ok().filter(|key| *key < 16)
+            .ok_or_else(|| format!("`{}` isn't a chip-8 key 0-F", key)))
+        .collect()
+}
+
+// how a headless run finished
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    // ran every frame it was asked to
+    Finished,
+    // a condition held, in the frame counted from 0
+    ConditionMet { condition: Condition, frame: u64 },
+    // the rom exited with 00FD
+    Halted { frame: u64 },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Finished => write!(f, "ran every frame"),
+            Outcome::ConditionMet { condition, frame } => write!(f, "{} in frame {}", condition, frame),
+            Outcome::Halted { frame } => write!(f, "exited in frame {}", frame),
+        }
+    }
+}
+
+// Runs a rom with no window, the same frames the frontend would run but as fast as possible.
+// Stops after `frames` frames, when the rom exits, or as soon as any condition holds.
+#[derive(Debug, Clone, Default)]
+pub struct HeadlessRunner {
+    pub frames: u64,
+    pub conditions: Vec<Condition>,
+    pub keys: KeyScript,
+}
+
+impl HeadlessRunner {
+    pub fn new(frames: u64) -> HeadlessRunner {
+        HeadlessRunner { frames, ..HeadlessRunner::default() }
+    }
+
+    pub fn run(&self, chip_8: &mut Chip8Hardware) -> Result<Outcome, Chip8Error> {
+        for frame in 0..self.frames {
+            self.keys.apply(frame, chip_8);
+            if let Some(condition) = self.met(chip_8) {
+                return Ok(Outcome::ConditionMet { condition, frame });
+            }
+            if chip_8.run_frame_until(|chip_8| self.met(chip_8).is_some())? {
+                let condition = self.met(chip_8).unwrap_or(self.conditions[0]);
+                return Ok(Outcome::ConditionMet { condition, frame });
+            }
+            if chip_8.is_halted() {
+                return Ok(Outcome::Halted { frame });
+            }
+        }
+        Ok(Outcome::Finished)
+    }
+
+    fn met(&self, chip_8: &Chip8Hardware) -> Option<Condition> {
+        self.conditions.iter().find(|condition| condition.is_met(chip_8)).copied()
+    }
+}
+
+// the screen as rows of `#` and `.`, plane colors other than off all count as on
+pub fn format_screen(chip_8: &Chip8Hardware) -> String {
+    let mut text = String::new();
+    for y in 0..chip_8.get_screen_height() as u16 {
+        let row: String = (0..chip_8.get_screen_width() as u16)
+            .map(|x| if chip_8.get_pixel_value_x_y(y, x) {'#'} else {'.'})
+            .collect();
+        text.push_str(&row);
+        text.push('\n');
+    }
+    text
+}
@@ -0,0 +1,312 @@
+use std::fmt;
+
+use crate::chip_8_emulator::{BYTE, WORD};
+
+// One decoded opcode. Register operands are indexes 0-F, addresses are already masked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Sys(WORD),                          // 0NNN
+    Cls,                                // 00E0
+    Ret,                                // 00EE
+    ScrollDown(BYTE),                   // 00CN
+    ScrollUp(BYTE),                     // 00DN
+    ScrollRight,                        // 00FB
+    ScrollLeft,                         // 00FC
+    Exit,                               // 00FD
+    LowRes,                             // 00FE
+    HighRes,                            // 00FF
+    Jump(WORD),                         // 1NNN
+    Call(WORD),                         // 2NNN
+    SkipEqualByte(BYTE, BYTE),          // 3XNN
+    SkipNotEqualByte(BYTE, BYTE),       // 4XNN
+    SkipEqual(BYTE, BYTE),              // 5XY0
+    SaveRange(BYTE, BYTE),              // 5XY2
+    LoadRange(BYTE, BYTE),              // 5XY3
+    LoadByte(BYTE, BYTE),               // 6XNN
+    AddByte(BYTE, BYTE),                // 7XNN
+    Move(BYTE, BYTE),                   // 8XY0
+    Or(BYTE, BYTE),                     // 8XY1
+    And(BYTE, BYTE),                    // 8XY2
+    Xor(BYTE, BYTE),                    // 8XY3
+    Add(BYTE, BYTE),                    // 8XY4
+    Sub(BYTE, BYTE),                    // 8XY5
+    ShiftRight(BYTE, BYTE),             // 8XY6
+    SubReverse(BYTE, BYTE),             // 8XY7
+    ShiftLeft(BYTE, BYTE),              // 8XYE
+    SkipNotEqual(BYTE, BYTE),           // 9XY0
+    LoadI(WORD),                        // ANNN
+    JumpOffset(WORD),                   // BNNN
+    Random(BYTE, BYTE),                 // CXNN
+    Draw(BYTE, BYTE, BYTE),             // DXYN
+    SkipKey(BYTE),                      // EX9E
+    SkipNotKey(BYTE),                   // EXA1
+    LoadILong(WORD),                    // F000 NNNN
+    Plane(BYTE),                        // FN01
+    Audio,                              // F002
+    GetDelay(BYTE),                     // FX07
+    WaitKey(BYTE),                      // FX0A
+    SetDelay(BYTE),                     // FX15
+    SetSound(BYTE),                     // FX18
+    AddI(BYTE),                         // FX1E
+    Font(BYTE),                         // FX29
+    BigFont(BYTE),                      // FX30
+    Bcd(BYTE),                          // FX33
+    Pitch(BYTE),                        // FX3A
+    Store(BYTE),                        // FX55
+    Load(BYTE),                         // FX65
+    SaveFlags(BYTE),                    // FX75
+    LoadFlags(BYTE),                    // FX85
+    Unknown(WORD),
+}
+
+impl Instruction {
+    // F000 is the only 4 byte instruction, decode() can't see its address so it comes back as 0,
+    // use decode_at when there's memory to read it from
+    pub fn decode(opcode: WORD) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as BYTE;
+        let y = ((opcode & 0x00F0) >> 4) as BYTE;
+        let n = (opcode & 0x000F) as BYTE;
+        let nn = (opcode & 0x00FF) as BYTE;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                _ if opcode & 0xFFF0 == 0x00C0 => Instruction::ScrollDown(n),
+                _ if opcode & 0xFFF0 == 0x00D0 => Instruction::ScrollUp(n),
+                _ => Instruction::Sys(nnn),
+            },
+            0x1000 => Instruction::Jump(nnn),
+            0x2000 => Instruction::Call(nnn),
+            0x3000 => Instruction::SkipEqualByte(x, nn),
+            0x4000 => Instruction::SkipNotEqualByte(x, nn),
+            0x5000 => match n {
+                0x0 => Instruction::SkipEqual(x, y),
+                0x2 => Instruction::SaveRange(x, y),
+                0x3 => Instruction::LoadRange(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x6000 => Instruction::LoadByte(x, nn),
+            0x7000 => Instruction::AddByte(x, nn),
+            0x8000 => match n {
+                0x0 => Instruction::Move(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::Add(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubReverse(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9000 if n == 0 => Instruction::SkipNotEqual(x, y),
+            0xA000 => Instruction::LoadI(nnn),
+            0xB000 => Instruction::JumpOffset(nnn),
+            0xC000 => Instruction::Random(x, nn),
+            0xD000 => Instruction::Draw(x, y, n),
+            0xE000 => match nn {
+                0x9E => Instruction::SkipKey(x),
+                0xA1 => Instruction::SkipNotKey(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF000 => match nn {
+                0x00 if x == 0 => Instruction::LoadILong(0),
+                0x01 => Instruction::Plane(x),
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::GetDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::Font(x),
+                0x30 => Instruction::BigFont(x),
+                0x33 => Instruction::Bcd(x),
+                0x3A => Instruction::Pitch(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                0x75 => Instruction::SaveFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    // decodes the instruction at address, None if it runs off the end of memory
+    pub fn decode_at(memory: &[BYTE], address: usize) -> Option<Instruction> {
+        if address + 1 >= memory.len() {
+            return None;
+        }
+        let opcode = (memory[address] as WORD) << 8 | memory[address + 1] as WORD;
+        match Instruction::decode(opcode) {
+            Instruction::LoadILong(_) => {
+                if address + 3 >= memory.len() {
+                    return None;
+                }
+                Some(Instruction::LoadILong((memory[address + 2] as WORD) << 8 | memory[address + 3] as WORD))
+            }
+            instruction => Some(instruction),
+        }
+    }
+
+    // size in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoadILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    // the address a jump, call or I load points at, for labelling
+    pub fn target(&self) -> Option<WORD> {
+        match *self {
+            Instruction::Jump(address) | Instruction::Call(address) | Instruction::LoadI(address)
+                | Instruction::JumpOffset(address) | Instruction::LoadILong(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    // instructions that conditionally skip the next one
+    pub fn is_skip(&self) -> bool {
+        matches!(self, Instruction::SkipEqualByte(..) | Instruction::SkipNotEqualByte(..) | Instruction::SkipEqual(..)
+            | Instruction::SkipNotEqual(..) | Instruction::SkipKey(_) | Instruction::SkipNotKey(_))
+    }
+
+    // back to the opcode, for F000 NNNN this is just the first word
+    pub fn encode(&self) -> WORD {
+        let xy = |x: BYTE, y: BYTE| (x as WORD) << 8 | (y as WORD) << 4;
+        let xnn = |x: BYTE, nn: BYTE| (x as WORD) << 8 | nn as WORD;
+        match *self {
+            Instruction::Sys(nnn) => nnn,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | n as WORD,
+            Instruction::ScrollUp(n) => 0x00D0 | n as WORD,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SkipEqualByte(x, nn) => 0x3000 | xnn(x, nn),
+            Instruction::SkipNotEqualByte(x, nn) => 0x4000 | xnn(x, nn),
+            Instruction::SkipEqual(x, y) => 0x5000 | xy(x, y),
+            Instruction::SaveRange(x, y) => 0x5002 | xy(x, y),
+            Instruction::LoadRange(x, y) => 0x5003 | xy(x, y),
+            Instruction::LoadByte(x, nn) => 0x6000 | xnn(x, nn),
+            Instruction::AddByte(x, nn) => 0x7000 | xnn(x, nn),
+            Instruction::Move(x, y) => 0x8000 | xy(x, y),
+            Instruction::Or(x, y) => 0x8001 | xy(x, y),
+            Instruction::And(x, y) => 0x8002 | xy(x, y),
+            Instruction::Xor(x, y) => 0x8003 | xy(x, y),
+            Instruction::Add(x, y) => 0x8004 | xy(x, y),
+            Instruction::Sub(x, y) => 0x8005 | xy(x, y),
+            Instruction::ShiftRight(x, y) => 0x8006 | xy(x, y),
+            Instruction::SubReverse(x, y) => 0x8007 | xy(x, y),
+            Instruction::ShiftLeft(x, y) => 0x800E | xy(x, y),
+            Instruction::SkipNotEqual(x, y) => 0x9000 | xy(x, y),
+            Instruction::LoadI(nnn) => 0xA000 | nnn,
+            Instruction::JumpOffset(nnn) => 0xB000 | nnn,
+            Instruction::Random(x, nn) => 0xC000 | xnn(x, nn),
+            Instruction::Draw(x, y, n) => 0xD000 | xy(x, y) | n as WORD,
+            Instruction::SkipKey(x) => 0xE09E | xnn(x, 0),
+            Instruction::SkipNotKey(x) => 0xE0A1 | xnn(x, 0),
+            Instruction::LoadILong(_) => 0xF000,
+            Instruction::Plane(n) => 0xF001 | xnn(n, 0),
+            Instruction::Audio => 0xF002,
+            Instruction::GetDelay(x) => 0xF007 | xnn(x, 0),
+            Instruction::WaitKey(x) => 0xF00A | xnn(x, 0),
+            Instruction::SetDelay(x) => 0xF015 | xnn(x, 0),
+            Instruction::SetSound(x) => 0xF018 | xnn(x, 0),
+            Instruction::AddI(x) => 0xF01E | xnn(x, 0),
+            Instruction::Font(x) => 0xF029 | xnn(x, 0),
+            Instruction::BigFont(x) => 0xF030 | xnn(x, 0),
+            Instruction::Bcd(x) => 0xF033 | xnn(x, 0),
+            Instruction::Pitch(x) => 0xF03A | xnn(x, 0),
+            Instruction::Store(x) => 0xF055 | xnn(x, 0),
+            Instruction::Load(x) => 0xF065 | xnn(x, 0),
+            Instruction::SaveFlags(x) => 0xF075 | xnn(x, 0),
+            Instruction::LoadFlags(x) => 0xF085 | xnn(x, 0),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+
+    // same as Display but with `label` written in place of the target address
+    pub fn to_string_with_label(&self, label: &str) -> String {
+        match *self {
+            Instruction::Jump(_) => format!("JP {}", label),
+            Instruction::Call(_) => format!("CALL {}", label),
+            Instruction::LoadI(_) => format!("LD I, {}", label),
+            Instruction::JumpOffset(_) => format!("JP V0, {}", label),
+            Instruction::LoadILong(_) => format!("LD I, LONG {}", label),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipEqualByte(x, nn) => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipNotEqualByte(x, nn) => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, nn) => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubReverse(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadILong(nnnn) => write!(f, "LD I, LONG 0x{:04X}", nnnn),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::fmt;
+
+// Just enough JSON for the debug adapter protocol, objects keep their keys in the order they
+// were written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(pairs: Vec<(&str, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    // Null for anything missing, so lookups can be chained
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map_or(&Json::Null, |(_, value)| value),
+            _ => &Json::Null,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(number) if number.fract() == 0.0 => Some(*number as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), position: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(format!("unexpected `{}` after the value", parser.chars[parser.position]));
+        }
+        Ok(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Json {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Json {
+        Json::String(text)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Json {
+        Json::Array(items)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => write!(f, "{}", *number as i64),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(text) => write_string(f, text),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(pairs) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in pairs.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// objects and arrays nested deeper than this are rejected instead of recursing until the stack
+// runs out, nothing the debug adapter protocol sends comes close
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    // how many objects and arrays the parser is inside
+    depth: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.position).is_some_and(|c| c.is_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = *self.chars.get(self.position).ok_or("unexpected end of json")?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(format!("expected `{}`", word));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.position).copied().ok_or("unexpected end of json")? {
+            '{' | '[' => {
+                if self.depth == MAX_DEPTH {
+                    return Err(format!("nested more than {} deep", MAX_DEPTH));
+                }
+                self.depth += 1;
+                let value = if self.chars[self.position] == '{' {self.object()} else {self.array()};
+                self.depth -= 1;
+                value
+            }
+            '"' => Ok(Json::String(self.string()?)),
+            't' => self.expect_word("true", Json::Bool(true)),
+            'f' => self.expect_word("false", Json::Bool(false)),
+            'n' => self.expect_word("null", Json::Null),
+            _ => self.number(),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut pairs = Vec::new();
+        self.skip_whitespace();
+        if self.chars.get(self.position) == Some(&'}') {
+            self.position += 1;
+            return Ok(Json::Object(pairs));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.get(self.position) != Some(&'"') {
+                return Err("expected a string key".to_string());
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.next()? != ':' {
+                return Err("expected `:` after a key".to_string());
+            }
+            pairs.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => continue,
+                '}' => return Ok(Json::Object(pairs)),
+                c => return Err(format!("expected `,` or `}}`, got `{}`", c)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.position += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.get(self.position) == Some(&']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next()? {
+                ',' => continue,
+                ']' => return Ok(Json::Array(items)),
+                c => return Err(format!("expected `,` or `]`, got `{}`", c)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(text),
+                '\\' => match self.next()? {
+                    c @ '"' | c @ '\\' | c @ '/' => text.push(c),
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // utf-16 surrogate pairs come as two escapes, half of one is U+FFFD
+                        if (0xD800..0xDC00).contains(&code) {
+                            if let Some(low) = self.low_surrogate() {
+                                self.position += 6;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                        }
+                        text.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    c => return Err(format!("bad escape `\\{}`", c)),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+
+    // the `\uDC00` to `\uDFFF` right after a high surrogate, without moving past it
+    fn low_surrogate(&self) -> Option<u32> {
+        let escape: String = self.chars.get(self.position..self.position + 6)?.iter().collect();
+        let low = u32::from_str_radix(escape.strip_prefix("\\u")?, 16).ok()?;
+        if (0xDC00..0xE000).contains(&low) {Some(low)} else {None}
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next()?.to_digit(16).ok_or("bad \\u escape")?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self.chars.get(self.position).is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse().map(Json::Number).map_err(|_| format!("`{}` isn't a json value", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(json: &str) -> String {
+        Json::parse(json).unwrap().as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let text = r#"{"seq":1,"ok":true,"body":null,"list":[1.5,-2,"a\"b\\c\n\u0001"],"empty":{},"none":[]}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(json.get("seq").as_u64(), Some(1));
+        assert_eq!(json.get("list").as_array()[1].as_i64(), Some(-2));
+        assert_eq!(json.get("missing").get("deeper"), &Json::Null);
+        assert_eq!(json.to_string(), text);
+        assert_eq!(Json::parse(" [ 1 , { \"a\" : 2 } ] ").unwrap().to_string(), "[1,{\"a\":2}]");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(string(r#""\/\b\f\t\r""#), "/\u{8}\u{c}\t\r");
+        assert_eq!(string(r#""é中""#), "é中");
+        assert_eq!(string(r#""\uD83D\uDE00""#), "😀");
+        // half a surrogate pair doesn't eat what comes after it
+        assert_eq!(string(r#""\uD800\n0041""#), "\u{FFFD}\n0041");
+        assert_eq!(string(r#""\uD800A""#), "\u{FFFD}A");
+        assert_eq!(string(r#""\uD800""#), "\u{FFFD}");
+        assert_eq!(string(r#""\uDC00x""#), "\u{FFFD}x");
+
+        assert_eq!(Json::parse(r#""\q""#), Err("bad escape `\\q`".to_string()));
+        assert_eq!(Json::parse(r#""\u12G4""#), Err("bad \\u escape".to_string()));
+        assert_eq!(Json::parse(r#""\u12"#), Err("unexpected end of json".to_string()));
+        assert_eq!(Json::parse(r#""open"#), Err("unexpected end of json".to_string()));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(Json::parse(&nested(MAX_DEPTH + 1)), Err(format!("nested more than {} deep", MAX_DEPTH)));
+        // deep enough to overflow the stack if it recursed all the way
+        assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+
+    #[test]
+    fn malformed_input() {
+        assert_eq!(Json::parse("{} x"), Err("unexpected `x` after the value".to_string()));
+        assert_eq!(Json::parse("[1] ]"), Err("unexpected `]` after the value".to_string()));
+        assert_eq!(Json::parse(""), Err("unexpected end of json".to_string()));
+        assert_eq!(Json::parse("{1:2}"), Err("expected a string key".to_string()));
+        assert_eq!(Json::parse("{\"a\" 2}"), Err("expected `:` after a key".to_string()));
+        assert_eq!(Json::parse("[1 2]"), Err("expected `,` or `]`, got `2`".to_string()));
+        assert_eq!(Json::parse("{\"a\":1 \"b\"}"), Err("expected `,` or `}`, got `\"`".to_string()));
+        assert_eq!(Json::parse("tru"), Err("unexpected end of json".to_string()));
+        assert_eq!(Json::parse("nul!"), Err("expected `null`".to_string()));
+        assert_eq!(Json::parse("1.2.3"), Err("`1.2.3` isn't a json value".to_string()));
+        assert_eq!(Json::parse("@"), Err("`` isn't a json value".to_string()));
+    }
+}
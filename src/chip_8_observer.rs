@@ -0,0 +1,63 @@
+use std::fmt;
+
+use crate::chip_8_cli::parse_hex;
+use crate::chip_8_emulator::{BYTE, WORD};
+
+// The parts of the machine an Observer hears about. Instruction fetches aren't reported, only
+// what the opcodes themselves read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Memory(usize),
+    Register(BYTE),
+    AddressI,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Location {
+    // a hex memory address, v0-vf, i, dt or st, the way the debugging tools take them
+    pub fn parse(text: &str) -> Result<Location, String> {
+        let text = text.to_lowercase();
+        let location = match text.as_str() {
+            "i" => Location::AddressI,
+            "dt" => Location::DelayTimer,
+            "st" => Location::SoundTimer,
+            _ => match text.strip_prefix('v') {
+                Some(index) => {
+                    let index = parse_hex(index).filter(|index| *index < 16);
+                    Location::Register(index.ok_or_else(|| format!("unknown register `{}`", text))? as BYTE)
+                }
+                None => {
+                    let address = parse_hex(&text).filter(|address| *address <= 0xFFFF);
+                    Location::Memory(address.ok_or_else(|| format!("`{}` isn't a register or address", text))?)
+                }
+            },
+        };
+        Ok(location)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Memory(address) => write!(f, "{:03X}", address),
+            Location::Register(index) => write!(f, "V{:X}", index),
+            Location::AddressI => write!(f, "I"),
+            Location::DelayTimer => write!(f, "DT"),
+            Location::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Set one with Chip8Hardware::set_observer. `pc` is the address of the instruction doing the
+// access, for reads `old` and `new` are both the value read. Timers count as written when the
+// 60hz tick changes them.
+pub trait Observer {
+    fn access(&mut self, pc: WORD, location: Location, access: Access, old: WORD, new: WORD);
+}
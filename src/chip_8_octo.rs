@@ -0,0 +1,945 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::f64::consts;
+use std::fs;
+
+use crate::chip_8_emulator::{BYTE, MEMORY_SIZE, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_source_map::SourceMap;
+
+// Compiler for Octo, the language most modern chip-8 / schip / xo-chip games are written in.
+// Like Octo the rom starts with a jump to `main` at 0x200 and everything else follows it.
+
+const ORIGIN: usize = 0x200;
+// macros that keep expanding into themselves stop here
+const MAX_MACRO_EXPANSIONS: usize = 10000;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+// addresses that weren't known when the instruction was written, filled in at the end
+#[derive(Clone, Copy, PartialEq)]
+enum FixupKind {
+    Address,
+    Long,
+}
+
+struct Fixup {
+    address: usize,
+    kind: FixupKind,
+    label: String,
+    line: usize,
+}
+
+// open loop / if blocks, the addresses are placeholder jumps waiting for their target
+enum Block {
+    Loop { start: usize, breaks: Vec<usize> },
+    If { jump: usize },
+    Else { jump: usize },
+}
+
+// the comparison in an if / while, turned into one skip instruction
+enum Condition {
+    Skip { when_true: WORD, when_false: WORD },
+    // <, >, <= and >= subtract into vf first, then test the borrow flag
+    Compare { setup: [WORD; 2], true_flag: WORD },
+}
+
+pub fn compile(source: &str) -> Result<Vec<BYTE>, Chip8Error> {
+    Ok(Compiler::new("<source>", source).run()?.0)
+}
+
+pub fn compile_file(path: &str) -> Result<Vec<BYTE>, Chip8Error> {
+    Ok(compile_file_with_map(path)?.0)
+}
+
+// the rom along with which line every statement came from, for debuggers
+pub fn compile_file_with_map(path: &str) -> Result<(Vec<BYTE>, SourceMap), Chip8Error> {
+    let source = fs::read_to_string(path)?;
+    Compiler::new(path, &source).run()
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<BYTE>,
+    written: Vec<bool>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, BYTE>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+    map: SourceMap,
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str) -> Compiler {
+        Compiler {
+            file: file.to_string(),
+            tokens: tokenize(source),
+            line: 1,
+            rom: Vec::new(),
+            written: Vec::new(),
+            here: ORIGIN,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+            map: SourceMap::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<(Vec<BYTE>, SourceMap), Chip8Error> {
+        self.compile().map_err(|message| Chip8Error::Assembly { file: self.file.clone(), line: self.line, message })?;
+        for (name, address) in self.labels.iter() {
+            self.map.add_label(name, *address as WORD);
+        }
+        Ok((self.rom, self.map))
+    }
+
+    fn compile(&mut self) -> Result<(), String> {
+        // room for the jump to main
+        self.emit_word(0x1000)?;
+        self.fixups.push(Fixup { address: ORIGIN, kind: FixupKind::Address, label: "main".to_string(), line: 1 });
+
+        while let Some(token) = self.next_token() {
+            let (line, start) = (self.line, self.here);
+            self.statement(&token)?;
+            // :org jumps ahead without writing anything, only count what the statement wrote
+            if self.here > start && self.written.get(start - ORIGIN) == Some(&true) {
+                let file = self.file.clone();
+                self.map.add_line(start as WORD, self.here - start, &file, line);
+            }
+        }
+
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::Loop { .. } => "loop without a matching again".to_string(),
+                _ => "begin without a matching end".to_string(),
+            });
+        }
+        if !self.labels.contains_key("main") {
+            self.line = 1;
+            return Err("this program does not define a main label".to_string());
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let target = match self.labels.get(&fixup.label) {
+                Some(&target) => target,
+                None => return Err(format!("undefined name `{}`", fixup.label)),
+            };
+            let index = fixup.address - ORIGIN;
+            match fixup.kind {
+                FixupKind::Address => {
+                    if target > 0xFFF {
+                        return Err(format!("`{}` is at 0x{:X}, past what a 12 bit address can reach", fixup.label, target));
+                    }
+                    self.rom[index] = (self.rom[index] & 0xF0) | (target >> 8) as BYTE;
+                    self.rom[index + 1] = target as BYTE;
+                }
+                FixupKind::Long => {
+                    self.rom[index] = (target >> 8) as BYTE;
+                    self.rom[index + 1] = target as BYTE;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        match token {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(&name) {
+                    return Err(format!("the label `{}` is already defined", name));
+                }
+                self.labels.insert(name, self.here);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value as f64);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {self.calc_block()?.floor() as i64} else {self.value()?};
+                self.emit_byte(self.check_range(value, 0xFF)? as BYTE)?;
+            }
+            ":org" => {
+                let address = self.value()?;
+                if address < ORIGIN as i64 || address >= MEMORY_SIZE as i64 {
+                    return Err(format!(":org 0x{:X} is outside program memory", address));
+                }
+                self.here = address as usize;
+            }
+            ":macro" => self.define_macro()?,
+            ":unpack" => {
+                // v0 gets the nibble and the high bits of the address, v1 the low byte
+                let nibble = self.value()?;
+                let address = self.known_address()?;
+                self.emit_word(0x6000 | ((nibble as WORD & 0xF) << 4) | (address >> 8) & 0xF)?;
+                self.emit_word(0x6100 | (address & 0xFF))?;
+            }
+            ":call" => self.address_instruction(0x2000)?,
+            ":breakpoint" => {
+                self.name()?;
+            }
+            ":monitor" => {
+                self.next_required()?;
+                self.next_required()?;
+            }
+            ";" | "return" => self.emit_word(0x00EE)?,
+            "clear" => self.emit_word(0x00E0)?,
+            "hires" => self.emit_word(0x00FF)?,
+            "lores" => self.emit_word(0x00FE)?,
+            "exit" => self.emit_word(0x00FD)?,
+            "scroll-right" => self.emit_word(0x00FB)?,
+            "scroll-left" => self.emit_word(0x00FC)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit_word(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit_word(0x00D0 | n)?;
+            }
+            "audio" => self.emit_word(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit_word(0xF001 | n << 8)?;
+            }
+            "bcd" => self.register_instruction(0xF033)?,
+            "saveflags" => self.register_instruction(0xF075)?,
+            "loadflags" => self.register_instruction(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()? as WORD;
+                if self.peek_is("-") {
+                    // save vx - vy is the xo-chip range version
+                    self.next_token();
+                    let y = self.register()? as WORD;
+                    let low = if token == "save" {0x2} else {0x3};
+                    self.emit_word(0x5000 | x << 8 | y << 4 | low)?;
+                } else {
+                    let low = if token == "save" {0x55} else {0x65};
+                    self.emit_word(0xF000 | x << 8 | low)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()? as WORD;
+                let y = self.register()? as WORD;
+                let n = self.nibble()?;
+                self.emit_word(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => self.address_instruction(0x1000)?,
+            "jump0" => self.address_instruction(0xB000)?,
+            "native" => self.address_instruction(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let low = match token {"delay" => 0x15, "buzzer" => 0x18, _ => 0x3A};
+                self.register_instruction(0xF000 | low)?;
+            }
+            "i" => self.index_statement()?,
+            "loop" => self.blocks.push(Block::Loop { start: self.here, breaks: Vec::new() }),
+            "again" => {
+                let (start, breaks) = match self.blocks.pop() {
+                    Some(Block::Loop { start, breaks }) => (start, breaks),
+                    _ => return Err("again without a matching loop".to_string()),
+                };
+                let target = jump_target(start)?;
+                self.emit_word(0x1000 | target)?;
+                for jump in breaks {
+                    self.patch_jump(jump)?;
+                }
+            }
+            "while" => {
+                // skip the jump out of the loop while the condition holds
+                let condition = self.condition()?;
+                self.emit_condition(&condition, true)?;
+                let jump = self.placeholder_jump()?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err("while outside of a loop".to_string()),
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next_required()?.as_str() {
+                    // then guards just the next statement, skip it when the condition is false
+                    "then" => self.emit_condition(&condition, false)?,
+                    // begin jumps over the block unless the condition is true
+                    "begin" => {
+                        self.emit_condition(&condition, true)?;
+                        let jump = self.placeholder_jump()?;
+                        self.blocks.push(Block::If { jump });
+                    }
+                    other => return Err(format!("expected then or begin, found `{}`", other)),
+                }
+            }
+            "else" => {
+                let jump = match self.blocks.pop() {
+                    Some(Block::If { jump }) => jump,
+                    _ => return Err("else without a matching begin".to_string()),
+                };
+                let skip_else = self.placeholder_jump()?;
+                self.patch_jump(jump)?;
+                self.blocks.push(Block::Else { jump: skip_else });
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump }) | Some(Block::Else { jump }) => self.patch_jump(jump)?,
+                _ => return Err("end without a matching begin".to_string()),
+            },
+            _ => {
+                if let Some(x) = self.register_name(token) {
+                    return self.register_statement(x);
+                }
+                if self.macros.contains_key(token) {
+                    return self.expand_macro(token);
+                }
+                if let Some(value) = parse_number(token) {
+                    // bare numbers are data
+                    return self.emit_byte(self.check_range(value, 0xFF)? as BYTE);
+                }
+                if self.constants.contains_key(token) {
+                    return Err(format!("the constant `{}` can't be used as a statement", token));
+                }
+                if !is_name(token) {
+                    return Err(format!("unexpected `{}`", token));
+                }
+                // anything else is a subroutine call, possibly to a label further down
+                self.push_address_word(0x2000, token)?;
+            }
+        }
+        Ok(())
+    }
+
+    // vx := ..., vx += ..., and the rest of the register operators
+    fn register_statement(&mut self, x: BYTE) -> Result<(), String> {
+        let x = x as WORD;
+        let operator = self.next_required()?;
+        let operand = self.next_required()?;
+        let y = self.register_name(&operand).map(WORD::from);
+
+        let word = match (operator.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x << 8 | y << 4,
+            ("|=", Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", Some(y)) => 0x8003 | x << 8 | y << 4,
+            ("+=", Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("-=", Some(y)) => 0x8005 | x << 8 | y << 4,
+            (">>=", Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("=-", Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("<<=", Some(y)) => 0x800E | x << 8 | y << 4,
+            (":=", None) if operand == "key" => 0xF00A | x << 8,
+            (":=", None) if operand == "delay" => 0xF007 | x << 8,
+            (":=", None) if operand == "random" => {
+                let mask = self.byte()?;
+                0xC000 | x << 8 | mask
+            }
+            (":=", None) => 0x6000 | x << 8 | self.byte_value(&operand)?,
+            ("+=", None) => 0x7000 | x << 8 | self.byte_value(&operand)?,
+            // there's no subtract immediate, add the negative instead
+            ("-=", None) => {
+                let value = self.token_value(&operand)?;
+                0x7000 | x << 8 | (self.check_range(value, 0xFF)?.wrapping_neg() & 0xFF) as WORD
+            }
+            _ => return Err(format!("can't use `{}` with `{}`", operator, operand)),
+        };
+        self.emit_word(word)
+    }
+
+    fn index_statement(&mut self) -> Result<(), String> {
+        let operator = self.next_required()?;
+        match operator.as_str() {
+            "+=" => self.register_instruction(0xF01E),
+            ":=" => {
+                let operand = self.next_required()?;
+                match operand.as_str() {
+                    "hex" => self.register_instruction(0xF029),
+                    "bighex" => self.register_instruction(0xF030),
+                    "long" => {
+                        let target = self.next_required()?;
+                        self.emit_word(0xF000)?;
+                        match self.known_value(&target)? {
+                            Some(value) => {
+                                let value = self.check_range(value, 0xFFFF)? as WORD;
+                                self.emit_word(value)
+                            }
+                            None => {
+                                self.fixups.push(Fixup { address: self.here, kind: FixupKind::Long, label: target, line: self.line });
+                                self.emit_word(0)
+                            }
+                        }
+                    }
+                    _ => self.push_address_word(0xA000, &operand),
+                }
+            }
+            _ => Err(format!("can't use `{}` with i", operator)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.register()? as WORD;
+        let operator = self.next_required()?;
+        match operator.as_str() {
+            "key" => return Ok(Condition::Skip { when_true: 0xE09E | x << 8, when_false: 0xE0A1 | x << 8 }),
+            "-key" => return Ok(Condition::Skip { when_true: 0xE0A1 | x << 8, when_false: 0xE09E | x << 8 }),
+            _ => (),
+        }
+
+        let operand = self.next_required()?;
+        let y = self.register_name(&operand).map(WORD::from);
+        let (equal, not_equal) = match y {
+            Some(y) => (0x5000 | x << 8 | y << 4, 0x9000 | x << 8 | y << 4),
+            None => {
+                let nn = self.byte_value(&operand)?;
+                (0x3000 | x << 8 | nn, 0x4000 | x << 8 | nn)
+            }
+        };
+        let condition = match operator.as_str() {
+            "==" => Condition::Skip { when_true: equal, when_false: not_equal },
+            "!=" => Condition::Skip { when_true: not_equal, when_false: equal },
+            "<" | ">" | "<=" | ">=" => {
+                // vf ends up as the no-borrow flag of either x - y or y - x
+                let x_minus_y = matches!(operator.as_str(), "<" | ">=");
+                let setup = match (y, x_minus_y) {
+                    (Some(y), true) => [0x8F00 | x << 4, 0x8F05 | y << 4],
+                    (Some(y), false) => [0x8F00 | y << 4, 0x8F05 | x << 4],
+                    (None, true) => [0x6F00 | (equal & 0xFF), 0x8F07 | x << 4],
+                    (None, false) => [0x6F00 | (equal & 0xFF), 0x8F05 | x << 4],
+                };
+                let true_flag = if matches!(operator.as_str(), "<=" | ">=") {1} else {0};
+                Condition::Compare { setup, true_flag }
+            }
+            _ => return Err(format!("unknown comparison `{}`", operator)),
+        };
+        Ok(condition)
+    }
+
+    // skip the next instruction either when the condition holds or when it doesn't
+    fn emit_condition(&mut self, condition: &Condition, skip_when_true: bool) -> Result<(), String> {
+        match condition {
+            Condition::Skip { when_true, when_false } => self.emit_word(if skip_when_true {*when_true} else {*when_false}),
+            Condition::Compare { setup, true_flag } => {
+                self.emit_word(setup[0])?;
+                self.emit_word(setup[1])?;
+                let flag = if skip_when_true {*true_flag} else {1 - true_flag};
+                self.emit_word(0x3F00 | flag)
+            }
+        }
+    }
+
+    fn placeholder_jump(&mut self) -> Result<usize, String> {
+        let address = self.here;
+        self.emit_word(0x1000)?;
+        Ok(address)
+    }
+
+    fn patch_jump(&mut self, jump: usize) -> Result<(), String> {
+        let target = jump_target(self.here)?;
+        let index = jump - ORIGIN;
+        self.rom[index] = 0x10 | (target >> 8) as BYTE;
+        self.rom[index + 1] = target as BYTE;
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next_required()?;
+            if token == "{" {
+                break;
+            }
+            parameters.push(token);
+        }
+        let body = self.block_tokens()?;
+        self.macros.insert(name, Macro { parameters, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(format!("too many macro expansions, does `{}` call itself?", name));
+        }
+        let count = self.macros[name].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..count {
+            let argument = self.next_required()?;
+            arguments.insert(self.macros[name].parameters[index].clone(), argument);
+        }
+        // the body goes back on the front of the token stream with the arguments swapped in
+        let line = self.line;
+        for token in self.macros[name].body.iter().rev() {
+            let text = arguments.get(&token.text).cloned().unwrap_or_else(|| token.text.clone());
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    // tokens up to the matching }, the opening { has already been read
+    fn block_tokens(&mut self) -> Result<Vec<Token>, String> {
+        let mut depth = 0;
+        let mut body = Vec::new();
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return Err("missing }".to_string()),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    fn calc_block(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+        let body: Vec<String> = self.block_tokens()?.into_iter().map(|token| token.text).collect();
+        let mut position = 0;
+        let value = self.calc_expression(&body, &mut position)?;
+        if position != body.len() {
+            return Err(format!("unexpected `{}` in expression", body[position]));
+        }
+        Ok(value)
+    }
+
+    // octo evaluates strictly right to left, no precedence, parentheses group
+    fn calc_expression(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let left = self.calc_term(tokens, position)?;
+        let operator = match tokens.get(*position) {
+            Some(operator) if is_binary_operator(operator) => operator.clone(),
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.calc_expression(tokens, position)?;
+        let result = match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (left as i64 & right as i64) as f64,
+            "|" => (left as i64 | right as i64) as f64,
+            "^" => (left as i64 ^ right as i64) as f64,
+            "<<" | ">>" => {
+                let count = u32::try_from(right as i64).map_err(|_| format!("can't shift by {}", right))?;
+                let shifted = if operator == "<<" {(left as i64).checked_shl(count)} else {(left as i64).checked_shr(count)};
+                shifted.ok_or_else(|| format!("can't shift by {}", right))? as f64
+            }
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            _ => (left != right) as i64 as f64,
+        };
+        Ok(result)
+    }
+
+    fn calc_term(&self, tokens: &[String], position: &mut usize) -> Result<f64, String> {
+        let token = match tokens.get(*position) {
+            Some(token) => token.clone(),
+            None => return Err("expression ends too early".to_string()),
+        };
+        *position += 1;
+
+        if token == "(" {
+            let value = self.calc_expression(tokens, position)?;
+            if tokens.get(*position).map(String::as_str) != Some(")") {
+                return Err("missing )".to_string());
+            }
+            *position += 1;
+            return Ok(value);
+        }
+        if is_unary_operator(&token) {
+            let value = self.calc_term(tokens, position)?;
+            let result = match token.as_str() {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => (value == 0.0) as i64 as f64,
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => if value == 0.0 {0.0} else {value.signum()},
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                // @ reads a byte that's already been compiled
+                _ => {
+                    let address = value as usize;
+                    if address < ORIGIN || address >= ORIGIN + self.rom.len() {
+                        return Err(format!("@ 0x{:X} is outside the compiled rom", address));
+                    }
+                    self.rom[address - ORIGIN] as f64
+                }
+            };
+            return Ok(result);
+        }
+        match token.as_str() {
+            "HERE" => return Ok(self.here as f64),
+            "PI" => return Ok(consts::PI),
+            "E" => return Ok(consts::E),
+            _ => (),
+        }
+        if let Some(value) = parse_number(&token) {
+            return Ok(value as f64);
+        }
+        if let Some(value) = self.constants.get(&token) {
+            return Ok(*value);
+        }
+        if let Some(address) = self.labels.get(&token) {
+            return Ok(*address as f64);
+        }
+        Err(format!("undefined name `{}` in expression", token))
+    }
+
+    // instructions that take a 12 bit address, labels further down get filled in at the end
+    fn address_instruction(&mut self, opcode: WORD) -> Result<(), String> {
+        let operand = self.next_required()?;
+        self.push_address_word(opcode, &operand)
+    }
+
+    fn push_address_word(&mut self, opcode: WORD, operand: &str) -> Result<(), String> {
+        match self.known_value(operand)? {
+            Some(value) => {
+                let address = self.check_range(value, 0xFFF)? as WORD;
+                self.emit_word(opcode | address)
+            }
+            None => {
+                self.fixups.push(Fixup { address: self.here, kind: FixupKind::Address, label: operand.to_string(), line: self.line });
+                self.emit_word(opcode)
+            }
+        }
+    }
+
+    fn register_instruction(&mut self, opcode: WORD) -> Result<(), String> {
+        let x = self.register()? as WORD;
+        self.emit_word(opcode | x << 8)
+    }
+
+    // numbers, constants and labels that are already defined, None for a name that isn't yet
+    fn known_value(&self, token: &str) -> Result<Option<i64>, String> {
+        if let Some(value) = parse_number(token) {
+            return Ok(Some(value));
+        }
+        if let Some(value) = self.constants.get(token) {
+            return Ok(Some(value.floor() as i64));
+        }
+        if let Some(address) = self.labels.get(token) {
+            return Ok(Some(*address as i64));
+        }
+        if is_name(token) && self.register_name(token).is_none() {
+            return Ok(None);
+        }
+        Err(format!("expected a value, found `{}`", token))
+    }
+
+    fn token_value(&self, token: &str) -> Result<i64, String> {
+        match self.known_value(token)? {
+            Some(value) => Ok(value),
+            None => Err(format!("undefined name `{}`", token)),
+        }
+    }
+
+    fn known_address(&mut self) -> Result<WORD, String> {
+        let token = self.next_required()?;
+        let value = self.token_value(&token)?;
+        Ok(self.check_range(value, 0xFFF)? as WORD)
+    }
+
+    fn value(&mut self) -> Result<i64, String> {
+        let token = self.next_required()?;
+        self.token_value(&token)
+    }
+
+    fn byte_value(&self, token: &str) -> Result<WORD, String> {
+        let value = self.token_value(token)?;
+        Ok(self.check_range(value, 0xFF)? as WORD & 0xFF)
+    }
+
+    fn byte(&mut self) -> Result<WORD, String> {
+        let token = self.next_required()?;
+        self.byte_value(&token)
+    }
+
+    fn nibble(&mut self) -> Result<WORD, String> {
+        let value = self.value()?;
+        Ok(self.check_range(value, 0xF)? as WORD)
+    }
+
+    // negative numbers are fine as long as they fit, they wrap like the hardware would
+    fn check_range(&self, value: i64, max: i64) -> Result<i64, String> {
+        if value > max || value < -(max + 1) / 2 {
+            return Err(format!("{} doesn't fit in 0x{:X}", value, max));
+        }
+        Ok(value & max)
+    }
+
+    fn register_name(&self, token: &str) -> Option<BYTE> {
+        if let Some(register) = self.aliases.get(token) {
+            return Some(*register);
+        }
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|x| x as BYTE),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<BYTE, String> {
+        let token = self.next_required()?;
+        self.register_name(&token).ok_or_else(|| format!("expected a register, found `{}`", token))
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next_required()?;
+        if !is_name(&token) || self.register_name(&token).is_some() {
+            return Err(format!("`{}` can't be used as a name", token));
+        }
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next_required()?;
+        if token != expected {
+            return Err(format!("expected `{}`, found `{}`", expected, token));
+        }
+        Ok(())
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn next_token(&mut self) -> Option<String> {
+        let token = self.tokens.pop_front()?;
+        self.line = token.line;
+        Some(token.text)
+    }
+
+    fn next_required(&mut self) -> Result<String, String> {
+        self.next_token().ok_or_else(|| "unexpected end of file".to_string())
+    }
+
+    fn emit_word(&mut self, word: WORD) -> Result<(), String> {
+        self.emit_byte((word >> 8) as BYTE)?;
+        self.emit_byte(word as BYTE)
+    }
+
+    fn emit_byte(&mut self, byte: BYTE) -> Result<(), String> {
+        if self.here >= MEMORY_SIZE {
+            return Err("program runs past the end of memory".to_string());
+        }
+        let index = self.here - ORIGIN;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+            self.written.resize(index + 1, false);
+        }
+        if self.written[index] {
+            return Err(format!("0x{:X} is written twice, check your :org", self.here));
+        }
+        self.rom[index] = byte;
+        self.written[index] = true;
+        self.here += 1;
+        Ok(())
+    }
+}
+
+// loops and if blocks jump with 1NNN, the same 12 bit limit as a jump to a label
+fn jump_target(address: usize) -> Result<WORD, String> {
+    if address > 0xFFF {
+        return Err(format!("a jump to 0x{:X} is past what a 12 bit address can reach", address));
+    }
+    Ok(address as WORD)
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        for text in line.split_whitespace() {
+            if text.starts_with('#') {
+                break;
+            }
+            tokens.push_back(Token { text: text.to_string(), line: index + 1 });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative {-value} else {value})
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn is_binary_operator(text: &str) -> bool {
+    matches!(text, "+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "pow" | "min" | "max"
+        | "<" | "<=" | ">" | ">=" | "==" | "!=")
+}
+
+fn is_unary_operator(text: &str) -> bool {
+    matches!(text, "-" | "~" | "!" | "sin" | "cos" | "tan" | "exp" | "log" | "abs" | "sqrt" | "sign" | "ceil" | "floor" | "@")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_8_emulator::Chip8Hardware;
+
+    // the rom as opcodes, starting with the jump to main
+    fn words(source: &str) -> Vec<WORD> {
+        let rom = compile(source).unwrap_or_else(|e| panic!("{}", e));
+        rom.chunks(2).map(|pair| (pair[0] as WORD) << 8 | *pair.get(1).unwrap_or(&0) as WORD).collect()
+    }
+
+    fn error(source: &str) -> String {
+        match compile(source) {
+            Err(Chip8Error::Assembly { message, .. }) => message,
+            other => panic!("expected a compile error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn if_then() {
+        assert_eq!(words(": main if v1 == 5 then v2 := 1"), [0x1202, 0x4105, 0x6201]);
+        assert_eq!(words(": main if v1 != v2 then v2 := 1"), [0x1202, 0x5120, 0x6201]);
+        assert_eq!(words(": main if v1 key then v2 := 1"), [0x1202, 0xE1A1, 0x6201]);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = ": main if v1 != v2 begin v3 := 1 else v3 := 2 end";
+        assert_eq!(words(source), [0x1202, 0x9120, 0x120A, 0x6301, 0x120C, 0x6302]);
+        assert_eq!(words(": main if v1 == 0 begin v3 := 1 end"), [0x1202, 0x3100, 0x1208, 0x6301]);
+        assert_eq!(error(": main else"), "else without a matching begin");
+        assert_eq!(error(": main if v1 == 0 begin"), "begin without a matching end");
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = ": main loop v1 += 1 while v1 != 10 again";
+        assert_eq!(words(source), [0x1202, 0x7101, 0x410A, 0x120A, 0x1202]);
+        assert_eq!(error(": main while v1 == 1"), "while outside of a loop");
+        assert_eq!(error(": main loop"), "loop without a matching again");
+    }
+
+    #[test]
+    fn comparisons_subtract_into_vf() {
+        assert_eq!(words(": main if v1 < v2 then v3 := 1"), [0x1202, 0x8F10, 0x8F25, 0x3F01, 0x6301]);
+        assert_eq!(words(": main if v1 > v2 then v3 := 1"), [0x1202, 0x8F20, 0x8F15, 0x3F01, 0x6301]);
+        assert_eq!(words(": main if v1 <= 7 then v3 := 1"), [0x1202, 0x6F07, 0x8F15, 0x3F00, 0x6301]);
+        assert_eq!(words(": main if v1 >= 7 then v3 := 1"), [0x1202, 0x6F07, 0x8F17, 0x3F00, 0x6301]);
+        assert_eq!(words(": main if v1 < 7 begin end"), [0x1202, 0x6F07, 0x8F17, 0x3F00, 0x120A]);
+    }
+
+    // every comparison against every pair of values, run on the emulator
+    #[test]
+    fn comparisons_agree_with_rust() {
+        for operator in ["<", ">", "<=", ">="] {
+            for (a, b) in [(0u8, 0u8), (3, 7), (7, 3), (255, 0), (0, 255)] {
+                let expected = match operator {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                };
+                for operand in [format!("{}", b), "v2".to_string()] {
+                    let source = format!(": main v1 := {} v2 := {} v3 := 0 if v1 {} {} then v3 := 1 loop again", a, b, operator, operand);
+                    let mut chip_8 = Chip8Hardware::new();
+                    chip_8.cpu_reset();
+                    chip_8.load_rom(&compile(&source).unwrap()).unwrap();
+                    chip_8.run_cycles(10).unwrap();
+                    assert_eq!(chip_8.get_registers()[3] == 1, expected, "{}", source);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn macros() {
+        let source = ":macro bump R AMOUNT { R += AMOUNT } : main bump v3 2 bump v4 0x10";
+        assert_eq!(words(source), [0x1202, 0x7302, 0x7410]);
+        assert_eq!(error(":macro forever { forever } : main forever"), "too many macro expansions, does `forever` call itself?");
+    }
+
+    #[test]
+    fn calc_and_const() {
+        let source = ":const BASE 4 :calc SIX { BASE + 2 } :calc HALF { SIX / 4 } : main v0 := SIX v1 := HALF";
+        assert_eq!(words(source), [0x1202, 0x6006, 0x6101]);
+        assert_eq!(words(":calc BIT { 1 << 3 } :calc BACK { BIT >> 2 } : main v0 := BIT v1 := BACK"), [0x1202, 0x6008, 0x6102]);
+        assert_eq!(error(":calc X { 1 << 64 } : main"), "can't shift by 64");
+        assert_eq!(error(":calc X { 1 >> -1 } : main"), "can't shift by -1");
+    }
+
+    #[test]
+    fn org_and_labels() {
+        let source = ": main i := data jump main :org 0x208 : data 0xAB 0xCD";
+        assert_eq!(words(source), [0x1202, 0xA208, 0x1202, 0x0000, 0xABCD]);
+        assert_eq!(error(": main :org 0x100"), ":org 0x100 is outside program memory");
+        assert_eq!(error(": main 0x01 :org 0x202 0x02"), "0x202 is written twice, check your :org");
+        assert_eq!(error("v0 := 1"), "this program does not define a main label");
+    }
+
+    #[test]
+    fn jumps_past_12_bits_are_errors() {
+        assert_eq!(error(": main jump far :org 0x1000 : far"), "`far` is at 0x1000, past what a 12 bit address can reach");
+        assert_eq!(error(": main :org 0x1000 loop again"), "a jump to 0x1000 is past what a 12 bit address can reach");
+        assert_eq!(error(": main :org 0xFFC if v0 == 0 begin v1 := 1 end"), "a jump to 0x1002 is past what a 12 bit address can reach");
+        assert_eq!(error(": main :org 0xFFC loop while v0 == 0 again"), "a jump to 0x1002 is past what a 12 bit address can reach");
+    }
+}
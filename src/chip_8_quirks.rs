@@ -0,0 +1,94 @@
+// What FX55 / FX65 do to I once the registers are stored or loaded. Three interpreters, three
+// answers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexIncrement {
+    // I stays where it was (SUPER-CHIP)
+    Unchanged,
+    // I ends up pointing at the last register touched (CHIP-48)
+    IncrementX,
+    // I ends up one past the last register touched (VIP)
+    IncrementXPlus1,
+}
+
+// The original interpreters disagree on what a handful of opcodes do, and roms were written
+// against whichever one the author had. Each flag here picks one side of one of those arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8XY6 / 8XYE shift VY into VX (VIP) instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    // where FX55 / FX65 leave I
+    pub load_store_index: IndexIncrement,
+    // BNNN is really BXNN, jumping to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // 8XY1 / 8XY2 / 8XY3 clear VF as a side effect (VIP)
+    pub logic_resets_vf: bool,
+    // DXYN cuts sprites off at the screen edge instead of wrapping them around
+    pub clip_sprites: bool,
+    // DXYN waits for the next 60hz frame before the cpu carries on (VIP)
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::IncrementXPlus1,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip_48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::IncrementX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::IncrementXPlus1,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    // names accepted wherever a preset can be picked by the user
+    pub const PRESET_NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "chip8" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip_48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::super_chip()),
+            "xochip" | "xo-chip" | "octo" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::cosmac_vip()
+    }
+}
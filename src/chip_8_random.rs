@@ -0,0 +1,166 @@
+use crate::chip_8_emulator::BYTE;
+
+// names accepted wherever a random source can be picked by the user
+pub const SOURCE_NAMES: [&str; 2] = ["xorshift", "vip"];
+
+// Where CXNN gets its numbers. Set one with Chip8Hardware::set_random_source. Everything a
+// source needs to carry on has to fit in `state`, save states and rewind store it so a loaded
+// state draws the same numbers the original run did.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> BYTE;
+    fn seed(&mut self, seed: u64);
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+pub fn source_from_name(name: &str, seed: u64) -> Option<Box<dyn RandomSource>> {
+    match name.to_lowercase().as_str() {
+        "xorshift" => Some(Box::new(Xorshift::new(seed))),
+        "vip" => Some(Box::new(CosmacVipRandom::new(seed))),
+        _ => None,
+    }
+}
+
+// xorshift64*, the default
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Xorshift {
+        let mut xorshift = Xorshift { state: 0 };
+        xorshift.seed(seed);
+        xorshift
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_byte(&mut self) -> BYTE {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        // the top bits of the multiply are the good ones
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as BYTE
+    }
+
+    fn seed(&mut self, seed: u64) {
+        // one splitmix64 step, so seeds next to each other still start far apart
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        self.set_state(z ^ (z >> 31));
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        // xorshift never leaves 0
+        self.state = if state == 0 {0x9E37_79B9_7F4A_7C15} else {state};
+    }
+}
+
+// Page 1 of the VIP's CHIP-8 interpreter, 0x0100-0x01FF, which its CXNN routine reads as a
+// table. Typed in from a disassembly listing, the last 15 bytes weren't in it and are left 0.
+const VIP_INTERPRETER_PAGE: [BYTE; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC, 0x22,
+    0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A, 0xF4,
+    0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA, 0x0A,
+    0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A, 0x0E,
+    0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F, 0x56,
+    0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17, 0x1A,
+    0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17, 0x1A,
+    0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA, 0x0F,
+    0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88, 0xD4,
+    0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88, 0xD4,
+    0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2, 0xFC,
+    0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A, 0xC4,
+    0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2, 0x56,
+    0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE, 0xF4,
+    0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F, 0xBA,
+    0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// The VIP interpreter's CXNN, which had no real generator. It keeps a 16 bit count in R9, bumps
+// it every call, adds the interpreter byte the low half points at to the high half, then adds
+// that to itself shifted right with the carry coming in on top. The result becomes the new high
+// half. Numbers come out streaky, the way roms written on a VIP saw them, and the same whatever
+// the rom does to memory.
+pub struct CosmacVipRandom {
+    r9: u16,
+}
+
+impl CosmacVipRandom {
+    pub fn new(seed: u64) -> CosmacVipRandom {
+        let mut random = CosmacVipRandom { r9: 0 };
+        random.seed(seed);
+        random
+    }
+}
+
+impl RandomSource for CosmacVipRandom {
+    fn next_byte(&mut self) -> BYTE {
+        self.r9 = self.r9.wrapping_add(1);
+        let (sum, carry) = ((self.r9 >> 8) as BYTE).overflowing_add(VIP_INTERPRETER_PAGE[(self.r9 & 0xFF) as usize]);
+        // SHRC, the carry from the add goes in at the top
+        let shifted = sum >> 1 | (carry as BYTE) << 7;
+        let result = shifted.wrapping_add(sum);
+        self.r9 = (result as u16) << 8 | (self.r9 & 0xFF);
+        result
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.set_state(seed);
+    }
+
+    fn state(&self) -> u64 {
+        self.r9 as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.r9 = state as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(source: &mut dyn RandomSource, count: usize) -> Vec<BYTE> {
+        (0..count).map(|_| source.next_byte()).collect()
+    }
+
+    #[test]
+    fn state_picks_up_where_it_left_off() {
+        for name in SOURCE_NAMES {
+            let mut source = source_from_name(name, 1234).unwrap();
+            draw(source.as_mut(), 100);
+            let state = source.state();
+            let expected = draw(source.as_mut(), 300);
+            source.set_state(state);
+            assert_eq!(draw(source.as_mut(), 300), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn vip_counts_through_the_interpreter_page() {
+        let mut random = CosmacVipRandom::new(0x12FF);
+        // R9 goes to 0x1300, 0x13 + the 0x00 at 0x0100 is 0x13, and 0x13 + 0x09 is 0x1C
+        assert_eq!(random.next_byte(), 0x1C);
+        assert_eq!(random.state(), 0x1C00);
+
+        // the high half carries the last number into the next one, so a wrap of the low half
+        // doesn't repeat the sequence
+        let mut random = CosmacVipRandom::new(0);
+        let first = draw(&mut random, 256);
+        assert_ne!(draw(&mut random, 256), first);
+    }
+
+    #[test]
+    fn xorshift_never_sticks_at_zero() {
+        let mut random = Xorshift::new(0);
+        random.set_state(0);
+        assert!(draw(&mut random, 16).iter().any(|&byte| byte != 0));
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+
+use crate::chip_8_emulator::Chip8Hardware;
+
+// Rewind history. Only the newest state is kept whole; every older frame is stored as the
+// xor of it and the frame after it, run length encoded. Consecutive frames barely differ so
+// the xor is almost all zeros and most frames squash down to a few dozen bytes.
+// Stepping back is newest ^ delta, and the oldest frame can be forgotten just by dropping its delta.
+pub struct RewindBuffer {
+    newest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>,
+    max_frames: usize,
+    max_bytes: usize,
+    // bytes held by deltas, newest isn't counted
+    used_bytes: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(max_frames: usize, max_bytes: usize) -> RewindBuffer {
+        RewindBuffer {
+            newest: Vec::new(),
+            deltas: VecDeque::new(),
+            max_frames,
+            max_bytes,
+            used_bytes: 0,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if !self.newest.is_empty() && self.newest.len() == state.len() {
+            let delta = compress_xor(&self.newest, &state);
+            self.used_bytes += delta.len();
+            self.deltas.push_back(delta);
+        } else {
+            // first frame, or the state layout changed under us, old deltas are useless now
+            self.clear();
+        }
+        self.newest = state;
+
+        while self.deltas.len() > self.max_frames || self.used_bytes > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.used_bytes -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    // takes the newest frame off and returns the one before it, which becomes the newest
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        self.used_bytes -= delta.len();
+        decompress_xor(&delta, &mut self.newest);
+        Some(&self.newest)
+    }
+
+    pub fn clear(&mut self) {
+        self.newest.clear();
+        self.deltas.clear();
+        self.used_bytes = 0;
+    }
+
+    // how many frames back we can go
+    pub fn len(&self) -> usize {
+        return self.deltas.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.deltas.is_empty();
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        return self.used_bytes + self.newest.len();
+    }
+}
+
+impl Chip8Hardware {
+    pub fn enable_rewind(&mut self, max_frames: usize, max_bytes: usize) {
+        let mut buffer = RewindBuffer::new(max_frames, max_bytes);
+        buffer.push(self.save_state());
+        self.rewind = Some(buffer);
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    pub fn get_rewind_buffer(&self) -> Option<&RewindBuffer> {
+        return self.rewind.as_ref();
+    }
+
+    // called by run_frame after every frame
+    pub(crate) fn record_rewind_frame(&mut self) {
+        if self.rewind.is_some() {
+            let state = self.save_state();
+            if let Some(buffer) = &mut self.rewind {
+                buffer.push(state);
+            }
+        }
+    }
+
+    // goes back one frame, false when there's no history left
+    pub fn rewind_frame(&mut self) -> bool {
+        let mut buffer = match self.rewind.take() {
+            Some(buffer) => buffer,
+            None => return false,
+        };
+
+        // keys are whatever the player is holding right now, not what they held back then
+        let keyboard = self.keyboard;
+        let rewound = match buffer.pop() {
+            Some(state) => self.load_state(state).is_ok(),
+            None => false,
+        };
+        self.keyboard = keyboard;
+        self.rewind = Some(buffer);
+        return rewound;
+    }
+}
+
+// xor of the two states as (zero run, literal length, literal bytes) groups, lengths as varints
+fn compress_xor(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < new.len() {
+        let zero_start = i;
+        while i < new.len() && old[i] == new[i] {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < new.len() && old[i] != new[i] {
+            i += 1;
+        }
+        write_varint(&mut out, literal_start - zero_start);
+        write_varint(&mut out, i - literal_start);
+        for j in literal_start..i {
+            out.push(old[j] ^ new[j]);
+        }
+    }
+    out
+}
+
+fn decompress_xor(delta: &[u8], state: &mut [u8]) {
+    let mut position = 0;
+    let mut i = 0;
+    while i < delta.len() {
+        let zeros = read_varint(delta, &mut i);
+        let literals = read_varint(delta, &mut i);
+        position += zeros;
+        for _ in 0..literals {
+            state[position] ^= delta[i];
+            position += 1;
+            i += 1;
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // states that differ from one to the next in a few places, like frames do
+    fn states(count: usize, size: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|n| {
+            let mut state = vec![0; size];
+            state[n % size] = n as u8 + 1;
+            state[size - 1] = n as u8;
+            state
+        }).collect()
+    }
+
+    fn round_trips(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let delta = compress_xor(old, new);
+        let mut forward = old.to_vec();
+        decompress_xor(&delta, &mut forward);
+        assert_eq!(forward, new);
+        let mut back = new.to_vec();
+        decompress_xor(&delta, &mut back);
+        assert_eq!(back, old);
+        delta
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let old: Vec<u8> = (0..1000).map(|n| (n * 7) as u8).collect();
+        // nothing changed is one zero run
+        assert_eq!(round_trips(&old, &old), [0xE8, 0x07, 0x00]);
+
+        let flipped: Vec<u8> = old.iter().map(|b| !b).collect();
+        let delta = round_trips(&old, &flipped);
+        assert_eq!(delta.len(), 1 + 2 + old.len());
+        assert_eq!(&delta[..4], [0x00, 0xE8, 0x07, 0xFF]);
+
+        // runs of 128 or more need two byte lengths, and over 16383 three
+        let mut long_runs = old.clone();
+        for b in &mut long_runs[300..500] {
+            *b ^= 0x55;
+        }
+        long_runs[999] ^= 1;
+        round_trips(&old, &long_runs);
+        let big = vec![0; 20_000];
+        let mut changed = big.clone();
+        changed[19_999] = 1;
+        assert_eq!(round_trips(&big, &changed), [0x9F, 0x9C, 0x01, 0x01, 0x01]);
+
+        assert_eq!(round_trips(&[], &[]), []);
+    }
+
+    #[test]
+    fn pop_gives_back_earlier_states_newest_first() {
+        let states = states(6, 64);
+        let mut buffer = RewindBuffer::new(100, 1 << 20);
+        assert_eq!(buffer.pop(), None);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 5);
+        for state in states[..5].iter().rev() {
+            assert_eq!(buffer.pop(), Some(state.as_slice()));
+        }
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+
+        // going back and then forward again carries on from where it went back to
+        buffer.push(states[3].clone());
+        assert_eq!(buffer.pop(), Some(states[0].as_slice()));
+
+        // a state of a different size can't be a delta, history starts again
+        buffer.push(states[1].clone());
+        buffer.push(vec![1; 10]);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.used_bytes(), 10);
+    }
+
+    #[test]
+    fn oldest_frames_go_first() {
+        let states = states(10, 64);
+        let mut buffer = RewindBuffer::new(3, 1 << 20);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 3);
+        for state in states[6..9].iter().rev() {
+            assert_eq!(buffer.pop(), Some(state.as_slice()));
+        }
+        assert_eq!(buffer.pop(), None);
+
+        // every delta here is the same size, so max_bytes fits a whole number of them
+        let delta = compress_xor(&states[0], &states[1]).len();
+        let mut buffer = RewindBuffer::new(100, delta * 4 + delta / 2);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.used_bytes(), delta * 4 + 64);
+        for state in states[5..9].iter().rev() {
+            assert_eq!(buffer.pop(), Some(state.as_slice()));
+        }
+        assert_eq!(buffer.pop(), None);
+
+        // too small for any delta still keeps the newest frame
+        let mut buffer = RewindBuffer::new(100, 0);
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.used_bytes(), 64);
+    }
+
+    #[test]
+    fn rewinding_the_chip_keeps_the_keys_held_now() {
+        let mut chip_8 = Chip8Hardware::new();
+        chip_8.cpu_reset();
+        // count frames in v0, waiting on the delay timer each time round
+        chip_8.load_rom(&[0x70, 0x01, 0x61, 0x01, 0xF1, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x06, 0x12, 0x00]).unwrap();
+        assert!(!chip_8.rewind_frame());
+        chip_8.enable_rewind(60, 1 << 20);
+
+        let mut saved = vec![chip_8.save_state()];
+        for _ in 0..5 {
+            chip_8.run_frame().unwrap();
+            saved.push(chip_8.save_state());
+        }
+        assert_eq!(chip_8.get_rewind_buffer().unwrap().len(), 5);
+
+        chip_8.keyboard[0xA] = true;
+        for frame in (0..5).rev() {
+            assert!(chip_8.rewind_frame());
+            assert!(chip_8.keyboard[0xA]);
+            chip_8.keyboard[0xA] = false;
+            assert_eq!(chip_8.save_state(), saved[frame]);
+            chip_8.keyboard[0xA] = true;
+        }
+        assert!(!chip_8.rewind_frame());
+        assert!(chip_8.keyboard[0xA]);
+    }
+}
@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use crate::chip_8_emulator::{Chip8Hardware, BYTE, WORD, ALL_PLANES, AUDIO_PATTERN_SIZE, HIRES_HEIGHT, HIRES_WIDTH, MEMORY_SIZE};
+use crate::chip_8_error::Chip8Error;
+
+// every save state starts with this, then a version number
+const MAGIC: &[u8; 4] = b"C8SS";
+// bump this whenever the layout below changes, old files get rejected instead of loading garbage
+pub const SAVE_STATE_VERSION: u16 = 2;
+
+// Save states hold the machine, not the settings: quirks, cpu speed and which random source CXNN
+// uses come from whoever is running the rom, so loading a state never changes how the emulator
+// behaves. The random source's state is machine though, so replays draw the same numbers.
+impl Chip8Hardware {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter { data: Vec::with_capacity(MEMORY_SIZE + HIRES_WIDTH * HIRES_HEIGHT + 256) };
+        out.bytes(MAGIC);
+        out.word(SAVE_STATE_VERSION);
+
+        for register in self.registers.iter() {
+            out.byte(*register);
+        }
+        out.word(self.address_i);
+        out.word(self.program_counter);
+        for entry in self.stack.iter() {
+            out.word(*entry);
+        }
+        out.byte(self.stack_pointer as BYTE);
+        out.byte(self.delay_timer);
+        out.byte(self.sound_timer);
+        out.long(self.timer_remainder.as_nanos() as u64);
+        for key in self.keyboard.iter() {
+            out.flag(*key);
+        }
+
+        out.flag(self.high_resolution);
+        out.byte(self.selected_planes);
+        out.flag(self.halted);
+        out.flag(self.waiting_for_vblank);
+        out.bytes(&self.rpl_flags);
+        out.bytes(&self.audio_pattern);
+        out.byte(self.audio_pitch);
+        out.long(self.random.state());
+
+        for row in self.screen_data.iter() {
+            out.bytes(row);
+        }
+        out.bytes(&self.memory);
+        out.data
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        // read everything into a copy first so a bad file leaves the running machine alone
+        let mut input = StateReader { data, position: 0 };
+        if input.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        let version = input.word()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(Chip8Error::InvalidSaveState { reason: format!("version {} isn't supported, expected {}", version, SAVE_STATE_VERSION) });
+        }
+
+        let mut registers = [0; 16];
+        for register in registers.iter_mut() {
+            *register = input.byte()?;
+        }
+        let address_i = input.word()?;
+        let program_counter = input.word()?;
+        let mut stack = [0; 16];
+        for entry in stack.iter_mut() {
+            *entry = input.word()?;
+        }
+        let stack_pointer = input.byte()? as usize;
+        if stack_pointer > stack.len() {
+            return Err(invalid("stack pointer past the end of the stack"));
+        }
+        let delay_timer = input.byte()?;
+        let sound_timer = input.byte()?;
+        let timer_remainder = Duration::from_nanos(input.long()?);
+        let mut keyboard = [false; 16];
+        for key in keyboard.iter_mut() {
+            *key = input.flag()?;
+        }
+
+        let high_resolution = input.flag()?;
+        let selected_planes = input.byte()?;
+        if selected_planes > ALL_PLANES {
+            return Err(invalid("selected planes past the last plane"));
+        }
+        let halted = input.flag()?;
+        let waiting_for_vblank = input.flag()?;
+        let mut rpl_flags = [0; 16];
+        rpl_flags.copy_from_slice(input.bytes(16)?);
+        let mut audio_pattern = [0; AUDIO_PATTERN_SIZE];
+        audio_pattern.copy_from_slice(input.bytes(AUDIO_PATTERN_SIZE)?);
+        let audio_pitch = input.byte()?;
+        let random_state = input.long()?;
+
+        let mut screen_data = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+        for row in screen_data.iter_mut() {
+            row.copy_from_slice(input.bytes(HIRES_WIDTH)?);
+        }
+        // the frontend picks pixel colors by these, anything bigger has nothing to index
+        if screen_data.iter().flatten().any(|&planes| planes > ALL_PLANES) {
+            return Err(invalid("screen pixel lit in a plane that doesn't exist"));
+        }
+        let memory = input.bytes(MEMORY_SIZE)?;
+        if input.position != data.len() {
+            return Err(invalid("extra data after the end of the state"));
+        }
+
+        self.registers = registers;
+        self.address_i = address_i;
+        self.program_counter = program_counter;
+        self.stack = stack;
+        self.stack_pointer = stack_pointer;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.timer_remainder = timer_remainder;
+        self.keyboard = keyboard;
+        self.high_resolution = high_resolution;
+        self.selected_planes = selected_planes;
+        self.halted = halted;
+        self.waiting_for_vblank = waiting_for_vblank;
+        // whatever is on screen now is stale, make sure the frontend redraws
+        self.draw_enabled = true;
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = audio_pattern;
+        self.audio_pitch = audio_pitch;
+        self.random.set_state(random_state);
+        self.screen_data = screen_data;
+        self.memory.copy_from_slice(memory);
+        self.clear_decode_cache();
+        Ok(())
+    }
+}
+
+fn invalid(reason: &str) -> Chip8Error {
+    Chip8Error::InvalidSaveState { reason: reason.to_string() }
+}
+
+// everything is stored big endian, same as the chip-8 itself
+struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    fn byte(&mut self, value: BYTE) {
+        self.data.push(value);
+    }
+
+    fn flag(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    fn word(&mut self, value: WORD) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn long(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn bytes(&mut self, values: &[u8]) {
+        self.data.extend_from_slice(values);
+    }
+}
+
+struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.position + count > self.data.len() {
+            return Err(invalid("file ends early"));
+        }
+        let slice = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<BYTE, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn flag(&mut self) -> Result<bool, Chip8Error> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("bad true/false value")),
+        }
+    }
+
+    fn word(&mut self) -> Result<WORD, Chip8Error> {
+        let bytes = self.bytes(2)?;
+        Ok(WORD::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn long(&mut self) -> Result<u64, Chip8Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Chip8Hardware {
+        let mut chip_8 = Chip8Hardware::new();
+        chip_8.cpu_reset();
+        // v0 := 5, i := the font 5, draw it at v1,v1 which is 0,0, then spin
+        chip_8.load_rom(&[0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]).unwrap();
+        for _ in 0..4 {
+            chip_8.step().unwrap();
+        }
+        chip_8
+    }
+
+    // the offset of the one byte that differs between two states
+    fn offset_of_difference(a: &[u8], b: &[u8]) -> usize {
+        let differences: Vec<usize> = (0..a.len()).filter(|&i| a[i] != b[i]).collect();
+        assert_eq!(differences.len(), 1);
+        differences[0]
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut original = machine();
+        original.set_delay_timer(30);
+        original.rpl_flags[3] = 7;
+        original.random.set_state(12345);
+        let state = original.save_state();
+
+        let mut loaded = Chip8Hardware::new();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.get_registers(), original.get_registers());
+        assert_eq!(loaded.get_program_counter(), 0x206);
+        assert_eq!(loaded.get_delay_timer(), 30);
+        assert_eq!(loaded.random.state(), 12345);
+        assert_eq!(loaded.get_pixel_planes_x_y(0, 0), 1);
+    }
+
+    #[test]
+    fn bad_plane_numbers_are_rejected() {
+        let mut chip_8 = machine();
+        let state = chip_8.save_state();
+        chip_8.selected_planes = 2;
+        let offset = offset_of_difference(&state, &chip_8.save_state());
+
+        let mut corrupt = state.clone();
+        corrupt[offset] = 4;
+        let mut loaded = Chip8Hardware::new();
+        assert!(matches!(loaded.load_state(&corrupt), Err(Chip8Error::InvalidSaveState { .. })));
+
+        // the screen sits right before memory, pixel 0,0 is lit
+        let mut corrupt = state.clone();
+        let pixel = state.len() - MEMORY_SIZE - HIRES_WIDTH * HIRES_HEIGHT;
+        assert_eq!(corrupt[pixel], 1);
+        corrupt[pixel] = 0xFF;
+        assert!(matches!(loaded.load_state(&corrupt), Err(Chip8Error::InvalidSaveState { .. })));
+        // and a failed load leaves the machine alone
+        assert_eq!(loaded.get_pixel_planes_x_y(0, 0), 0);
+    }
+
+    #[test]
+    fn truncated_and_mislabelled_states_are_rejected() {
+        let state = machine().save_state();
+        let mut loaded = Chip8Hardware::new();
+        assert!(loaded.load_state(&state[..state.len() - 1]).is_err());
+        assert!(loaded.load_state(&[state.as_slice(), &[0]].concat()).is_err());
+        assert!(loaded.load_state(b"C8SX").is_err());
+
+        let mut old_version = state.clone();
+        old_version[5] = 1;
+        assert!(loaded.load_state(&old_version).is_err());
+    }
+}
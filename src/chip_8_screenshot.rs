@@ -0,0 +1,292 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::chip_8_emulator::{Chip8Hardware, BYTE};
+use crate::chip_8_error::Chip8Error;
+
+pub type Rgb = [BYTE; 3];
+
+// colors for each combination of the two xo-chip planes, plain chip-8 only uses the first two
+pub const DEFAULT_PALETTE: [Rgb; 4] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+];
+
+// biggest block a stored (uncompressed) deflate block can hold
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    // black and white, anything that isn't the background color comes out black
+    Pbm,
+}
+
+impl ImageFormat {
+    // picked from the file extension
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pbm" => Some(ImageFormat::Pbm),
+            _ => None,
+        }
+    }
+}
+
+// an RGB picture, rows top to bottom
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+    // the color of unlit pixels, what PBM treats as white
+    pub background: Rgb,
+}
+
+// Draws the display the way it is right now, whatever resolution it's in, every chip-8 pixel
+// becoming a scale x scale square. The palette is indexed by which planes are lit, like the
+// frontend does.
+pub fn render(chip_8: &Chip8Hardware, scale: usize, palette: &[Rgb; 4]) -> Image {
+    let scale = scale.max(1);
+    let width = chip_8.get_screen_width() * scale;
+    let height = chip_8.get_screen_height() * scale;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let planes = chip_8.get_pixel_planes_x_y((y / scale) as u16, (x / scale) as u16);
+            pixels.push(palette[planes as usize & 3]);
+        }
+    }
+    Image { width, height, pixels, background: palette[0] }
+}
+
+// renders the display and writes it to path in the format its extension asks for
+pub fn save_screenshot(chip_8: &Chip8Hardware, path: &str, scale: usize, palette: &[Rgb; 4]) -> Result<(), Chip8Error> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| Chip8Error::Io(io::Error::new(
+        io::ErrorKind::InvalidInput, format!("{} should end in .png or .pbm", path))))?;
+    fs::write(path, render(chip_8, scale, palette).encode(format))?;
+    Ok(())
+}
+
+impl Image {
+    pub fn encode(&self, format: ImageFormat) -> Vec<BYTE> {
+        match format {
+            ImageFormat::Png => self.to_png(),
+            ImageFormat::Pbm => self.to_pbm(),
+        }
+    }
+
+    // 8 bit RGB, no compression, the deflate stream is just stored blocks so nothing outside
+    // std is needed
+    pub fn to_png(&self) -> Vec<BYTE> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            // filter type 0, none
+            raw.push(0);
+            for pixel in row {
+                raw.extend_from_slice(pixel);
+            }
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // bit depth 8, color type 2 (RGB), then default compression, filtering and no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    // raw PBM (P4), a bit per pixel, rows padded to whole bytes, 1 is black
+    pub fn to_pbm(&self) -> Vec<BYTE> {
+        let mut pbm = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        for row in self.pixels.chunks(self.width.max(1)) {
+            for pixels in row.chunks(8) {
+                let mut byte = 0;
+                for (bit, pixel) in pixels.iter().enumerate() {
+                    if *pixel != self.background {
+                        byte |= 0x80 >> bit;
+                    }
+                }
+                pbm.push(byte);
+            }
+        }
+        pbm
+    }
+}
+
+fn write_chunk(png: &mut Vec<BYTE>, kind: &[BYTE; 4], data: &[BYTE]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[BYTE]) -> Vec<BYTE> {
+    // deflate, 32k window, no preset dictionary, fastest
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[BYTE]> = if data.is_empty() {vec![&[]]} else {data.chunks(STORED_BLOCK_SIZE).collect()};
+    for (index, block) in blocks.iter().enumerate() {
+        zlib.push(if index + 1 == blocks.len() {1} else {0});
+        let length = block.len() as u16;
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[BYTE]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {(crc >> 1) ^ 0xEDB8_8320} else {crc >> 1};
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[BYTE]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+// four comma separated RRGGBB colors, background first
+pub fn parse_palette(text: &str) -> Result<[Rgb; 4], String> {
+    let colors: Vec<&str> = text.split(',').map(|c| c.trim().trim_start_matches('#')).collect();
+    if colors.len() != 4 {
+        return Err(format!("--palette needs 4 colors, got {}", colors.len()));
+    }
+
+    let mut palette = DEFAULT_PALETTE;
+    for (i, color) in colors.iter().enumerate() {
+        let rgb = u32::from_str_radix(color, 16).ok().filter(|_| color.len() == 6)
+            .ok_or_else(|| format!("`{}` isn't a RRGGBB color", color))?;
+        palette[i] = [(rgb >> 16) as BYTE, (rgb >> 8) as BYTE, rgb as BYTE];
+    }
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    use crate::chip_8_golden::Frame;
+
+    fn image(width: usize, height: usize, lit: impl Fn(usize, usize) -> bool) -> Image {
+        let pixels = (0..width * height)
+            .map(|index| DEFAULT_PALETTE[lit(index % width, index / width) as usize])
+            .collect();
+        Image { width, height, pixels, background: DEFAULT_PALETTE[0] }
+    }
+
+    // the chunks of a png as (kind, data), checking each one's crc on the way
+    fn chunks(png: &[BYTE]) -> Vec<(String, Vec<BYTE>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1A\n");
+        let mut chunks = Vec::new();
+        let mut position = 8;
+        while position < png.len() {
+            let length = u32::from_be_bytes(png[position..position + 4].try_into().unwrap()) as usize;
+            let body = &png[position + 4..position + 8 + length];
+            let crc = u32::from_be_bytes(png[position + 8 + length..position + 12 + length].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            chunks.push((String::from_utf8_lossy(&body[..4]).into_owned(), body[4..].to_vec()));
+            position += 12 + length;
+        }
+        chunks
+    }
+
+    #[test]
+    fn checksums_match_the_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+        // big enough that both sums wrap round the modulus
+        assert_eq!(adler32(&[0xFF; 6000]), 0xA497_59EA);
+    }
+
+    #[test]
+    fn big_images_split_into_stored_blocks() {
+        let image = image(256, 128, |x, y| (x ^ y) & 1 == 1);
+        let png = image.to_png();
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+
+        let zlib = &chunks[1].1;
+        assert_eq!(&zlib[..2], [0x78, 0x01]);
+        let mut position = 2;
+        let mut raw = Vec::new();
+        let mut lengths = Vec::new();
+        loop {
+            let last = zlib[position];
+            let length = u16::from_le_bytes([zlib[position + 1], zlib[position + 2]]);
+            let inverse = u16::from_le_bytes([zlib[position + 3], zlib[position + 4]]);
+            assert_eq!(inverse, !length);
+            raw.extend_from_slice(&zlib[position + 5..position + 5 + length as usize]);
+            lengths.push(length as usize);
+            position += 5 + length as usize;
+            if last == 1 {
+                break;
+            }
+            assert_eq!(last, 0);
+        }
+        let expected = (256 * 3 + 1) * 128;
+        assert_eq!(lengths, [STORED_BLOCK_SIZE, expected - STORED_BLOCK_SIZE]);
+        assert_eq!(raw.len(), expected);
+        assert_eq!(&zlib[position..], adler32(&raw).to_be_bytes());
+        // row 1 starts with filter 0 then a lit pixel
+        assert_eq!(&raw[256 * 3 + 1..256 * 3 + 5], [0, 0xFF, 0xFF, 0xFF]);
+
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn pbm_rows_are_padded_to_whole_bytes() {
+        let image = image(10, 2, |x, y| x == y || x == 9);
+        let mut expected = b"P4\n10 2\n".to_vec();
+        expected.extend_from_slice(&[0b1000_0000, 0b0100_0000, 0b0100_0000, 0b0100_0000]);
+        assert_eq!(image.to_pbm(), expected);
+    }
+
+    #[test]
+    fn screenshots_read_back_as_the_same_frame() {
+        let mut chip_8 = Chip8Hardware::new();
+        chip_8.cpu_reset();
+        // the font's 0 at 3,5 and its 8 at the right edge, so it wraps
+        chip_8.load_rom(&[0x60, 0x03, 0x61, 0x05, 0xF2, 0x29, 0xD0, 0x15, 0x60, 0x3E, 0x62, 0x08, 0xF2, 0x29, 0xD0, 0x15, 0x12, 0x10]).unwrap();
+        chip_8.run_frame().unwrap();
+
+        let frame = Frame::capture(&chip_8);
+        assert!(frame.pixels.iter().flatten().any(|&planes| planes != 0));
+        for scale in [1, 3] {
+            let pbm = render(&chip_8, scale, &DEFAULT_PALETTE).encode(ImageFormat::Pbm);
+            let read = Frame::parse_pbm(&pbm).unwrap();
+            assert_eq!((read.width, read.height), (frame.width * scale, frame.height * scale));
+            for (y, row) in read.pixels.iter().enumerate() {
+                for (x, &lit) in row.iter().enumerate() {
+                    assert_eq!(lit != 0, frame.pixels[y / scale][x / scale] != 0, "{},{} at scale {}", x, y, scale);
+                }
+            }
+        }
+    }
+}
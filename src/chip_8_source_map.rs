@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+use crate::chip_8_cli::parse_hex;
+use crate::chip_8_emulator::WORD;
+use crate::chip_8_error::Chip8Error;
+
+// a run of rom bytes that came from one line of source
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedLine {
+    pub address: WORD,
+    pub length: usize,
+    pub file: String,
+    pub line: usize,
+}
+
+// Which source line every part of a rom came from, and where the labels ended up, so debuggers
+// can show source and put breakpoints on lines. The assembler and the octo compiler both make
+// one, and it can be saved as a .map file next to a rom that gets loaded already built.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    // in address order
+    lines: Vec<MappedLine>,
+    labels: BTreeMap<String, WORD>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn add_line(&mut self, address: WORD, length: usize, file: &str, line: usize) {
+        if length == 0 {
+            return;
+        }
+        let index = self.lines.partition_point(|mapped| mapped.address <= address);
+        self.lines.insert(index, MappedLine { address, length, file: file.to_string(), line });
+    }
+
+    pub fn add_label(&mut self, name: &str, address: WORD) {
+        self.labels.insert(name.to_string(), address);
+    }
+
+    pub fn lines(&self) -> &[MappedLine] {
+        &self.lines
+    }
+
+    pub fn labels(&self) -> &BTreeMap<String, WORD> {
+        &self.labels
+    }
+
+    // the line that produced the byte at address
+    pub fn line_at(&self, address: WORD) -> Option<&MappedLine> {
+        let index = self.lines.partition_point(|mapped| mapped.address <= address);
+        let mapped = self.lines[..index].last()?;
+        if (address as usize) < mapped.address as usize + mapped.length {
+            Some(mapped)
+        } else {
+            None
+        }
+    }
+
+    // Where a breakpoint on `line` of `file` should go. Lines without any code (comments,
+    // labels on their own) move down to the next line that has some, like most debuggers do.
+    // `same_file` decides whether a file name in the map is the one being asked about.
+    pub fn address_of_line<F: Fn(&str) -> bool>(&self, same_file: F, line: usize) -> Option<&MappedLine> {
+        self.lines.iter()
+            .filter(|mapped| mapped.line >= line && same_file(&mapped.file))
+            .min_by_key(|mapped| (mapped.line, mapped.address))
+    }
+
+    // the label at or closest before address, for naming stack frames
+    pub fn label_before(&self, address: WORD) -> Option<(&str, WORD)> {
+        self.labels.iter()
+            .filter(|(_, &label_address)| label_address <= address)
+            .max_by_key(|(_, &label_address)| label_address)
+            .map(|(name, &label_address)| (name.as_str(), label_address))
+    }
+
+    pub fn parse(file: &str, text: &str) -> Result<SourceMap, Chip8Error> {
+        let mut map = SourceMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| Chip8Error::Assembly { file: file.to_string(), line: index + 1, message: message.to_string() };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(5, ' ');
+            match fields.next() {
+                Some("label") => {
+                    let address = fields.next().and_then(parse_address).ok_or_else(|| error("expected `label <address> <name>`"))?;
+                    let name = fields.next().ok_or_else(|| error("expected `label <address> <name>`"))?;
+                    map.add_label(name, address);
+                }
+                Some("line") => {
+                    let expected = "expected `line <address> <length> <line> <file>`";
+                    let address = fields.next().and_then(parse_address).ok_or_else(|| error(expected))?;
+                    let length = fields.next().and_then(|text| text.parse().ok()).ok_or_else(|| error(expected))?;
+                    let number = fields.next().and_then(|text| text.parse().ok()).ok_or_else(|| error(expected))?;
+                    let source = fields.next().ok_or_else(|| error(expected))?;
+                    map.add_line(address, length, source, number);
+                }
+                _ => return Err(error("expected a `label` or `line` entry")),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &str) -> Result<SourceMap, Chip8Error> {
+        SourceMap::parse(path, &fs::read_to_string(path)?)
+    }
+}
+
+fn parse_address(text: &str) -> Option<WORD> {
+    parse_hex(text).filter(|address| *address <= 0xFFFF).map(|address| address as WORD)
+}
+
+// the .map file format, one entry a line, file names go last so they can have spaces in them
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# chip-8 source map")?;
+        for (name, address) in &self.labels {
+            writeln!(f, "label {:04X} {}", address, name)?;
+        }
+        for mapped in &self.lines {
+            writeln!(f, "line {:04X} {} {} {}", mapped.address, mapped.length, mapped.line, mapped.file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SourceMap {
+        let mut map = SourceMap::new();
+        map.add_line(0x204, 2, "game.8o", 7);
+        map.add_line(0x200, 4, "game.8o", 3);
+        map.add_line(0x206, 0, "game.8o", 8);
+        map.add_line(0x300, 6, "my sprites.8o", 1);
+        map.add_label("main", 0x200);
+        map.add_label("draw", 0x204);
+        map
+    }
+
+    #[test]
+    fn saved_maps_parse_back_the_same() {
+        let map = sample();
+        let text = map.to_string();
+        assert!(text.starts_with("# chip-8 source map\n"));
+        assert!(text.contains("line 0300 6 1 my sprites.8o\n"));
+        assert_eq!(SourceMap::parse("game.map", &text).unwrap(), map);
+        assert_eq!(SourceMap::parse("empty.map", &SourceMap::new().to_string()).unwrap(), SourceMap::new());
+    }
+
+    #[test]
+    fn lines_are_found_by_address_and_by_line() {
+        let map = sample();
+        let lines: Vec<_> = map.lines().iter().map(|mapped| mapped.address).collect();
+        assert_eq!(lines, [0x200, 0x204, 0x300]);
+
+        assert_eq!(map.line_at(0x203).map(|mapped| mapped.line), Some(3));
+        assert_eq!(map.line_at(0x205).map(|mapped| mapped.line), Some(7));
+        assert_eq!(map.line_at(0x206), None);
+        assert_eq!(map.line_at(0x1FF), None);
+
+        let in_game = |file: &str| file == "game.8o";
+        assert_eq!(map.address_of_line(in_game, 3).map(|mapped| mapped.address), Some(0x200));
+        assert_eq!(map.address_of_line(in_game, 4).map(|mapped| mapped.address), Some(0x204));
+        assert_eq!(map.address_of_line(in_game, 8), None);
+        assert_eq!(map.address_of_line(|file| file == "my sprites.8o", 1).map(|mapped| mapped.address), Some(0x300));
+
+        assert_eq!(map.label_before(0x203), Some(("main", 0x200)));
+        assert_eq!(map.label_before(0x304), Some(("draw", 0x204)));
+        assert_eq!(map.label_before(0x100), None);
+    }
+
+    #[test]
+    fn bad_entries_are_errors() {
+        for text in ["labl 0200 main", "label main", "label 0200", "line 0200 2 3", "line 0200 x 3 a.8o"] {
+            match SourceMap::parse("bad.map", &format!("# chip-8 source map\n\n{}\n", text)) {
+                Err(Chip8Error::Assembly { file, line, .. }) => assert_eq!((file.as_str(), line), ("bad.map", 3), "{}", text),
+                other => panic!("`{}` gave {:?}", text, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::chip_8_cli::parse_hex;
+use crate::chip_8_debugger::OpcodePattern;
+use crate::chip_8_emulator::{Chip8Hardware, BYTE, WORD};
+use crate::chip_8_error::Chip8Error;
+use crate::chip_8_instruction::Instruction;
+use crate::chip_8_observer::Location;
+
+// start of every binary trace, the last byte is the format version
+const BINARY_MAGIC: [u8; 4] = *b"C8T\x01";
+// tags for what changed in a binary record, registers are 0x0-0xF
+const TAG_ADDRESS_I: u8 = 0x10;
+const TAG_DELAY_TIMER: u8 = 0x11;
+const TAG_SOUND_TIMER: u8 = 0x12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // one line per instruction, made for diff
+    Text,
+    // the same records packed into a few bytes each, chip8-trace turns them back into text
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+// Which instructions make it into the trace, everything if nothing's set. Frames count the
+// 60hz timer ticks since tracing started.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<(WORD, WORD)>,
+    pub opcodes: Vec<OpcodePattern>,
+    pub frames: Option<(u64, u64)>,
+}
+
+impl TraceFilter {
+    // `200-2FF`, hex like every other address, both ends included
+    pub fn parse_addresses(text: &str) -> Option<(WORD, WORD)> {
+        let (start, end) = parse_range(text, |digits| parse_hex(digits).map(|value| value as u64))?;
+        Some((start.min(0xFFFF) as WORD, end.min(0xFFFF) as WORD))
+    }
+
+    // `120-300` in decimal, either end can be left off
+    pub fn parse_frames(text: &str) -> Option<(u64, u64)> {
+        parse_range(text, |digits| digits.parse().ok())
+    }
+
+    // comma separated opcode patterns like `D???,Fx55`
+    pub fn parse_opcodes(text: &str) -> Option<Vec<OpcodePattern>> {
+        text.split(',').map(|pattern| OpcodePattern::parse(pattern.trim())).collect()
+    }
+
+    pub fn matches(&self, frame: u64, address: WORD, instruction: &Instruction) -> bool {
+        let in_range = |range: Option<(u64, u64)>, value: u64| range.is_none_or(|(start, end)| start <= value && value <= end);
+        in_range(self.frames, frame)
+            && in_range(self.addresses.map(|(start, end)| (start as u64, end as u64)), address as u64)
+            && (self.opcodes.is_empty() || self.opcodes.iter().any(|pattern| pattern.matches(instruction.encode())))
+    }
+}
+
+fn parse_range(text: &str, parse: fn(&str) -> Option<u64>) -> Option<(u64, u64)> {
+    let (start, end) = text.split_once('-')?;
+    let bound = |text: &str, default: u64| match text.trim() {
+        "" => Some(default),
+        digits => parse(digits),
+    };
+    let range = (bound(start, 0)?, bound(end, u64::MAX)?);
+    if range.0 > range.1 {
+        return None;
+    }
+    Some(range)
+}
+
+// One executed instruction and whatever registers, I or timers it changed, with their new values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub frame: u64,
+    pub address: WORD,
+    pub instruction: Instruction,
+    pub changes: Vec<(Location, WORD)>,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = match self.instruction {
+            Instruction::LoadILong(long) => format!("F000 {:04X}", long),
+            _ => format!("{:04X}", self.instruction.encode()),
+        };
+        write!(f, "{:>6} {:03X} {:<9} {}", self.frame, self.address, raw, self.instruction)?;
+        if !self.changes.is_empty() {
+            // line the changes up in a column after the mnemonic
+            write!(f, "{:width$}", "", width = 18usize.saturating_sub(self.instruction.to_string().len()))?;
+        }
+        for (location, value) in &self.changes {
+            match location {
+                Location::AddressI => write!(f, " {}={:03X}", location, value)?,
+                _ => write!(f, " {}={:02X}", location, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// the parts of the machine a record compares before and after an instruction
+#[derive(Clone, Copy)]
+pub(crate) struct TraceSnapshot {
+    registers: [BYTE; 16],
+    address_i: WORD,
+    delay_timer: BYTE,
+    sound_timer: BYTE,
+}
+
+impl TraceSnapshot {
+    pub(crate) fn take(chip_8: &Chip8Hardware) -> TraceSnapshot {
+        TraceSnapshot {
+            registers: chip_8.registers,
+            address_i: chip_8.address_i,
+            delay_timer: chip_8.delay_timer,
+            sound_timer: chip_8.sound_timer,
+        }
+    }
+
+    fn changes(&self, after: &TraceSnapshot) -> Vec<(Location, WORD)> {
+        let mut changes = Vec::new();
+        for index in 0..16 {
+            if self.registers[index] != after.registers[index] {
+                changes.push((Location::Register(index as BYTE), after.registers[index] as WORD));
+            }
+        }
+        if self.address_i != after.address_i {
+            changes.push((Location::AddressI, after.address_i));
+        }
+        if self.delay_timer != after.delay_timer {
+            changes.push((Location::DelayTimer, after.delay_timer as WORD));
+        }
+        if self.sound_timer != after.sound_timer {
+            changes.push((Location::SoundTimer, after.sound_timer as WORD));
+        }
+        changes
+    }
+}
+
+// Writes a record for every instruction the chip runs that gets past the filter. Hand one to
+// Chip8Hardware::enable_trace, tracing a run and diffing it against another shows where they split.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    frame: u64,
+    wrote_header: bool,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, format: TraceFormat, filter: TraceFilter) -> Tracer {
+        Tracer { writer, format, filter, frame: 0, wrote_header: false }
+    }
+
+    pub fn get_frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub(crate) fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub(crate) fn record(&mut self, address: WORD, instruction: Instruction, before: &TraceSnapshot, after: &TraceSnapshot) -> Result<(), Chip8Error> {
+        if !self.filter.matches(self.frame, address, &instruction) {
+            return Ok(());
+        }
+        let record = TraceRecord { frame: self.frame, address, instruction, changes: before.changes(after) };
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", record)?,
+            TraceFormat::Binary => {
+                if !self.wrote_header {
+                    self.writer.write_all(&BINARY_MAGIC)?;
+                    self.wrote_header = true;
+                }
+                self.writer.write_all(&encode_record(&record))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Chip8Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// frame u32, address u16, opcode u16 (and the address word for F000), change count u8,
+// then a tag u8 and new value u16 per change, everything little endian
+fn encode_record(record: &TraceRecord) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(16);
+    bytes.extend_from_slice(&(record.frame as u32).to_le_bytes());
+    bytes.extend_from_slice(&record.address.to_le_bytes());
+    bytes.extend_from_slice(&record.instruction.encode().to_le_bytes());
+    if let Instruction::LoadILong(long) = record.instruction {
+        bytes.extend_from_slice(&long.to_le_bytes());
+    }
+    bytes.push(record.changes.len() as u8);
+    for (location, value) in &record.changes {
+        bytes.push(match location {
+            Location::Register(index) => *index,
+            Location::AddressI => TAG_ADDRESS_I,
+            Location::DelayTimer => TAG_DELAY_TIMER,
+            Location::SoundTimer => TAG_SOUND_TIMER,
+            Location::Memory(_) => unreachable!("traces don't record memory"),
+        });
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn is_binary_trace(data: &[u8]) -> bool {
+    data.starts_with(&BINARY_MAGIC)
+}
+
+// back from the binary format, for chip8-trace
+pub fn read_binary_trace(data: &[u8]) -> Result<Vec<TraceRecord>, Chip8Error> {
+    if !is_binary_trace(data) {
+        return Err(invalid("not a binary trace"));
+    }
+    let mut reader = ByteReader { data, position: BINARY_MAGIC.len() };
+    let mut records = Vec::new();
+    while reader.position < data.len() {
+        let frame = reader.word()? as u64 | (reader.word()? as u64) << 16;
+        let address = reader.word()?;
+        let opcode = reader.word()?;
+        let instruction = match opcode {
+            0xF000 => Instruction::LoadILong(reader.word()?),
+            _ => Instruction::decode(opcode),
+        };
+        let count = reader.byte()?;
+        let mut changes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let location = match reader.byte()? {
+                index @ 0x0..=0xF => Location::Register(index),
+                TAG_ADDRESS_I => Location::AddressI,
+                TAG_DELAY_TIMER => Location::DelayTimer,
+                TAG_SOUND_TIMER => Location::SoundTimer,
+                tag => return Err(invalid(&format!("unknown change tag {:02X} at byte {}", tag, reader.position - 1))),
+            };
+            changes.push((location, reader.word()?));
+        }
+        records.push(TraceRecord { frame, address, instruction, changes });
+    }
+    Ok(records)
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl ByteReader<'_> {
+    fn byte(&mut self) -> Result<u8, Chip8Error> {
+        let byte = *self.data.get(self.position).ok_or_else(|| invalid("the last record is cut off"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn word(&mut self) -> Result<WORD, Chip8Error> {
+        Ok(self.byte()? as WORD | (self.byte()? as WORD) << 8)
+    }
+}
+
+fn invalid(reason: &str) -> Chip8Error {
+    Chip8Error::InvalidTrace { reason: reason.to_string() }
+}
+
+impl Chip8Hardware {
+    pub fn enable_trace(&mut self, tracer: Tracer) {
+        self.trace = Some(tracer);
+    }
+
+    // stops tracing and hands the tracer back, flushed
+    pub fn disable_trace(&mut self) -> Result<Option<Tracer>, Chip8Error> {
+        let mut tracer = self.trace.take();
+        if let Some(tracer) = &mut tracer {
+            tracer.flush()?;
+        }
+        Ok(tracer)
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        return self.trace.is_some();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(frame: u64, address: WORD, instruction: Instruction, changes: Vec<(Location, WORD)>) -> TraceRecord {
+        TraceRecord { frame, address, instruction, changes }
+    }
+
+    fn binary(records: &[TraceRecord]) -> Vec<u8> {
+        let mut data = BINARY_MAGIC.to_vec();
+        for record in records {
+            data.extend(encode_record(record));
+        }
+        data
+    }
+
+    #[test]
+    fn binary_records_read_back_the_same() {
+        let records = vec![
+            record(0, 0x200, Instruction::decode(0x6A12), vec![(Location::Register(0xA), 0x12)]),
+            record(1, 0x202, Instruction::LoadILong(0xBEEF), vec![(Location::AddressI, 0xBEEF)]),
+            record(0x1234_5678, 0x206, Instruction::decode(0xF015), vec![
+                (Location::Register(0x0), 0xFF),
+                (Location::Register(0xF), 0x01),
+                (Location::AddressI, 0xFFF),
+                (Location::DelayTimer, 0x3C),
+                (Location::SoundTimer, 0x02),
+            ]),
+            record(2, 0xFFFE, Instruction::decode(0x00E0), vec![]),
+        ];
+        let data = binary(&records);
+        assert!(is_binary_trace(&data));
+        assert_eq!(read_binary_trace(&data).unwrap(), records);
+        assert_eq!(read_binary_trace(&BINARY_MAGIC).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn broken_binary_traces_are_errors() {
+        let data = binary(&[
+            record(5, 0x200, Instruction::LoadILong(0x1234), vec![(Location::SoundTimer, 0x10)]),
+        ]);
+        // every way of cutting the record short
+        for length in BINARY_MAGIC.len() + 1..data.len() {
+            match read_binary_trace(&data[..length]) {
+                Err(Chip8Error::InvalidTrace { reason }) => assert_eq!(reason, "the last record is cut off"),
+                other => panic!("{} bytes gave {:?}", length, other),
+            }
+        }
+
+        let tag = data.len() - 3;
+        let mut bad_tag = data.clone();
+        bad_tag[tag] = 0x13;
+        match read_binary_trace(&bad_tag) {
+            Err(Chip8Error::InvalidTrace { reason }) => assert_eq!(reason, format!("unknown change tag 13 at byte {}", tag)),
+            other => panic!("a bad tag gave {:?}", other),
+        }
+        assert!(!is_binary_trace(b"C8T"));
+        assert!(read_binary_trace(b"     0 200 00E0      CLS").is_err());
+    }
+
+    #[test]
+    fn ranges_parse() {
+        assert_eq!(TraceFilter::parse_addresses("200-2FF"), Some((0x200, 0x2FF)));
+        assert_eq!(TraceFilter::parse_addresses("0x200-0X2ff"), Some((0x200, 0x2FF)));
+        assert_eq!(TraceFilter::parse_addresses("-300"), Some((0, 0x300)));
+        assert_eq!(TraceFilter::parse_addresses("E00-"), Some((0xE00, 0xFFFF)));
+        assert_eq!(TraceFilter::parse_addresses("300-200"), None);
+        assert_eq!(TraceFilter::parse_addresses("200"), None);
+        assert_eq!(TraceFilter::parse_addresses("20G-300"), None);
+
+        assert_eq!(TraceFilter::parse_frames("120-300"), Some((120, 300)));
+        assert_eq!(TraceFilter::parse_frames(" 10 - "), Some((10, u64::MAX)));
+        assert_eq!(TraceFilter::parse_frames("-"), Some((0, u64::MAX)));
+        // frames are decimal, a hex looking one is a mistake rather than 10-20
+        assert_eq!(TraceFilter::parse_frames("0x10-20"), None);
+        assert_eq!(TraceFilter::parse_frames("1A-20"), None);
+    }
+
+    #[test]
+    fn filters_need_every_part_to_match() {
+        let draw = Instruction::decode(0xD125);
+        let store = Instruction::decode(0xF355);
+        assert!(TraceFilter::default().matches(u64::MAX, 0xFFFF, &draw));
+
+        let filter = TraceFilter {
+            addresses: TraceFilter::parse_addresses("200-2FF"),
+            opcodes: TraceFilter::parse_opcodes("D???, Fx55").unwrap(),
+            frames: TraceFilter::parse_frames("10-20"),
+        };
+        assert!(filter.matches(10, 0x200, &draw));
+        assert!(filter.matches(20, 0x2FF, &store));
+        assert!(!filter.matches(9, 0x200, &draw));
+        assert!(!filter.matches(21, 0x200, &draw));
+        assert!(!filter.matches(15, 0x300, &draw));
+        assert!(!filter.matches(15, 0x250, &Instruction::decode(0xF365)));
+        assert!(TraceFilter::parse_opcodes("D???,F5").is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use ggez::graphics;
+use ggez::nalgebra;
+use ggez::{Context, GameResult};
+
+use chip_8_emulator::chip_8_debugger::{self, Debugger, StopReason, DEFAULT_STEP_LIMIT};
+use chip_8_emulator::Chip8Hardware;
+
+// the panel sits to the right of the game, the window grows to fit it while it's showing
+const PANEL_WIDTH: f32 = 520.0;
+const PANEL_HEIGHT: f32 = 500.0;
+const MARGIN: f32 = 8.0;
+const TEXT_SIZE: f32 = 13.0;
+const MEMORY_BYTES: usize = 0x40;
+const PANEL_COLOR: graphics::Color = graphics::Color { r: 0.08, g: 0.08, b: 0.1, a: 1.0 };
+const TEXT_COLOR: graphics::Color = graphics::Color { r: 0.85, g: 0.85, b: 0.85, a: 1.0 };
+const HEADING_COLOR: graphics::Color = graphics::Color { r: 0.5, g: 0.7, b: 1.0, a: 1.0 };
+const HIGHLIGHT_COLOR: graphics::Color = graphics::Color { r: 1.0, g: 0.85, b: 0.3, a: 1.0 };
+const DIM_COLOR: graphics::Color = graphics::Color { r: 0.35, g: 0.35, b: 0.35, a: 1.0 };
+// the hex keypad the way the cosmac vip laid it out
+const KEYPAD_ROWS: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Registers, stack, timers, keypad, code around the program counter and memory at I, drawn
+// next to the game. Stepping goes through a Debugger so timers tick the same as in chip8-debug.
+pub struct DebugOverlay {
+    visible: bool,
+    debugger: Debugger,
+}
+
+impl DebugOverlay {
+    pub fn new() -> DebugOverlay {
+        DebugOverlay {
+            visible: false,
+            debugger: Debugger::new(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    // shows or hides the panel, resizing the window around a game that's game_width x game_height
+    pub fn toggle(&mut self, ctx: &mut Context, game_width: f32, game_height: f32) -> GameResult {
+        self.visible = !self.visible;
+        let (width, height) = if self.visible {
+            (game_width + PANEL_WIDTH, game_height.max(PANEL_HEIGHT))
+        } else {
+            (game_width, game_height)
+        };
+        graphics::set_drawable_size(ctx, width, height)?;
+        graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))
+    }
+
+    pub fn step(&mut self, chip_8: &mut Chip8Hardware) -> StopReason {
+        self.debugger.step(chip_8)
+    }
+
+    // step, but a call runs until it returns
+    pub fn next(&mut self, chip_8: &mut Chip8Hardware) -> StopReason {
+        self.debugger.next(chip_8, DEFAULT_STEP_LIMIT)
+    }
+
+    pub fn draw(&self, ctx: &mut Context, chip_8: &Chip8Hardware, left: f32) -> GameResult {
+        let (_, height) = graphics::drawable_size(ctx);
+        let bounds = graphics::Rect::new(left, 0.0, PANEL_WIDTH, height);
+        let background = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds, PANEL_COLOR)?;
+        graphics::draw(ctx, &background, graphics::DrawParam::new())?;
+
+        let mut text = graphics::Text::default();
+        heading(&mut text, "registers");
+        add_text(&mut text, &chip_8_debugger::format_registers(chip_8), TEXT_COLOR);
+
+        heading(&mut text, "stack");
+        let stack: Vec<String> = chip_8.get_stack().iter().rev().map(|address| format!("{:03X}", address)).collect();
+        add_text(&mut text, &if stack.is_empty() {"empty\n".to_string()} else {stack.join(" ") + "\n"}, TEXT_COLOR);
+
+        heading(&mut text, "keypad");
+        for row in KEYPAD_ROWS.iter() {
+            for &key in row {
+                let color = if chip_8.keyboard[key] {HIGHLIGHT_COLOR} else {DIM_COLOR};
+                add_text(&mut text, &format!("{:X}  ", key), color);
+            }
+            add_text(&mut text, "\n", TEXT_COLOR);
+        }
+
+        heading(&mut text, "code");
+        let listing = self.debugger.disassemble_around(chip_8, chip_8.get_program_counter(), 4, 9);
+        for listing_line in listing.lines() {
+            let color = if listing_line.starts_with("=>") {HIGHLIGHT_COLOR} else {TEXT_COLOR};
+            add_text(&mut text, &format!("{}\n", listing_line), color);
+        }
+
+        // rows start on a 16 byte boundary so the addresses stay tidy
+        let start = chip_8.get_address_i() as usize & !0xF;
+        heading(&mut text, "memory at I");
+        add_text(&mut text, &chip_8_debugger::format_memory(chip_8, start, MEMORY_BYTES), TEXT_COLOR);
+
+        graphics::draw(ctx, &text, (nalgebra::Point2::new(left + MARGIN, MARGIN),))
+    }
+}
+
+fn heading(text: &mut graphics::Text, title: &str) {
+    add_text(text, &format!("{}\n", title), HEADING_COLOR);
+}
+
+fn add_text(text: &mut graphics::Text, content: &str, color: graphics::Color) {
+    let fragment = graphics::TextFragment::new(content)
+        .color(color)
+        .scale(graphics::Scale::uniform(TEXT_SIZE));
+    text.add(fragment);
+}
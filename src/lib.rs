@@ -0,0 +1,32 @@
+// the opcode handlers use explicit returns and `x = x >> n` on purpose, it reads closer to the spec
+#![allow(clippy::needless_return, clippy::assign_op_pattern)]
+
+// Core of the emulator, no window stuff in here so other frontends and tools can use it
+
+pub mod chip_8_assembler;
+pub mod chip_8_cli;
+pub mod chip_8_conformance;
+pub mod chip_8_dap;
+pub mod chip_8_debugger;
+pub mod chip_8_disassembler;
+pub mod chip_8_emulator;
+pub mod chip_8_error;
+pub mod chip_8_gdb;
+pub mod chip_8_golden;
+pub mod chip_8_headless;
+pub mod chip_8_instruction;
+mod chip_8_json;
+pub mod chip_8_octo;
+pub mod chip_8_observer;
+pub mod chip_8_quirks;
+pub mod chip_8_random;
+pub mod chip_8_rewind;
+pub mod chip_8_save_state;
+pub mod chip_8_screenshot;
+pub mod chip_8_source_map;
+pub mod chip_8_trace;
+
+pub use chip_8_emulator::{Chip8Hardware, LORES_HEIGHT, LORES_WIDTH, TIMER_HZ};
+pub use chip_8_error::Chip8Error;
+pub use chip_8_instruction::Instruction;
+pub use chip_8_quirks::{IndexIncrement, Quirks};
@@ -1,42 +1,238 @@
-mod chip_8_emulator;
-use ggez;
+mod beeper;
+mod debug_overlay;
+mod options;
+
 use ggez::event;
 use ggez::graphics;
+use ggez::timer;
 use ggez::{Context, GameResult};
 use ggez::nalgebra;
 use ggez::event::{KeyCode, KeyMods};
+use std::time::{Duration, Instant};
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+
+use chip_8_emulator::chip_8_debugger::StopReason;
+use chip_8_emulator::chip_8_headless::{self, HeadlessRunner};
+use chip_8_emulator::chip_8_random;
+use chip_8_emulator::chip_8_screenshot::{self, Rgb, DEFAULT_PALETTE};
+use chip_8_emulator::chip_8_trace::Tracer;
+use chip_8_emulator::{chip_8_cli, Chip8Error, Chip8Hardware};
+use options::Options;
 
+// speed multipliers for the slow motion and fast forward hotkeys
+const SLOW_MOTION_SPEED: f64 = 0.25;
+const FAST_FORWARD_SPEED: f64 = 4.0;
+// never try to catch up more than this many frames in one update, or a hiccup snowballs
+const MAX_FRAMES_PER_UPDATE: u32 = 16;
+// how long turbo mode is allowed to spin each update before letting ggez draw
+const TURBO_BUDGET: Duration = Duration::from_millis(14);
+// F1-F9 load a save state slot, shift + the same key saves it
+const SAVE_STATE_KEYS: [KeyCode; 9] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5,
+    KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9,
+];
 struct MainState {
-    chip_8: chip_8_emulator::Chip8Hardware,
+    chip_8: Chip8Hardware,
     start_x: f32,
     start_y: f32,
-    opcode_value: u16,
+    // set once the core hits an error, we stop running and keep showing the last frame
+    crashed: Option<Chip8Error>,
+    // 1.0 is real time, slow motion / fast forward change it
+    speed: f64,
+    // tab held down, run as many frames as we can
+    turbo: bool,
+    // backspace held down, play the history backwards
+    rewinding: bool,
+    // fractional frames owed to the emulator from previous updates
+    pending_frames: f64,
+    beeper: beeper::Beeper,
+    paused: bool,
+    // the display is always this wide on screen, pixels get smaller in high resolution mode
+    display_width: f32,
+    // screenshots use the palette, drawing uses the same colors already turned into ggez ones
+    palette: [Rgb; 4],
+    colors: [graphics::Color; 4],
+    keymap: [KeyCode; 16],
+    // save state files go next to the rom
+    rom_path: String,
+    // F10 shows it, F11 steps an instruction and shift + F11 steps over a call
+    overlay: debug_overlay::DebugOverlay,
+    // the window changed size, draw even if the game didn't
+    force_redraw: bool,
 }
 
 impl MainState {
-    fn new() -> GameResult<MainState> {
-        let mut c_8 = chip_8_emulator::Chip8Hardware::new();
-        c_8.cpu_reset();
-        c_8.load_game("games/Chip8Picture.ch8".to_string());
+    fn new(options: &Options) -> GameResult<MainState> {
+        let mut c_8 = build_chip_8(options)
+            .map_err(|e| ggez::GameError::ResourceLoadError(e.to_string()))?;
+        if options.rewind_seconds > 0{
+            c_8.enable_rewind(options.rewind_seconds * chip_8_emulator::TIMER_HZ as usize, options.rewind_megabytes * 1024 * 1024);
+        }
+        let palette = options.palette.unwrap_or(DEFAULT_PALETTE);
         let s = MainState {
             chip_8: c_8,
             start_x: 0.0,
-            start_y: 50.0,
-            opcode_value: 0,
+            start_y: 0.0,
+            crashed: None,
+            speed: 1.0,
+            turbo: false,
+            rewinding: false,
+            pending_frames: 0.0,
+            beeper: beeper::Beeper::new(),
+            paused: options.start_paused,
+            display_width: chip_8_emulator::LORES_WIDTH as f32 * options.scale,
+            palette,
+            colors: palette.map(|[r, g, b]| graphics::Color::from_rgb(r, g, b)),
+            keymap: options.keymap,
+            rom_path: options.rom_path.clone(),
+            overlay: debug_overlay::DebugOverlay::new(),
+            force_redraw: false,
         };
         Ok(s)
     }
+
+    fn run_frame(&mut self) -> bool{
+        // false once the core has crashed
+        if let Err(e) = self.chip_8.run_frame(){
+            println!("Emulation stopped: {}", e);
+            self.crashed = Some(e);
+            return false;
+        }
+        true
+    }
+
+    fn toggle_speed(&mut self, speed: f64){
+        self.speed = if self.speed == speed {1.0} else {speed};
+        self.pending_frames = 0.0;
+    }
+
+    fn save_state_path(&self, slot: usize) -> String{
+        format!("{}.state{}", self.rom_path, slot)
+    }
+
+    fn save_state(&mut self, slot: usize){
+        let path = self.save_state_path(slot);
+        match fs::write(&path, self.chip_8.save_state()){
+            Ok(_) => println!("Saved state {} to {}", slot, path),
+            Err(e) => println!("Couldn't save state {}: {}", slot, e),
+        }
+    }
+
+    fn load_state(&mut self, slot: usize){
+        let path = self.save_state_path(slot);
+        let result = fs::read(&path)
+            .map_err(Chip8Error::from)
+            .and_then(|data| self.chip_8.load_state(&data));
+        match result{
+            Ok(_) => {
+                println!("Loaded state {} from {}", slot, path);
+                // a state from before a crash is a way out of it
+                self.crashed = None;
+            }
+            Err(e) => println!("Couldn't load state {}: {}", slot, e),
+        }
+    }
+
+    fn save_screenshot(&mut self){
+        // next free <rom>.N.png, at the size the game is showing in the window
+        let path = (1..).map(|n| format!("{}.{}.png", self.rom_path, n))
+            .find(|path| !Path::new(path).exists())
+            .unwrap_or_default();
+        let scale = (self.display_width / self.chip_8.get_screen_width() as f32).round() as usize;
+        match chip_8_screenshot::save_screenshot(&self.chip_8, &path, scale, &self.palette){
+            Ok(_) => println!("Saved screenshot to {}", path),
+            Err(e) => println!("Couldn't save a screenshot: {}", e),
+        }
+    }
+
+    fn toggle_overlay(&mut self, ctx: &mut Context){
+        let game_height = self.display_width * chip_8_emulator::LORES_HEIGHT as f32 / chip_8_emulator::LORES_WIDTH as f32;
+        if let Err(e) = self.overlay.toggle(ctx, self.display_width, game_height){
+            println!("Couldn't resize the window: {}", e);
+        }
+        self.force_redraw = true;
+    }
+
+    fn debug_step(&mut self, over_calls: bool){
+        if self.crashed.is_some(){
+            return;
+        }
+        // stepping pauses, otherwise the next update would carry straight on
+        self.paused = true;
+        let reason = if over_calls {self.overlay.next(&mut self.chip_8)} else {self.overlay.step(&mut self.chip_8)};
+        match reason{
+            StopReason::Stepped | StopReason::Halted => (),
+            StopReason::Error(e) => {
+                println!("Emulation stopped: {}", e);
+                self.crashed = Some(e);
+            }
+            other => println!("{}", other),
+        }
+    }
+
+    fn change_clock(&mut self, delta_hz: i32){
+        let hz = (self.chip_8.get_clock_hz() as i32 + delta_hz).max(chip_8_emulator::TIMER_HZ as i32);
+        self.chip_8.set_clock_hz(hz as u32);
+        println!("CPU clock: {} Hz", self.chip_8.get_clock_hz());
+    }
 }
 
 impl event::EventHandler for MainState{
 
-    fn update(&mut self, _ctx: &mut Context) -> GameResult{
+    fn update(&mut self, ctx: &mut Context) -> GameResult{
+
+        if self.rewinding{
+            // same pace as playing forwards, one saved frame per 1/60th of a second
+            let elapsed = timer::duration_to_f64(timer::delta(ctx));
+            self.pending_frames += elapsed * chip_8_emulator::TIMER_HZ as f64 * self.speed;
+            self.pending_frames = self.pending_frames.min(MAX_FRAMES_PER_UPDATE as f64);
+            while self.pending_frames >= 1.0{
+                self.pending_frames -= 1.0;
+                if !self.chip_8.rewind_frame(){
+                    break;
+                }
+                // back before the crash, let it run again
+                self.crashed = None;
+            }
+            return Ok(());
+        }
 
-        self.opcode_value = 0;
-        self.chip_8.fetch_opcode(&mut self.opcode_value);
-        let opc = self.opcode_value;
-        self.chip_8.decode_and_execute_opcode(opc);
-        self.chip_8.decrement_timer_counter();
+        if self.crashed.is_some(){
+            return Ok(());
+        }
+
+        // schip 00FD, the rom is done so close the window
+        if self.chip_8.is_halted(){
+            event::quit(ctx);
+            return Ok(());
+        }
+
+        if self.paused{
+            self.pending_frames = 0.0;
+        } else if self.turbo{
+            let start = Instant::now();
+            while start.elapsed() < TURBO_BUDGET{
+                if !self.run_frame(){
+                    break;
+                }
+            }
+        } else {
+            // each emulated frame is 1/60th of a second of game time, scaled by the speed setting
+            let elapsed = timer::duration_to_f64(timer::delta(ctx));
+            self.pending_frames += elapsed * chip_8_emulator::TIMER_HZ as f64 * self.speed;
+            self.pending_frames = self.pending_frames.min(MAX_FRAMES_PER_UPDATE as f64);
+            while self.pending_frames >= 1.0{
+                self.pending_frames -= 1.0;
+                if !self.run_frame(){
+                    break;
+                }
+            }
+        }
+
+        self.beeper.update(ctx, &self.chip_8);
         Ok(())
     }
 
@@ -44,18 +240,21 @@ impl event::EventHandler for MainState{
 
         let mut screen = graphics::MeshBuilder::new();
 
-        if self.chip_8.get_draw_enabled() == true{
-            for y in 0..32{
-                for x in 0..64{
-                    let pixel_color = if self.chip_8.get_pixel_value_x_y(y, x) == true {graphics::WHITE} else {graphics::BLACK};
-                    let x_coord = x as f32 * 12.5 + self.start_x;
-                    let y_coord = y as f32 * 12.5 + self.start_y;
+        if self.chip_8.get_draw_enabled() || self.overlay.is_visible() || self.force_redraw{
+            let width = self.chip_8.get_screen_width() as u16;
+            let height = self.chip_8.get_screen_height() as u16;
+            let pixel_size = self.display_width / width as f32;
+            for y in 0..height{
+                for x in 0..width{
+                    let pixel_color = self.colors[self.chip_8.get_pixel_planes_x_y(y, x) as usize & 3];
+                    let x_coord = x as f32 * pixel_size + self.start_x;
+                    let y_coord = y as f32 * pixel_size + self.start_y;
                     // let pixel = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), graphics::Rect::new(x_coord, y_coord, self.height, self.width), pixel_color)?;
                     // self.pixels.push(pixel);
                     let top_left_point = nalgebra::Point2::new(x_coord, y_coord);
-                    let top_right_point = nalgebra::Point2::new(x_coord + 12.5, y_coord);
-                    let bottom_left_point = nalgebra::Point2::new(x_coord, y_coord + 12.5);
-                    let bottom_right_point = nalgebra::Point2::new(x_coord + 12.5, y_coord + 12.5);
+                    let top_right_point = nalgebra::Point2::new(x_coord + pixel_size, y_coord);
+                    let bottom_left_point = nalgebra::Point2::new(x_coord, y_coord + pixel_size);
+                    let bottom_right_point = nalgebra::Point2::new(x_coord + pixel_size, y_coord + pixel_size);
                     screen.polygon(graphics::DrawMode::fill(), &[top_left_point, bottom_left_point, bottom_right_point, top_right_point], pixel_color).unwrap();
                 }
             }
@@ -67,6 +266,10 @@ impl event::EventHandler for MainState{
                 Err(e) => println!("Error {}", e),
             };
 
+            if self.overlay.is_visible(){
+                self.overlay.draw(ctx, &self.chip_8, self.start_x + self.display_width)?;
+            }
+
             // for pixel in self.pixels.iter(){
             //     graphics::draw(ctx, pixel, graphics::DrawParam::default())?;
             // }
@@ -75,6 +278,7 @@ impl event::EventHandler for MainState{
             // self.pixels.clear();
             
             self.chip_8.disable_draw_enabled();
+            self.force_redraw = false;
 
         }
 
@@ -83,54 +287,109 @@ impl event::EventHandler for MainState{
     }
 
     fn key_down_event(&mut self, _ctx: &mut Context, _keycode: KeyCode, _keymod: KeyMods,  _repeat: bool){
+        if let Some(key) = self.keymap.iter().position(|k| *k == _keycode){
+            self.chip_8.keyboard[key] = true;
+            return;
+        }
+
+        if let Some(slot) = SAVE_STATE_KEYS.iter().position(|k| *k == _keycode){
+            if !_repeat{
+                if _keymod.contains(KeyMods::SHIFT) {self.save_state(slot + 1)} else {self.load_state(slot + 1)};
+            }
+            return;
+        }
+
         match _keycode{
-            KeyCode::Key1   => self.chip_8.keyboard[0]  = true,
-            KeyCode::Key2   => self.chip_8.keyboard[1]  = true,
-            KeyCode::Key3   => self.chip_8.keyboard[2]  = true,
-            KeyCode::Key4   => self.chip_8.keyboard[3]  = true,
-            KeyCode::Q      => self.chip_8.keyboard[4]  = true,
-            KeyCode::W      => self.chip_8.keyboard[5]  = true,
-            KeyCode::E      => self.chip_8.keyboard[6]  = true,
-            KeyCode::R      => self.chip_8.keyboard[7]  = true,
-            KeyCode::A      => self.chip_8.keyboard[8]  = true,
-            KeyCode::S      => self.chip_8.keyboard[9]  = true,
-            KeyCode::D      => self.chip_8.keyboard[10] = true,
-            KeyCode::F      => self.chip_8.keyboard[11] = true,
-            KeyCode::Z      => self.chip_8.keyboard[12] = true,
-            KeyCode::X      => self.chip_8.keyboard[13] = true,
-            KeyCode::C      => self.chip_8.keyboard[14] = true,
-            KeyCode::V      => self.chip_8.keyboard[15] = true,
+            KeyCode::Tab    => self.turbo = true,
+            KeyCode::Back   => self.rewinding = true,
+            KeyCode::P if !_repeat      => self.paused = !self.paused,
+            KeyCode::F10 if !_repeat    => self.toggle_overlay(_ctx),
+            KeyCode::F11 => self.debug_step(_keymod.contains(KeyMods::SHIFT)),
+            KeyCode::F12 if !_repeat    => self.save_screenshot(),
+            KeyCode::Minus if !_repeat  => self.toggle_speed(SLOW_MOTION_SPEED),
+            KeyCode::Equals if !_repeat => self.toggle_speed(FAST_FORWARD_SPEED),
+            KeyCode::LBracket => self.change_clock(-60),
+            KeyCode::RBracket => self.change_clock(60),
             _               => (),
         };
     }
 
     fn key_up_event(&mut self, _ctx: &mut Context, _keycode: KeyCode, _keymod: KeyMods){
+        if let Some(key) = self.keymap.iter().position(|k| *k == _keycode){
+            self.chip_8.keyboard[key] = false;
+            return;
+        }
+
         match _keycode{
-            KeyCode::Key1   => self.chip_8.keyboard[0]  = false,
-            KeyCode::Key2   => self.chip_8.keyboard[1]  = false,
-            KeyCode::Key3   => self.chip_8.keyboard[2]  = false,
-            KeyCode::Key4   => self.chip_8.keyboard[3]  = false,
-            KeyCode::Q      => self.chip_8.keyboard[4]  = false,
-            KeyCode::W      => self.chip_8.keyboard[5]  = false,
-            KeyCode::E      => self.chip_8.keyboard[6]  = false,
-            KeyCode::R      => self.chip_8.keyboard[7]  = false,
-            KeyCode::A      => self.chip_8.keyboard[8]  = false,
-            KeyCode::S      => self.chip_8.keyboard[9]  = false,
-            KeyCode::D      => self.chip_8.keyboard[10] = false,
-            KeyCode::F      => self.chip_8.keyboard[11] = false,
-            KeyCode::Z      => self.chip_8.keyboard[12] = false,
-            KeyCode::X      => self.chip_8.keyboard[13] = false,
-            KeyCode::C      => self.chip_8.keyboard[14] = false,
-            KeyCode::V      => self.chip_8.keyboard[15] = false,
+            KeyCode::Tab    => self.turbo = false,
+            KeyCode::Back   => {
+                self.rewinding = false;
+                self.pending_frames = 0.0;
+            }
             _               => (),
         };
     }
 
 }
 
+fn build_chip_8(options: &Options) -> Result<Chip8Hardware, Chip8Error> {
+    let mut c_8 = Chip8Hardware::with_quirks(options.quirks);
+    c_8.enable_decode_cache();
+    if let Some(hz) = options.cpu_hz{
+        c_8.set_clock_hz(hz);
+    }
+    let seed = options.seed.unwrap_or_else(rand::random);
+    if let Some(random) = chip_8_random::source_from_name(&options.random_source, seed){
+        c_8.set_random_source(random);
+    }
+    c_8.load_program(&options.rom_path)?;
+    if let Some(path) = &options.trace_path{
+        let writer = BufWriter::new(fs::File::create(path)?);
+        c_8.enable_trace(Tracer::new(Box::new(writer), options.trace_format, options.trace_filter.clone()));
+    }
+    Ok(c_8)
+}
+
+fn run_headless(options: &Options) -> Result<(), Chip8Error> {
+    // no window, the same run chip8-headless does and then what ended up on screen, even after a crash
+    let mut c_8 = build_chip_8(options)?;
+    let outcome = HeadlessRunner::new(u64::from(options.frames)).run(&mut c_8);
+    c_8.disable_trace()?;
+
+    print!("{}", chip_8_headless::format_screen(&c_8));
+    if let Some(path) = &options.screenshot_path{
+        chip_8_screenshot::save_screenshot(&c_8, path, options.screenshot_scale, &options.palette.unwrap_or(DEFAULT_PALETTE))?;
+    }
+    outcome?;
+    Ok(())
+}
+
 pub fn main() -> GameResult {
-    let cb = ggez::ContextBuilder::new("super_simple", "ggez").window_setup(ggez::conf::WindowSetup::default());
+    let options = match Options::parse(std::env::args().skip(1)){
+        Ok(options) => options,
+        Err(message) => {
+            chip_8_cli::fail(&message, options::USAGE);
+        }
+    };
+    if options.show_help{
+        println!("{}", options::USAGE);
+        return Ok(());
+    }
+
+    if options.headless{
+        if let Err(e) = run_headless(&options){
+            eprintln!("Emulation stopped: {}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let width = chip_8_emulator::LORES_WIDTH as f32 * options.scale;
+    let height = chip_8_emulator::LORES_HEIGHT as f32 * options.scale;
+    let cb = ggez::ContextBuilder::new("super_simple", "ggez")
+        .window_setup(ggez::conf::WindowSetup::default().title("CHIP-8"))
+        .window_mode(ggez::conf::WindowMode::default().dimensions(width, height));
     let (ctx, event_loop) = &mut cb.build()?;
-    let state = &mut MainState::new()?;
+    let state = &mut MainState::new(&options)?;
     event::run(ctx, event_loop, state)
 }
@@ -0,0 +1,217 @@
+use std::fs;
+
+use ggez::event::KeyCode;
+
+use chip_8_emulator::chip_8_random;
+use chip_8_emulator::chip_8_screenshot::{self, ImageFormat, Rgb};
+use chip_8_emulator::chip_8_trace::{TraceFilter, TraceFormat};
+use chip_8_emulator::Quirks;
+
+pub const USAGE: &str = "\
+usage: chip_8_emulator [options] <rom>
+
+<rom> can also be octo source (.8o), it gets compiled when it loads
+
+options:
+  --platform <name>    quirks preset: vip, chip48, schip, xochip (default vip)
+  --cpu-hz <hz>        cpu clock, rounded down to a multiple of 60 (default 600)
+  --rng <name>         where CXNN's random numbers come from: xorshift, or vip for the COSMAC
+                       VIP interpreter's streakier routine (default xorshift)
+  --seed <n>           seed for CXNN's random numbers, so runs repeat exactly
+  --scale <pixels>     size of one low resolution pixel on screen (default 12.5)
+  --palette <colors>   four comma separated RRGGBB colors, background first
+  --keymap <file>      file of `<chip-8 key> = <keyboard key>` lines
+  --paused             start paused, P toggles
+  --rewind-seconds <n> how much history backspace can rewind, 0 turns it off (default 60)
+  --rewind-mb <n>      memory the rewind history may use (default 32)
+  --headless           no window, run --frames frames and print the screen
+  --frames <n>         frames to run in headless mode (default 600)
+  --screenshot <file>  in headless mode, save the screen to file at the end, .png or .pbm
+  --screenshot-scale <n>
+                       size of a chip-8 pixel in --screenshot files (default 1)
+  --trace <file>       write every instruction run, with the registers it changed, to file
+  --trace-format <f>   text or binary (default text), chip8-trace reads either
+  --trace-addrs <r>    only trace instructions in a hex address range, like 200-2FF
+  --trace-ops <list>   only trace opcodes matching comma separated patterns, like D???,Fx55
+  --trace-frames <r>   only trace frames in a range, like 120-300, either end can be left off
+  -h, --help           show this";
+
+// the usual layout, the 4x4 hex keypad squashed onto 1234 / QWER / ASDF / ZXCV
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+pub const DEFAULT_KEYMAP: [KeyCode; 16] = [
+    KeyCode::X,                                         // 0
+    KeyCode::Key1, KeyCode::Key2, KeyCode::Key3,        // 1 2 3
+    KeyCode::Q, KeyCode::W, KeyCode::E,                 // 4 5 6
+    KeyCode::A, KeyCode::S, KeyCode::D,                 // 7 8 9
+    KeyCode::Z, KeyCode::C,                             // A B
+    KeyCode::Key4, KeyCode::R, KeyCode::F, KeyCode::V,  // C D E F
+];
+
+// keys a keymap file can name, matched against their KeyCode name ignoring case
+const NAMED_KEYS: [KeyCode; 52] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right, KeyCode::Space, KeyCode::Return,
+];
+
+pub struct Options {
+    pub rom_path: String,
+    pub quirks: Quirks,
+    pub cpu_hz: Option<u32>,
+    pub random_source: String,
+    pub seed: Option<u64>,
+    pub scale: f32,
+    pub palette: Option<[Rgb; 4]>,
+    pub keymap: [KeyCode; 16],
+    pub start_paused: bool,
+    pub rewind_seconds: usize,
+    pub rewind_megabytes: usize,
+    pub headless: bool,
+    pub frames: u32,
+    pub screenshot_path: Option<String>,
+    pub screenshot_scale: usize,
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub show_help: bool,
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            rom_path: String::new(),
+            quirks: Quirks::default(),
+            cpu_hz: None,
+            random_source: "xorshift".to_string(),
+            seed: None,
+            scale: 12.5,
+            palette: None,
+            keymap: DEFAULT_KEYMAP,
+            start_paused: false,
+            rewind_seconds: 60,
+            rewind_megabytes: 32,
+            headless: false,
+            frames: 600,
+            screenshot_path: None,
+            screenshot_scale: 1,
+            trace_path: None,
+            trace_format: TraceFormat::Text,
+            trace_filter: TraceFilter::default(),
+            show_help: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => options.show_help = true,
+                "--paused" => options.start_paused = true,
+                "--headless" => options.headless = true,
+                "--platform" => {
+                    let name = value(&mut args, &arg)?;
+                    options.quirks = Quirks::from_name(&name).ok_or_else(|| format!(
+                        "unknown platform `{}`, expected one of {}", name, Quirks::PRESET_NAMES.join(", ")))?;
+                }
+                "--cpu-hz" => options.cpu_hz = Some(number(&value(&mut args, &arg)?, &arg)?),
+                "--rng" => {
+                    let name = value(&mut args, &arg)?.to_lowercase();
+                    if !chip_8_random::SOURCE_NAMES.contains(&name.as_str()) {
+                        return Err(format!("unknown rng `{}`, expected one of {}", name, chip_8_random::SOURCE_NAMES.join(", ")));
+                    }
+                    options.random_source = name;
+                }
+                "--seed" => options.seed = Some(number(&value(&mut args, &arg)?, &arg)?),
+                "--rewind-seconds" => options.rewind_seconds = number(&value(&mut args, &arg)?, &arg)?,
+                "--rewind-mb" => options.rewind_megabytes = number(&value(&mut args, &arg)?, &arg)?,
+                "--frames" => options.frames = number(&value(&mut args, &arg)?, &arg)?,
+                "--screenshot" => {
+                    let path = value(&mut args, &arg)?;
+                    if ImageFormat::from_path(&path).is_none() {
+                        return Err(format!("--screenshot writes .png or .pbm files, not `{}`", path));
+                    }
+                    options.screenshot_path = Some(path);
+                }
+                "--screenshot-scale" => options.screenshot_scale = number(&value(&mut args, &arg)?, &arg)?,
+                "--trace" => options.trace_path = Some(value(&mut args, &arg)?),
+                "--trace-format" => {
+                    let name = value(&mut args, &arg)?;
+                    options.trace_format = TraceFormat::from_name(&name)
+                        .ok_or_else(|| format!("unknown trace format `{}`, expected text or binary", name))?;
+                }
+                "--trace-addrs" => {
+                    let text = value(&mut args, &arg)?;
+                    options.trace_filter.addresses = Some(TraceFilter::parse_addresses(&text)
+                        .ok_or_else(|| format!("`{}` isn't a hex address range like 200-2FF", text))?);
+                }
+                "--trace-ops" => {
+                    let text = value(&mut args, &arg)?;
+                    options.trace_filter.opcodes = TraceFilter::parse_opcodes(&text)
+                        .ok_or_else(|| format!("`{}` isn't a list of opcode patterns like D???,Fx55", text))?;
+                }
+                "--trace-frames" => {
+                    let text = value(&mut args, &arg)?;
+                    options.trace_filter.frames = Some(TraceFilter::parse_frames(&text)
+                        .ok_or_else(|| format!("`{}` isn't a frame range like 120-300", text))?);
+                }
+                "--scale" => {
+                    options.scale = number(&value(&mut args, &arg)?, &arg)?;
+                    if options.scale <= 0.0 {
+                        return Err("--scale has to be bigger than 0".to_string());
+                    }
+                }
+                "--palette" => options.palette = Some(chip_8_screenshot::parse_palette(&value(&mut args, &arg)?)?),
+                "--keymap" => {
+                    let path = value(&mut args, &arg)?;
+                    let text = fs::read_to_string(&path).map_err(|e| format!("can't read keymap {}: {}", path, e))?;
+                    options.keymap = parse_keymap(&text)?;
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ if options.rom_path.is_empty() => options.rom_path = arg,
+                _ => return Err(format!("only one rom at a time, got `{}` too", arg)),
+            }
+        }
+
+        if options.rom_path.is_empty() && !options.show_help {
+            return Err("no rom given".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", option))
+}
+
+fn number<T: std::str::FromStr>(text: &str, option: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("{} expects a number, got `{}`", option, text))
+}
+
+fn parse_keymap(text: &str) -> Result<[KeyCode; 16], String> {
+    // starts from the default so a file only has to list the keys it changes
+    let mut keymap = DEFAULT_KEYMAP;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=').map(str::trim);
+        let (chip_8_key, key_name) = match (parts.next(), parts.next()) {
+            (Some(k), Some(n)) => (k, n),
+            _ => return Err(format!("keymap line {}: expected `<chip-8 key> = <keyboard key>`", number + 1)),
+        };
+        let index = u8::from_str_radix(chip_8_key, 16).ok().filter(|k| *k < 16)
+            .ok_or_else(|| format!("keymap line {}: `{}` isn't a chip-8 key 0-F", number + 1, chip_8_key))?;
+        let key = NAMED_KEYS.iter().find(|k| format!("{:?}", k).eq_ignore_ascii_case(key_name))
+            .ok_or_else(|| format!("keymap line {}: unknown key `{}`", number + 1, key_name))?;
+        keymap[index as usize] = *key;
+    }
+    Ok(keymap)
+}
@@ -0,0 +1,113 @@
+// Golden framebuffer tests. Every case runs an octo rom from tests/golden for a fixed number of
+// frames, with a fixed rng seed and optionally a key script, then checks the screen against a
+// checked in golden file, text or PBM. A mismatch prints both screens overlaid. Each rom runs a
+// second time with the decode cache on, which must not change what gets drawn.
+//
+// After a change that's meant to alter what gets drawn, `UPDATE_GOLDEN=1 cargo test --test golden`
+// rewrites the goldens from what the roms draw now, check the diff before committing them.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use chip_8_emulator::chip_8_golden::Frame;
+use chip_8_emulator::chip_8_headless::{HeadlessRunner, KeyScript};
+use chip_8_emulator::{chip_8_octo, chip_8_random, Chip8Hardware, Quirks};
+
+struct Case {
+    rom: &'static str,
+    platform: &'static str,
+    frames: u64,
+    seed: u64,
+    // a chip_8_random source name, the default xorshift when None
+    random: Option<&'static str>,
+    keys: Option<&'static str>,
+    // .pbm goldens only say whether a pixel is lit, not which planes
+    golden: &'static str,
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name)
+}
+
+fn run(case: &Case, decode_cache: bool) -> Frame {
+    let rom_path = golden_path(case.rom);
+    let quirks = Quirks::from_name(case.platform).unwrap_or_else(|| panic!("unknown platform {}", case.platform));
+    let mut chip_8 = Chip8Hardware::with_quirks(quirks);
+    chip_8.cpu_reset();
+    if decode_cache {
+        chip_8.enable_decode_cache();
+    }
+    match case.random {
+        Some(name) => {
+            chip_8.set_random_source(chip_8_random::source_from_name(name, case.seed).unwrap_or_else(|| panic!("unknown rng {}", name)));
+        }
+        None => chip_8.seed_rng(case.seed),
+    }
+    let rom = chip_8_octo::compile_file(rom_path.to_str().unwrap()).unwrap_or_else(|e| panic!("{}", e));
+    chip_8.load_rom(&rom).unwrap();
+
+    let mut runner = HeadlessRunner::new(case.frames);
+    if let Some(keys) = case.keys {
+        let text = fs::read_to_string(golden_path(keys)).unwrap();
+        runner.keys = KeyScript::parse(&text).unwrap_or_else(|e| panic!("{}", e));
+    }
+    runner.run(&mut chip_8).unwrap_or_else(|e| panic!("{} crashed: {}", case.rom, e));
+    Frame::capture(&chip_8)
+}
+
+fn check(case: Case) {
+    let golden = golden_path(case.golden);
+    let golden = golden.to_str().unwrap();
+
+    let mut actual = run(&case, false);
+    if let Some(diff) = actual.diff(&run(&case, true)) {
+        panic!("{} draws something else with the decode cache on\n{}", case.rom, diff);
+    }
+    if golden.ends_with(".pbm") {
+        actual = actual.flattened();
+    }
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(golden).unwrap();
+        return;
+    }
+    let expected = Frame::load(golden).unwrap_or_else(|e| panic!("{}, UPDATE_GOLDEN=1 writes it", e));
+    if let Some(diff) = expected.diff(&actual) {
+        panic!("{} after {} frames doesn't match {}\n{}", case.rom, case.frames, case.golden, diff);
+    }
+}
+
+#[test]
+fn sprites() {
+    check(Case { rom: "sprites.8o", platform: "vip", frames: 60, seed: 1, random: None, keys: None, golden: "sprites.txt" });
+}
+
+#[test]
+fn arithmetic() {
+    check(Case { rom: "arithmetic.8o", platform: "vip", frames: 120, seed: 1, random: None, keys: None, golden: "arithmetic.txt" });
+}
+
+#[test]
+fn random() {
+    check(Case { rom: "random.8o", platform: "chip48", frames: 20, seed: 1, random: None, keys: None, golden: "random.txt" });
+}
+
+#[test]
+fn random_vip() {
+    check(Case { rom: "random.8o", platform: "vip", frames: 140, seed: 1, random: Some("vip"), keys: None, golden: "random_vip.txt" });
+}
+
+#[test]
+fn keys() {
+    check(Case { rom: "keys.8o", platform: "vip", frames: 80, seed: 1, random: None, keys: Some("keys.keys"), golden: "keys.txt" });
+}
+
+#[test]
+fn hires() {
+    check(Case { rom: "hires.8o", platform: "schip", frames: 30, seed: 1, random: None, keys: None, golden: "hires.pbm" });
+}
+
+#[test]
+fn planes() {
+    check(Case { rom: "planes.8o", platform: "xochip", frames: 10, seed: 1, random: None, keys: None, golden: "planes.txt" });
+}
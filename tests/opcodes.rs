@@ -0,0 +1,58 @@
+// Regression tests for opcode handlers that were once wrong. Each one runs a few hand assembled
+// instructions and checks the registers they leave behind.
+
+use chip_8_emulator::{Chip8Hardware, Quirks};
+
+fn run(program: &[u16], steps: usize) -> Chip8Hardware {
+    run_with(Quirks::default(), program, steps)
+}
+
+fn run_with(quirks: Quirks, program: &[u16], steps: usize) -> Chip8Hardware {
+    let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+    let mut chip_8 = Chip8Hardware::with_quirks(quirks);
+    chip_8.cpu_reset();
+    chip_8.load_rom(&rom).unwrap();
+    for _ in 0..steps {
+        chip_8.step().unwrap();
+    }
+    chip_8
+}
+
+// 00EE used to come back two bytes past the instruction after the call, skipping it
+#[test]
+fn call_returns_to_the_next_instruction() {
+    let mut chip_8 = run(&[
+        0x2206, // 200: call 206
+        0x6101, // 202: v1 := 1
+        0x1204, // 204: jump 204
+        0x00EE, // 206: return
+    ], 3);
+    assert_eq!(chip_8.get_register_value(1), 1);
+}
+
+// with VF as the destination the flag has to win over the result
+#[test]
+fn add_with_vf_as_destination_keeps_the_carry() {
+    let mut chip_8 = run(&[0x6FFF, 0x6101, 0x8F14], 3);
+    assert_eq!(chip_8.get_register_value(0xF), 1);
+}
+
+#[test]
+fn subtract_with_vf_as_destination_keeps_the_borrow_flag() {
+    let mut chip_8 = run(&[0x6F05, 0x6103, 0x8F15], 3);
+    assert_eq!(chip_8.get_register_value(0xF), 1);
+
+    let mut chip_8 = run(&[0x6F03, 0x6105, 0x8F17], 3);
+    assert_eq!(chip_8.get_register_value(0xF), 1);
+}
+
+// CHIP-48 leaves I on the last register touched, it used to get the VIP's one past that
+#[test]
+fn load_store_moves_i_the_way_each_preset_says() {
+    for (quirks, after) in [(Quirks::cosmac_vip(), 0x303), (Quirks::chip_48(), 0x302), (Quirks::super_chip(), 0x300)] {
+        let chip_8 = run_with(quirks, &[0xA300, 0xF255], 2);
+        assert_eq!(chip_8.get_address_i(), after, "FX55 under {:?}", quirks);
+        let chip_8 = run_with(quirks, &[0xA300, 0xF265], 2);
+        assert_eq!(chip_8.get_address_i(), after, "FX65 under {:?}", quirks);
+    }
+}